@@ -0,0 +1,342 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, HeaderMode};
+use tempfile::TempDir;
+use crate::components::config::RushGetConfig;
+use crate::components::container_engine::{create_engine, ContainerEngine, ContainerEngineKind};
+use crate::components::progress::PullProgress;
+use crate::components::registry::Platform;
+use crate::components::RushGetTask;
+use crate::docker::{DockerPullOptions, DockerPullTask};
+use crate::docker::save::{append_json, write_archive, SaveFormat};
+use crate::error::DockermirError;
+use crate::github::download_release_file;
+
+pub(crate) const DEFAULT_BUNDLE_FILE: &str = "rg-bundle.tar";
+const BUNDLE_VERSION: u32 = 1;
+/// The first entry of the bundle, so the content is known before the images are read
+const INDEX_ENTRY: &str = "index.json";
+/// One `docker save` archive with every image of the bundle
+const IMAGES_ENTRY: &str = "images.tar";
+const FILES_DIR: &str = "files";
+
+/// The images and release files to bundle, read from the yaml file given to `rg bundle create`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub(crate) struct BundleManifest {
+    #[serde(default)]
+    pub(crate) images: Vec<String>,
+    #[serde(default)]
+    pub(crate) files: Vec<BundleManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum BundleManifestFile {
+    /// The url of the release file, placed under its file name
+    Url(String),
+    /// The url of the release file and the relative path to place it at
+    Placed {
+        url: String,
+        path: String,
+    },
+}
+
+impl BundleManifest {
+    pub(crate) fn read(path: &Path) -> Result<BundleManifest, DockermirError> {
+        let content = fs::read_to_string(path).map_err(|e| bundle_error(path, e.to_string()))?;
+        serde_yaml::from_str(&content).map_err(|e| bundle_error(path, e.to_string()))
+    }
+
+    /// The files with the path they are placed at, the paths are relative and unique
+    pub(crate) fn bundle_files(&self) -> Result<Vec<BundleFile>, String> {
+        let files: Vec<BundleFile> = self.files.iter()
+            .map(|file| match file {
+                BundleManifestFile::Url(url) => BundleFile {
+                    url: url.to_string(),
+                    path: url.split('/').next_back().unwrap_or_default().to_string(),
+                },
+                BundleManifestFile::Placed { url, path } => BundleFile {
+                    url: url.to_string(),
+                    path: path.to_string(),
+                },
+            })
+            .collect();
+        check_files(&files)?;
+        Ok(files)
+    }
+}
+
+/// A release file in the bundle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct BundleFile {
+    pub(crate) url: String,
+    /// Where the file is placed on import, relative to the destination
+    pub(crate) path: String,
+}
+
+/// The `index.json` of the bundle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct BundleIndex {
+    pub(crate) version: u32,
+    pub(crate) images: Vec<String>,
+    pub(crate) files: Vec<BundleFile>,
+    /// The sha256 digest of every other entry by its name in the bundle
+    pub(crate) checksums: BTreeMap<String, String>,
+}
+
+impl BundleIndex {
+    /// The entries the index promises, every one of them needs a checksum
+    fn entries(&self) -> Vec<String> {
+        let mut entries = vec![];
+        if !self.images.is_empty() {
+            entries.push(IMAGES_ENTRY.to_string());
+        }
+        entries.extend(self.files.iter().map(|file| file_entry(&file.path)));
+        entries
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BundleCreateOptions {
+    pub(crate) output: PathBuf,
+    /// Pull from the original registry when all mirrors failed
+    pub(crate) fallback_to_upstream: bool,
+    /// The platform to pick from multi-arch images, the platform of the host if not set
+    pub(crate) platform: Option<Platform>,
+}
+
+/// Pull the images and download the release files of the manifest into one archive, for machines without network
+pub(crate) struct BundleCreateTask {
+    manifest: PathBuf,
+    config: RushGetConfig,
+    options: BundleCreateOptions,
+}
+
+impl BundleCreateTask {
+    pub(crate) fn new(config: RushGetConfig, manifest: PathBuf, options: BundleCreateOptions) -> Self {
+        BundleCreateTask {
+            manifest,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for BundleCreateTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let output = &self.options.output;
+        let manifest = BundleManifest::read(&self.manifest)?;
+        let files = manifest.bundle_files().map_err(|e| bundle_error(&self.manifest, e))?;
+        let staging = tempfile::tempdir().map_err(|e| bundle_error(output, e.to_string()))?;
+
+        // the staged file of every entry, in the order of the bundle
+        let mut entries = vec![];
+        if !manifest.images.is_empty() {
+            let layout_dir = staging.path().join("layout");
+            for image in &manifest.images {
+                DockerPullTask::new(self.config.clone(), image.to_owned(), DockerPullOptions {
+                    oci_layout: Some(layout_dir.clone()),
+                    fallback_to_upstream: self.options.fallback_to_upstream,
+                    platform: self.options.platform.clone(),
+                    ..Default::default()
+                }).run().await?;
+            }
+            let images = staging.path().join(IMAGES_ENTRY);
+            write_archive(&layout_dir, &images, SaveFormat::DockerArchive)?;
+            entries.push((IMAGES_ENTRY.to_string(), images));
+        }
+        for (index, file) in files.iter().enumerate() {
+            let staged = staging.path().join(format!("file-{}", index));
+            download_release_file(&self.config, &file.url, &staged).await
+                .map_err(|error| DockermirError::GithubReleaseDownloadError {
+                    url: file.url.to_owned(),
+                    error,
+                })?;
+            info!("Downloaded release file: {} as {}", file.url, file.path);
+            entries.push((file_entry(&file.path), staged));
+        }
+
+        let mut checksums = BTreeMap::new();
+        for (name, staged) in &entries {
+            checksums.insert(name.to_owned(), file_digest(staged).map_err(|e| bundle_error(output, e.to_string()))?);
+        }
+        let index = BundleIndex {
+            version: BUNDLE_VERSION,
+            images: manifest.images,
+            files,
+            checksums,
+        };
+        write_bundle(output, &index, &entries)?;
+        info!("Bundled {} images and {} files into {}", index.images.len(), index.files.len(), output.display());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BundleImportOptions {
+    /// The directory the release files are placed in
+    pub(crate) dest: PathBuf,
+    /// The container engine to load the images with, overrides the engine in config
+    pub(crate) engine: Option<ContainerEngineKind>,
+}
+
+/// Verify the bundle, load its images into the container engine and place its release files
+pub(crate) struct BundleImportTask {
+    bundle: PathBuf,
+    config: RushGetConfig,
+    options: BundleImportOptions,
+}
+
+impl BundleImportTask {
+    pub(crate) fn new(config: RushGetConfig, bundle: PathBuf, options: BundleImportOptions) -> Self {
+        BundleImportTask {
+            bundle,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for BundleImportTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let bundle = UnpackedBundle::unpack(&self.bundle)?;
+        if !bundle.index.images.is_empty() {
+            // the engine is only required when there is something to load
+            let kind = ContainerEngineKind::resolve(self.options.engine, &self.config)?;
+            bundle.load_images(create_engine(kind)?.as_ref()).await?;
+        }
+        bundle.place_files(&self.options.dest)?;
+        info!("Imported {} images and {} files from {}", bundle.index.images.len(), bundle.index.files.len(), self.bundle.display());
+        Ok(())
+    }
+}
+
+/// A bundle unpacked into a temporary directory, every checksum is verified before anything is imported
+pub(crate) struct UnpackedBundle {
+    path: PathBuf,
+    dir: TempDir,
+    pub(crate) index: BundleIndex,
+}
+
+impl UnpackedBundle {
+    pub(crate) fn unpack(path: &Path) -> Result<UnpackedBundle, DockermirError> {
+        let invalid = |error: String| bundle_error(path, error);
+        let dir = tempfile::tempdir().map_err(|e| invalid(e.to_string()))?;
+        let mut archive = Archive::new(File::open(path).map_err(|e| invalid(e.to_string()))?);
+        for entry in archive.entries().map_err(|e| invalid(e.to_string()))? {
+            let mut entry = entry.map_err(|e| invalid(e.to_string()))?;
+            let name = entry.path().map_err(|e| invalid(e.to_string()))?.display().to_string();
+            check_relative_path(&name).map_err(invalid)?;
+            entry.unpack_in(dir.path()).map_err(|e| invalid(format!("failed to unpack {}, {}", name, e)))?;
+        }
+
+        let content = fs::read(dir.path().join(INDEX_ENTRY)).map_err(|e| invalid(format!("failed to read {}, {}", INDEX_ENTRY, e)))?;
+        let index: BundleIndex = serde_json::from_slice(&content).map_err(|e| invalid(format!("invalid {}, {}", INDEX_ENTRY, e)))?;
+        if index.version != BUNDLE_VERSION {
+            return Err(invalid(format!("unsupported version {}, expected {}", index.version, BUNDLE_VERSION)));
+        }
+        check_files(&index.files).map_err(invalid)?;
+        for entry in index.entries() {
+            if !index.checksums.contains_key(&entry) {
+                return Err(invalid(format!("no checksum for {}", entry)));
+            }
+        }
+        for (entry, expected) in &index.checksums {
+            check_relative_path(entry).map_err(invalid)?;
+            let actual = file_digest(&dir.path().join(entry)).map_err(|e| invalid(format!("failed to read {}, {}", entry, e)))?;
+            if actual != *expected {
+                return Err(invalid(format!("checksum mismatch of {}, expected: {}, actual: {}", entry, expected, actual)));
+            }
+        }
+        Ok(UnpackedBundle {
+            path: path.to_path_buf(),
+            dir,
+            index,
+        })
+    }
+
+    pub(crate) async fn load_images(&self, engine: &dyn ContainerEngine) -> Result<(), DockermirError> {
+        let progress = PullProgress::new(&self.path.display().to_string());
+        engine.load(&self.dir.path().join(IMAGES_ENTRY), &progress).await?;
+        for image in &self.index.images {
+            info!("Loaded image: {}", image);
+        }
+        Ok(())
+    }
+
+    /// Copy the release files to their paths in the destination, existing files are replaced
+    pub(crate) fn place_files(&self, dest: &Path) -> Result<(), DockermirError> {
+        for file in &self.index.files {
+            let target = dest.join(&file.path);
+            let place_error = |e: io::Error| bundle_error(&target, e.to_string());
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(place_error)?;
+            }
+            fs::copy(self.dir.path().join(file_entry(&file.path)), &target).map_err(place_error)?;
+            info!("Placed release file: {} at {}", file.url, target.display());
+        }
+        Ok(())
+    }
+}
+
+/// Write the index and the staged entries into the bundle, through a temporary file next to it
+fn write_bundle(output: &Path, index: &BundleIndex, entries: &[(String, PathBuf)]) -> Result<(), DockermirError> {
+    let write_error = |error: String| bundle_error(output, error);
+    let parent = output.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let partial = tempfile::NamedTempFile::new_in(parent).map_err(|e| write_error(e.to_string()))?;
+    let mut builder = Builder::new(File::create(partial.path()).map_err(|e| write_error(e.to_string()))?);
+    builder.mode(HeaderMode::Deterministic);
+    append_json(&mut builder, INDEX_ENTRY, index).map_err(write_error)?;
+    for (name, staged) in entries {
+        builder.append_path_with_name(staged, name).map_err(|e| write_error(e.to_string()))?;
+    }
+    builder.into_inner().and_then(|file| file.sync_all()).map_err(|e| write_error(e.to_string()))?;
+    partial.persist(output).map_err(|e| write_error(e.to_string()))?;
+    Ok(())
+}
+
+/// The paths come from the manifest or from the bundle, neither may point outside of the destination
+fn check_files(files: &[BundleFile]) -> Result<(), String> {
+    let mut paths = HashSet::new();
+    for file in files {
+        check_relative_path(&file.path).map_err(|e| format!("{}, url: {}", e, file.url))?;
+        if !paths.insert(file.path.as_str()) {
+            return Err(format!("more than one file is placed at {}", file.path));
+        }
+    }
+    Ok(())
+}
+
+fn check_relative_path(path: &str) -> Result<(), String> {
+    let is_relative = !path.is_empty() && Path::new(path).components().all(|component| matches!(component, Component::Normal(_)));
+    match is_relative {
+        true => Ok(()),
+        false => Err(format!("invalid path: {:?}, expected a relative path without `..`", path)),
+    }
+}
+
+fn file_entry(path: &str) -> String {
+    format!("{}/{}", FILES_DIR, path)
+}
+
+fn file_digest(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn bundle_error(path: &Path, error: String) -> DockermirError {
+    DockermirError::BundleError {
+        path: path.display().to_string(),
+        error,
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::LevelFilter;
+use rstest::*;
+use tokio::net::TcpListener;
+use crate::components::config::ConfigLoader;
+use crate::components::test_registry::TestRegistry;
+use crate::docker::tests::{FakeEngine, FALLBACK_YAML};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+const RELEASE_URL: &str = "https://github.com/newbe36524/tool/releases/download/v1.0";
+
+/// Serve the release files by the end of the path, a stand-in for a github mirror
+async fn start_file_server(files: &[(&str, &str)]) -> String {
+    let files: Arc<HashMap<String, String>> = Arc::new(files.iter().map(|(name, content)| (name.to_string(), content.to_string())).collect());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let files = files.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request: hyper::Request<hyper::body::Incoming>| {
+                    let content = files.iter().find(|(name, _)| request.uri().path().ends_with(name.as_str())).map(|(_, content)| content.clone());
+                    async move {
+                        let mut response = Response::new(Full::new(Bytes::from(content.clone().unwrap_or_default())));
+                        if content.is_none() {
+                            *response.status_mut() = StatusCode::NOT_FOUND;
+                        }
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+            });
+        }
+    });
+    host
+}
+
+fn bundle_config(primary: &str, secondary: &str, file_server: &str) -> RushGetConfig {
+    let yaml = FALLBACK_YAML.replace("${primary}", primary).replace("${secondary}", secondary)
+        .replace("  mirrors: []", &format!("  mirrors:\n    - name: \"test\"\n      replace_template: \"http://{}/${{release_url}}\"", file_server));
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+fn write_manifest(dir: &Path, content: &str) -> PathBuf {
+    let path = dir.join("manifest.yaml");
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn entry_names(bundle: &Path) -> Vec<String> {
+    Archive::new(File::open(bundle).unwrap()).entries().unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+        .collect()
+}
+
+#[rstest]
+#[tokio::test]
+async fn create_and_import(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let file_server = start_file_server(&[("tool.tar.gz", "tool"), ("checksums.txt", "checksums")]).await;
+    let config = bundle_config(&primary.host(), &secondary.host(), &file_server);
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(dir.path(), &format!(r#"
+images:
+  - mcr.microsoft.com/dotnet/sdk:6.0
+files:
+  - {0}/tool.tar.gz
+  - url: {0}/checksums.txt
+    path: tools/checksums.txt
+"#, RELEASE_URL));
+    let output = dir.path().join(DEFAULT_BUNDLE_FILE);
+
+    BundleCreateTask::new(config, manifest, BundleCreateOptions {
+        output: output.clone(),
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await.unwrap();
+
+    assert_eq!(entry_names(&output), vec![INDEX_ENTRY, IMAGES_ENTRY, "files/tool.tar.gz", "files/tools/checksums.txt"]);
+    let bundle = UnpackedBundle::unpack(&output).unwrap();
+    assert_eq!(bundle.index.images, vec!["mcr.microsoft.com/dotnet/sdk:6.0"]);
+    assert_eq!(bundle.index.files, vec![
+        BundleFile { url: format!("{}/tool.tar.gz", RELEASE_URL), path: "tool.tar.gz".to_string() },
+        BundleFile { url: format!("{}/checksums.txt", RELEASE_URL), path: "tools/checksums.txt".to_string() },
+    ]);
+    assert_eq!(bundle.index.checksums["files/tool.tar.gz"], crate::components::registry::sha256_digest(b"tool"));
+
+    let engine = FakeEngine::default();
+    bundle.load_images(&engine).await.unwrap();
+    let dest = dir.path().join("dest");
+    bundle.place_files(&dest).unwrap();
+
+    let calls = engine.calls.lock().unwrap().clone();
+    assert_eq!(calls.len(), 1);
+    assert!(calls[0].starts_with("load ") && calls[0].ends_with(IMAGES_ENTRY), "{}", calls[0]);
+    assert_eq!(fs::read_to_string(dest.join("tool.tar.gz")).unwrap(), "tool");
+    assert_eq!(fs::read_to_string(dest.join("tools/checksums.txt")).unwrap(), "checksums");
+}
+
+#[cfg(unix)]
+#[rstest]
+#[tokio::test]
+async fn import_into_docker_api(_init_logger: ()) {
+    use crate::components::docker_api::DockerApi;
+    use crate::components::test_docker_daemon::TestDockerDaemon;
+
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let config = bundle_config(&primary.host(), &secondary.host(), "127.0.0.1:1");
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(dir.path(), "images:\n  - mcr.microsoft.com/dotnet/sdk:6.0\n");
+    let output = dir.path().join(DEFAULT_BUNDLE_FILE);
+    BundleCreateTask::new(config, manifest, BundleCreateOptions {
+        output: output.clone(),
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await.unwrap();
+
+    let daemon = TestDockerDaemon::start().await;
+    UnpackedBundle::unpack(&output).unwrap().load_images(&DockerApi::new(daemon.host())).await.unwrap();
+
+    assert_eq!(daemon.local_tags(), vec!["mcr.microsoft.com/dotnet/sdk:6.0"]);
+    assert_eq!(daemon.requests(), vec!["POST /images/load?quiet=0"]);
+}
+
+/// Copy the bundle with the content of one entry replaced, the index is kept
+fn tamper(bundle: &Path, name: &str, content: &[u8]) {
+    let tampered = bundle.with_extension("tampered");
+    let mut builder = Builder::new(File::create(&tampered).unwrap());
+    let mut archive = Archive::new(File::open(bundle).unwrap());
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().display().to_string();
+        let mut header = entry.header().clone();
+        if path == name {
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, &path, content).unwrap();
+        } else {
+            builder.append_data(&mut header, &path, &mut entry).unwrap();
+        }
+    }
+    builder.finish().unwrap();
+    fs::rename(tampered, bundle).unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn reject_tampered_bundle(_init_logger: ()) {
+    let file_server = start_file_server(&[("tool.tar.gz", "tool")]).await;
+    let config = bundle_config("127.0.0.1:1", "127.0.0.1:1", &file_server);
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(dir.path(), &format!("files:\n  - {}/tool.tar.gz\n", RELEASE_URL));
+    let output = dir.path().join(DEFAULT_BUNDLE_FILE);
+    BundleCreateTask::new(config, manifest, BundleCreateOptions {
+        output: output.clone(),
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await.unwrap();
+    UnpackedBundle::unpack(&output).unwrap();
+
+    tamper(&output, "files/tool.tar.gz", b"evil");
+
+    match UnpackedBundle::unpack(&output) {
+        Err(DockermirError::BundleError { error, .. }) => assert!(error.contains("checksum mismatch of files/tool.tar.gz"), "{}", error),
+        other => panic!("unexpected result: {:?}", other.map(|bundle| bundle.index)),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn missing_release_file(_init_logger: ()) {
+    let file_server = start_file_server(&[]).await;
+    let config = bundle_config("127.0.0.1:1", "127.0.0.1:1", &file_server);
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = write_manifest(dir.path(), &format!("files:\n  - {}/tool.tar.gz\n", RELEASE_URL));
+    let output = dir.path().join(DEFAULT_BUNDLE_FILE);
+
+    let result = BundleCreateTask::new(config, manifest, BundleCreateOptions {
+        output: output.clone(),
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await;
+
+    assert!(matches!(result, Err(DockermirError::GithubReleaseDownloadError { .. })), "{:?}", result);
+    assert!(!output.exists());
+}
+
+#[rstest]
+#[case("tool.tar.gz", true)]
+#[case("tools/linux/tool", true)]
+#[case("", false)]
+#[case("../tool", false)]
+#[case("tools/../../tool", false)]
+#[case("/usr/local/bin/tool", false)]
+fn check_file_paths(_init_logger: (), #[case] path: &str, #[case] valid: bool) {
+    let manifest: BundleManifest = serde_yaml::from_str(&format!("files:\n  - url: {}/tool\n    path: \"{}\"\n", RELEASE_URL, path)).unwrap();
+    assert_eq!(manifest.bundle_files().is_ok(), valid);
+}
+
+#[rstest]
+fn reject_duplicate_paths(_init_logger: ()) {
+    let manifest: BundleManifest = serde_yaml::from_str(&format!(
+        "files:\n  - {0}/tool\n  - url: https://github.com/other/tool/releases/download/v2.0/tool\n    path: tool\n", RELEASE_URL)).unwrap();
+    let error = manifest.bundle_files().unwrap_err();
+    assert!(error.contains("more than one file is placed at tool"), "{}", error);
+}
+
+#[rstest]
+fn reject_unlisted_checksum(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let staged = dir.path().join("tool");
+    fs::write(&staged, "tool").unwrap();
+    let output = dir.path().join(DEFAULT_BUNDLE_FILE);
+    // the index promises a file it has no checksum for
+    let index = BundleIndex {
+        version: BUNDLE_VERSION,
+        images: vec![],
+        files: vec![BundleFile { url: format!("{}/tool", RELEASE_URL), path: "tool".to_string() }],
+        checksums: BTreeMap::new(),
+    };
+    write_bundle(&output, &index, &[("files/tool".to_string(), staged)]).unwrap();
+
+    assert!(matches!(UnpackedBundle::unpack(&output), Err(DockermirError::BundleError { error, .. }) if error == "no checksum for files/tool"));
+}
@@ -1,7 +1,17 @@
 use crate::error::DockermirError;
 
 pub(crate) mod config;
+pub(crate) mod container_engine;
+pub(crate) mod credentials;
+pub(crate) mod docker_api;
 pub(crate) mod docker_exec;
+pub(crate) mod oci_layout;
+pub(crate) mod progress;
+pub(crate) mod registry;
+#[cfg(all(test, unix))]
+pub(crate) mod test_docker_daemon;
+#[cfg(test)]
+pub(crate) mod test_registry;
 
 #[async_trait::async_trait]
 pub(crate) trait RushGetTask {
@@ -2,6 +2,7 @@ use serde::Deserialize;
 use reqwest::Client;
 use std::fs;
 use std::path::Path;
+use crate::components::container_engine::ContainerEngineKind;
 use crate::error::DockermirError;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -16,9 +17,28 @@ pub(crate) struct DockerMirrorRuleset {
     pub(crate) name: String,
     pub(crate) mirror_host: String,
     pub(crate) mirror_namespace: String,
+    /// Credentials of the mirror host, looked up in the environment and the docker config if not set
+    #[serde(default)]
+    pub(crate) auth: Option<RegistryAuthConfig>,
     pub(crate) rules: Vec<DockerMirrorRule>,
 }
 
+/// The password can reference a secret with `${env:NAME}` or `${file:PATH}` instead of being written inline
+#[derive(Deserialize, Clone)]
+pub(crate) struct RegistryAuthConfig {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+impl std::fmt::Debug for RegistryAuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryAuthConfig")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub(crate) struct RushGetGithubConfig {
     pub(crate) mirrors: Vec<GithubMirror>,
@@ -32,9 +52,33 @@ pub(crate) struct GithubMirror {
 
 #[derive(Debug, Deserialize, Clone)]
 pub(crate) struct RushGetDockerConfig {
+    /// The container engine to pull images with, detected from PATH if not set
+    #[serde(default)]
+    pub(crate) engine: Option<ContainerEngineKind>,
+    /// Pull from the original registry when all mirrors failed
+    #[serde(default)]
+    pub(crate) fallback_to_upstream: bool,
+    /// How many images are pulled at the same time in a batch
+    #[serde(default)]
+    pub(crate) concurrency: Option<usize>,
+    /// What is done with the image after the container engine pulled it
+    #[serde(default)]
+    pub(crate) post_pull: PostPullConfig,
+    /// Rulesets are tried in order, the earlier the ruleset the higher the priority
     pub(crate) ruleset: Vec<DockerMirrorRuleset>,
 }
 
+/// By default the pulled image is tagged with the source name and the mirror name is removed
+#[derive(Debug, Default, Deserialize, Clone)]
+pub(crate) struct PostPullConfig {
+    /// Keep the mirror name next to the source name
+    #[serde(default)]
+    pub(crate) keep_mirror_tag: bool,
+    /// Extra names of the image, `${registry}`, `${repository}` and `${tag}` are taken from the source image
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub(crate) struct RushGetConfig {
     pub(crate) name: String,
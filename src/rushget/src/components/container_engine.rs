@@ -0,0 +1,109 @@
+#[cfg(test)]
+mod tests;
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use serde::Deserialize;
+use crate::components::config::RushGetConfig;
+use crate::components::credentials::RegistryCredential;
+use crate::components::docker_api::{DockerApi, DockerHost};
+use crate::components::docker_exec::{DockerExec, DockermirPullInput};
+use crate::components::progress::PullProgress;
+use crate::error::DockermirError;
+
+/// The container engine used to pull images into the local image store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ContainerEngineKind {
+    Docker,
+    Podman,
+    /// nerdctl, the docker compatible cli of containerd
+    Nerdctl,
+    /// The Docker Engine API on the socket of `DOCKER_HOST`, without the docker cli
+    #[serde(rename = "docker-api")]
+    DockerApi,
+}
+
+impl ContainerEngineKind {
+    const ALL: [ContainerEngineKind; 3] = [ContainerEngineKind::Docker, ContainerEngineKind::Podman, ContainerEngineKind::Nerdctl];
+
+    pub(crate) fn program(&self) -> &'static str {
+        match self {
+            ContainerEngineKind::Docker => "docker",
+            ContainerEngineKind::Podman => "podman",
+            ContainerEngineKind::Nerdctl => "nerdctl",
+            ContainerEngineKind::DockerApi => "docker",
+        }
+    }
+
+    /// Find the first engine which is available on PATH
+    pub(crate) fn detect() -> Option<ContainerEngineKind> {
+        Self::detect_in(&env::var_os("PATH")?)
+    }
+
+    fn detect_in(path: &OsStr) -> Option<ContainerEngineKind> {
+        let dirs: Vec<_> = env::split_paths(path).collect();
+        ContainerEngineKind::ALL.into_iter().find(|kind| {
+            dirs.iter().any(|dir| dir.join(kind.program()).is_file() || dir.join(format!("{}.exe", kind.program())).is_file())
+        })
+    }
+
+    /// The engine set in cli takes precedence over the config, detect from PATH if neither is set
+    pub(crate) fn resolve(cli: Option<ContainerEngineKind>, config: &RushGetConfig) -> Result<ContainerEngineKind, DockermirError> {
+        if let Some(kind) = cli.or(config.docker.engine) {
+            return Ok(kind);
+        }
+        let kind = ContainerEngineKind::detect().ok_or(DockermirError::ContainerEngineNotFound)?;
+        trace!("Detected container engine: {}", kind);
+        Ok(kind)
+    }
+}
+
+impl Display for ContainerEngineKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerEngineKind::DockerApi => write!(f, "docker-api"),
+            _ => write!(f, "{}", self.program()),
+        }
+    }
+}
+
+/// Part of the output of `image inspect`, the field names are shared by docker, podman and nerdctl
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct ImageInspect {
+    pub(crate) id: String,
+    #[serde(default)]
+    pub(crate) repo_tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub(crate) repo_digests: Option<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+pub(crate) trait ContainerEngine: Send + Sync {
+    fn kind(&self) -> ContainerEngineKind;
+    /// Store the credential of the registry in the engine, so that its pulls are authenticated
+    async fn login(&self, registry: &str, credential: &RegistryCredential) -> Result<(), DockermirError>;
+    /// Pull the mirror image, reporting the progress of its layers while pulling
+    async fn pull(&self, input: &DockermirPullInput, progress: &PullProgress) -> Result<(), DockermirError>;
+    async fn tag(&self, input: &DockermirPullInput) -> Result<(), DockermirError>;
+    async fn rmi(&self, image: &str) -> Result<(), DockermirError>;
+    /// Push the local image to its registry, reporting the progress of its layers while pushing
+    async fn push(&self, image: &str, progress: &PullProgress) -> Result<(), DockermirError>;
+    /// Run the build with the arguments of `docker build`, the output of the build goes to the terminal
+    async fn build(&self, args: &[String]) -> Result<(), DockermirError>;
+    /// Load the images of a `docker save` archive into the local image store
+    async fn load(&self, archive: &Path, progress: &PullProgress) -> Result<(), DockermirError>;
+    /// Inspect the image in the local image store, returns None if the image does not exist
+    async fn inspect(&self, image: &str) -> Result<Option<ImageInspect>, DockermirError>;
+}
+
+pub(crate) fn create_engine(kind: ContainerEngineKind) -> Result<Box<dyn ContainerEngine>, DockermirError> {
+    match kind {
+        ContainerEngineKind::DockerApi => Ok(Box::new(DockerApi::new(DockerHost::from_env()?))),
+        // podman and nerdctl share the command line interface of docker
+        _ => Ok(Box::new(DockerExec::new(kind))),
+    }
+}
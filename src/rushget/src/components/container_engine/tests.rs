@@ -0,0 +1,50 @@
+use std::fs::File;
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, DEFAULT_CONFIG_YAML};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+fn detect_first_engine_on_path(_init_logger: ()) {
+    let docker_dir = tempfile::tempdir().unwrap();
+    let podman_dir = tempfile::tempdir().unwrap();
+    File::create(podman_dir.path().join("podman")).unwrap();
+    let path = env::join_paths([docker_dir.path(), podman_dir.path()]).unwrap();
+    assert_eq!(ContainerEngineKind::detect_in(&path), Some(ContainerEngineKind::Podman));
+
+    File::create(docker_dir.path().join("docker")).unwrap();
+    assert_eq!(ContainerEngineKind::detect_in(&path), Some(ContainerEngineKind::Docker));
+}
+
+#[rstest]
+fn detect_nothing_on_path(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = env::join_paths([dir.path()]).unwrap();
+    assert_eq!(ContainerEngineKind::detect_in(&path), None);
+}
+
+#[rstest]
+fn resolve_cli_over_config(_init_logger: ()) {
+    let loader = ConfigLoader::default();
+    let yaml = DEFAULT_CONFIG_YAML.replace("docker:\n", "docker:\n  engine: nerdctl\n");
+    let config = loader.load_config_yaml(&yaml).unwrap();
+    assert_eq!(config.docker.engine, Some(ContainerEngineKind::Nerdctl));
+    assert_eq!(ContainerEngineKind::resolve(None, &config), Ok(ContainerEngineKind::Nerdctl));
+    assert_eq!(ContainerEngineKind::resolve(Some(ContainerEngineKind::Podman), &config), Ok(ContainerEngineKind::Podman));
+}
+
+#[rstest]
+fn resolve_docker_api_from_config(_init_logger: ()) {
+    let yaml = DEFAULT_CONFIG_YAML.replace("docker:\n", "docker:\n  engine: docker-api\n");
+    let config = ConfigLoader::default().load_config_yaml(&yaml).unwrap();
+    assert_eq!(ContainerEngineKind::resolve(None, &config), Ok(ContainerEngineKind::DockerApi));
+    assert_eq!(ContainerEngineKind::DockerApi.to_string(), "docker-api");
+}
@@ -0,0 +1,260 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::process::Stdio;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use crate::components::config::RegistryAuthConfig;
+use crate::docker::reference::DOCKER_HUB_REGISTRY;
+use crate::error::DockermirError;
+
+/// The username docker uses for identity tokens, the secret is then an OAuth2 refresh token
+pub(crate) const IDENTITY_TOKEN_USERNAME: &str = "<token>";
+/// Docker keeps the credentials of docker hub under its legacy v1 address
+const DOCKER_HUB_CONFIG_KEY: &str = "https://index.docker.io/v1/";
+const DOCKER_CONFIG_ENV: &str = "DOCKER_CONFIG";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CredentialSource {
+    Config,
+    Environment,
+    DockerConfig,
+    Helper(String),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct RegistryCredential {
+    pub(crate) username: String,
+    pub(crate) secret: String,
+    pub(crate) source: CredentialSource,
+}
+
+impl RegistryCredential {
+    pub(crate) fn is_identity_token(&self) -> bool {
+        self.username == IDENTITY_TOKEN_USERNAME
+    }
+
+    /// Credentials of the docker config are known to the engine already, the others have to be passed by a login
+    pub(crate) fn requires_login(&self) -> bool {
+        matches!(self.source, CredentialSource::Config | CredentialSource::Environment)
+    }
+}
+
+impl Debug for RegistryCredential {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryCredential")
+            .field("username", &self.username)
+            .field("secret", &"***")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DockerConfigFile {
+    #[serde(default)]
+    auths: HashMap<String, DockerConfigAuth>,
+    #[serde(rename = "credsStore")]
+    creds_store: Option<String>,
+    #[serde(rename = "credHelpers", default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DockerConfigAuth {
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    identitytoken: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredential {
+    username: String,
+    secret: String,
+}
+
+/// Resolve the credential of a registry, the first source which knows the registry wins:
+/// the auth of the ruleset, `RUSHGET_AUTH_<HOST>_USERNAME` and `RUSHGET_AUTH_<HOST>_PASSWORD`,
+/// then `credHelpers`, `credsStore` and `auths` of the docker config
+pub(crate) struct CredentialResolver {
+    config_dir: Option<PathBuf>,
+    helper_path: Option<OsString>,
+}
+
+impl CredentialResolver {
+    pub(crate) fn new() -> CredentialResolver {
+        let config_dir = std::env::var_os(DOCKER_CONFIG_ENV).map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(|home| PathBuf::from(home).join(".docker")));
+        CredentialResolver {
+            config_dir,
+            helper_path: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_config_dir(config_dir: PathBuf, helper_path: Option<OsString>) -> CredentialResolver {
+        CredentialResolver {
+            config_dir: Some(config_dir),
+            helper_path,
+        }
+    }
+
+    pub(crate) async fn resolve(&self, registry: &str, auth: Option<&RegistryAuthConfig>) -> Result<Option<RegistryCredential>, DockermirError> {
+        let credential_error = |error: String| DockermirError::RegistryCredentialError {
+            registry: registry.to_string(),
+            error,
+        };
+        if let Some(auth) = auth {
+            return Ok(Some(RegistryCredential {
+                username: auth.username.clone(),
+                secret: resolve_secret(&auth.password).map_err(credential_error)?,
+                source: CredentialSource::Config,
+            }));
+        }
+        if let Some(credential) = credential_from_env(registry) {
+            return Ok(Some(credential));
+        }
+        let config = self.load_docker_config().map_err(credential_error)?;
+        let key = docker_config_key(registry);
+        let helper = config.cred_helpers.iter()
+            .find(|(server, _)| normalize_server(server) == normalize_server(&key))
+            .map(|(_, helper)| helper)
+            .or(config.creds_store.as_ref());
+        if let Some(helper) = helper {
+            match self.run_helper(helper, &key).await {
+                Ok(Some(credential)) => return Ok(Some(credential)),
+                Ok(None) => trace!("Credential helper {} has no credential for {}", helper, registry),
+                Err(e) => warn!("Failed to get credential of {} from helper {}, error: {}", registry, helper, e),
+            }
+        }
+        let auth = config.auths.iter()
+            .find(|(server, _)| normalize_server(server) == normalize_server(&key))
+            .map(|(_, auth)| auth);
+        match auth {
+            Some(auth) => credential_from_docker_auth(auth).map_err(credential_error),
+            None => Ok(None),
+        }
+    }
+
+    fn load_docker_config(&self) -> Result<DockerConfigFile, String> {
+        let Some(path) = self.config_dir.as_ref().map(|dir| dir.join("config.json")) else {
+            return Ok(DockerConfigFile::default());
+        };
+        if !path.is_file() {
+            return Ok(DockerConfigFile::default());
+        }
+        let content = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    async fn run_helper(&self, helper: &str, server: &str) -> Result<Option<RegistryCredential>, String> {
+        let program = format!("docker-credential-{}", helper);
+        let mut command = Command::new(&program);
+        if let Some(path) = &self.helper_path {
+            command.env("PATH", path);
+        }
+        let mut child = command.arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", program, e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(server.as_bytes()).await.map_err(|e| e.to_string())?;
+        drop(stdin);
+        let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            // helpers print "credentials not found in native keychain" for unknown servers
+            let message = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+            if message.to_lowercase().contains("not found") {
+                return Ok(None);
+            }
+            return Err(format!("{}, {}", output.status, message.trim()));
+        }
+        let credential: HelperCredential = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+        Ok(Some(RegistryCredential {
+            username: credential.username,
+            secret: credential.secret,
+            source: CredentialSource::Helper(helper.to_string()),
+        }))
+    }
+}
+
+/// `${env:NAME}` and `${file:PATH}` are read from the environment and the file, anything else is the secret itself
+fn resolve_secret(value: &str) -> Result<String, String> {
+    let Some(reference) = value.strip_prefix("${").and_then(|value| value.strip_suffix('}')) else {
+        return Ok(value.to_string());
+    };
+    if let Some(name) = reference.strip_prefix("env:") {
+        return std::env::var(name).map_err(|_| format!("environment variable {} is not set", name));
+    }
+    if let Some(path) = reference.strip_prefix("file:") {
+        return std::fs::read_to_string(path)
+            .map(|content| content.trim_end().to_string())
+            .map_err(|e| format!("failed to read secret file {}: {}", path, e));
+    }
+    Err(format!("unknown secret reference: {}", value))
+}
+
+/// `registry.example.com:5000` is looked up as `RUSHGET_AUTH_REGISTRY_EXAMPLE_COM_5000_USERNAME`
+fn env_prefix(registry: &str) -> String {
+    let host: String = registry.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("RUSHGET_AUTH_{}", host)
+}
+
+fn credential_from_env(registry: &str) -> Option<RegistryCredential> {
+    let prefix = env_prefix(registry);
+    let username = std::env::var(format!("{}_USERNAME", prefix)).ok()?;
+    let secret = std::env::var(format!("{}_PASSWORD", prefix)).ok()?;
+    Some(RegistryCredential {
+        username,
+        secret,
+        source: CredentialSource::Environment,
+    })
+}
+
+fn credential_from_docker_auth(auth: &DockerConfigAuth) -> Result<Option<RegistryCredential>, String> {
+    let credential = |username: String, secret: String| RegistryCredential {
+        username,
+        secret,
+        source: CredentialSource::DockerConfig,
+    };
+    if let Some(token) = &auth.identitytoken {
+        return Ok(Some(credential(IDENTITY_TOKEN_USERNAME.to_string(), token.clone())));
+    }
+    if let Some(encoded) = auth.auth.as_ref().filter(|encoded| !encoded.is_empty()) {
+        let decoded = STANDARD.decode(encoded).map_err(|e| format!("invalid auth in docker config: {}", e))?;
+        let decoded = String::from_utf8(decoded).map_err(|e| format!("invalid auth in docker config: {}", e))?;
+        let (username, password) = decoded.split_once(':').ok_or_else(|| "invalid auth in docker config".to_string())?;
+        return Ok(Some(credential(username.to_string(), password.to_string())));
+    }
+    match (&auth.username, &auth.password) {
+        (Some(username), Some(password)) => Ok(Some(credential(username.clone(), password.clone()))),
+        _ => Ok(None),
+    }
+}
+
+fn docker_config_key(registry: &str) -> String {
+    if registry == DOCKER_HUB_REGISTRY {
+        DOCKER_HUB_CONFIG_KEY.to_string()
+    } else {
+        registry.to_string()
+    }
+}
+
+/// The keys of the docker config may be urls like `https://registry.example.com/v1/`
+fn normalize_server(server: &str) -> &str {
+    let server = server.strip_prefix("https://").or_else(|| server.strip_prefix("http://")).unwrap_or(server);
+    server.split('/').next().unwrap_or(server)
+}
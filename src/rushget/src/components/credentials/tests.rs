@@ -0,0 +1,122 @@
+use log::LevelFilter;
+use rstest::*;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn auth(username: &str, password: &str) -> RegistryAuthConfig {
+    RegistryAuthConfig {
+        username: username.to_string(),
+        password: password.to_string(),
+    }
+}
+
+fn resolver_with_config(config: &str) -> (tempfile::TempDir, CredentialResolver) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.json"), config).unwrap();
+    let resolver = CredentialResolver::with_config_dir(dir.path().to_path_buf(), Some(dir.path().as_os_str().to_owned()));
+    (dir, resolver)
+}
+
+#[rstest]
+#[tokio::test]
+async fn resolve_inline_secret_reference(_init_logger: ()) {
+    std::env::set_var("RUSHGET_TEST_INLINE_PASSWORD", "from-env");
+    let (dir, resolver) = resolver_with_config("{}");
+    let secret_file = dir.path().join("password");
+    std::fs::write(&secret_file, "from-file\n").unwrap();
+
+    let credential = resolver.resolve("mirror.example.com", Some(&auth("robot", "${env:RUSHGET_TEST_INLINE_PASSWORD}"))).await.unwrap().unwrap();
+    assert_eq!((credential.username.as_str(), credential.secret.as_str()), ("robot", "from-env"));
+    assert_eq!(credential.source, CredentialSource::Config);
+    assert!(credential.requires_login());
+
+    let reference = format!("${{file:{}}}", secret_file.display());
+    let credential = resolver.resolve("mirror.example.com", Some(&auth("robot", &reference))).await.unwrap().unwrap();
+    assert_eq!(credential.secret, "from-file");
+
+    let credential = resolver.resolve("mirror.example.com", Some(&auth("robot", "inline"))).await.unwrap().unwrap();
+    assert_eq!(credential.secret, "inline");
+}
+
+#[rstest]
+#[tokio::test]
+async fn resolve_missing_secret(_init_logger: ()) {
+    let (_dir, resolver) = resolver_with_config("{}");
+    let result = resolver.resolve("mirror.example.com", Some(&auth("robot", "${env:RUSHGET_TEST_MISSING_PASSWORD}"))).await;
+    assert!(matches!(result, Err(DockermirError::RegistryCredentialError { .. })));
+}
+
+#[rstest]
+#[tokio::test]
+async fn resolve_from_environment(_init_logger: ()) {
+    assert_eq!(env_prefix("env.example.com:5000"), "RUSHGET_AUTH_ENV_EXAMPLE_COM_5000");
+    std::env::set_var("RUSHGET_AUTH_ENV_EXAMPLE_COM_5000_USERNAME", "ci");
+    std::env::set_var("RUSHGET_AUTH_ENV_EXAMPLE_COM_5000_PASSWORD", "secret");
+    let (_dir, resolver) = resolver_with_config("{}");
+    let credential = resolver.resolve("env.example.com:5000", None).await.unwrap().unwrap();
+    assert_eq!((credential.username.as_str(), credential.secret.as_str()), ("ci", "secret"));
+    assert_eq!(credential.source, CredentialSource::Environment);
+}
+
+#[rstest]
+#[tokio::test]
+async fn resolve_from_docker_config_auths(_init_logger: ()) {
+    let (_dir, resolver) = resolver_with_config(r#"{
+        "auths": {
+            "https://private.example.com/v1/": {"auth": "dXNlcjpwYXNz"},
+            "https://index.docker.io/v1/": {"username": "hub", "password": "hub-pass"},
+            "token.example.com": {"auth": "", "identitytoken": "refresh"}
+        }
+    }"#);
+
+    let credential = resolver.resolve("private.example.com", None).await.unwrap().unwrap();
+    assert_eq!((credential.username.as_str(), credential.secret.as_str()), ("user", "pass"));
+    assert_eq!(credential.source, CredentialSource::DockerConfig);
+    assert!(!credential.requires_login());
+
+    let credential = resolver.resolve(DOCKER_HUB_REGISTRY, None).await.unwrap().unwrap();
+    assert_eq!(credential.username, "hub");
+
+    let credential = resolver.resolve("token.example.com", None).await.unwrap().unwrap();
+    assert!(credential.is_identity_token());
+    assert_eq!(credential.secret, "refresh");
+
+    assert_eq!(resolver.resolve("unknown.example.com", None).await.unwrap(), None);
+}
+
+#[cfg(unix)]
+#[rstest]
+#[tokio::test]
+async fn resolve_from_credential_helper(_init_logger: ()) {
+    use std::os::unix::fs::PermissionsExt;
+    let (dir, resolver) = resolver_with_config(r#"{
+        "credsStore": "test",
+        "auths": {"fallback.example.com": {"auth": "dXNlcjpwYXNz"}}
+    }"#);
+    let helper = dir.path().join("docker-credential-test");
+    std::fs::write(&helper, r#"#!/bin/sh
+read server
+if [ "$server" = "helper.example.com" ]; then
+  echo '{"ServerURL":"helper.example.com","Username":"from-helper","Secret":"helper-secret"}'
+else
+  echo "credentials not found in native keychain"
+  exit 1
+fi
+"#).unwrap();
+    std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let credential = resolver.resolve("helper.example.com", None).await.unwrap().unwrap();
+    assert_eq!((credential.username.as_str(), credential.secret.as_str()), ("from-helper", "helper-secret"));
+    assert_eq!(credential.source, CredentialSource::Helper("test".to_string()));
+
+    // the helper does not know the server, so the auths of the config are used
+    let credential = resolver.resolve("fallback.example.com", None).await.unwrap().unwrap();
+    assert_eq!(credential.username, "user");
+}
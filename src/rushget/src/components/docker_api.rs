@@ -0,0 +1,492 @@
+#[cfg(all(test, unix))]
+mod tests;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use crate::components::container_engine::{ContainerEngine, ContainerEngineKind, ImageInspect};
+use crate::components::credentials::RegistryCredential;
+use crate::components::docker_exec::DockermirPullInput;
+use crate::components::progress::PullProgress;
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+const DEFAULT_DOCKER_HOST: &str = "unix:///var/run/docker.sock";
+const LOAD_CHUNK_SIZE: usize = 64 * 1024;
+/// `{}`, the daemon requires the header for pushes even without credentials
+const ANONYMOUS_REGISTRY_AUTH: &str = "e30=";
+
+/// Where the docker daemon listens, taken from `DOCKER_HOST` like the docker cli does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DockerHost {
+    Unix(PathBuf),
+    Tcp(String),
+    /// `ssh://[user@]host[:port]`, the connection runs through `docker system dial-stdio` on the remote host
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+    },
+}
+
+impl DockerHost {
+    pub(crate) fn from_env() -> Result<DockerHost, DockermirError> {
+        let host = env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_DOCKER_HOST.to_string());
+        DockerHost::parse(&host)
+    }
+
+    pub(crate) fn parse(host: &str) -> Result<DockerHost, DockermirError> {
+        let invalid = |error: &str| DockermirError::DockerDaemonError {
+            host: host.to_string(),
+            error: error.to_string(),
+        };
+        if let Some(path) = host.strip_prefix("unix://") {
+            return Ok(DockerHost::Unix(PathBuf::from(path)));
+        }
+        if let Some(address) = host.strip_prefix("tcp://") {
+            let address = address.trim_end_matches('/');
+            if address.is_empty() {
+                return Err(invalid("missing address"));
+            }
+            return Ok(DockerHost::Tcp(address.to_string()));
+        }
+        if let Some(rest) = host.strip_prefix("ssh://") {
+            let rest = rest.trim_end_matches('/');
+            let (destination, port) = match rest.rsplit_once(':') {
+                Some((destination, port)) => (destination, Some(port.parse::<u16>().map_err(|_| invalid("invalid port"))?)),
+                None => (rest, None),
+            };
+            if destination.is_empty() {
+                return Err(invalid("missing host"));
+            }
+            return Ok(DockerHost::Ssh {
+                destination: destination.to_string(),
+                port,
+            });
+        }
+        Err(invalid("unsupported scheme, expected unix://, tcp:// or ssh://"))
+    }
+
+    pub(crate) async fn connect(&self) -> std::io::Result<Box<dyn Connection>> {
+        match self {
+            #[cfg(unix)]
+            DockerHost::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            DockerHost::Unix(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets are not supported on this platform")),
+            DockerHost::Tcp(address) => Ok(Box::new(TcpStream::connect(address).await?)),
+            DockerHost::Ssh { destination, port } => {
+                let mut command = Command::new("ssh");
+                if let Some(port) = port {
+                    command.args(["-p", &port.to_string()]);
+                }
+                let mut child = command.args([destination.as_str(), "docker", "system", "dial-stdio"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                // ssh exits by itself once the connection is dropped and its stdin is closed
+                let stdout = child.stdout.take().unwrap();
+                let stdin = child.stdin.take().unwrap();
+                Ok(Box::new(tokio::io::join(stdout, stdin)))
+            }
+        }
+    }
+}
+
+impl Display for DockerHost {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DockerHost::Unix(path) => write!(f, "unix://{}", path.display()),
+            DockerHost::Tcp(address) => write!(f, "tcp://{}", address),
+            DockerHost::Ssh { destination, port: Some(port) } => write!(f, "ssh://{}:{}", destination, port),
+            DockerHost::Ssh { destination, port: None } => write!(f, "ssh://{}", destination),
+        }
+    }
+}
+
+pub(crate) trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The body of the error responses of the daemon
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+/// One line of the progress stream of pulls and pushes
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default, rename = "progressDetail")]
+    progress_detail: Option<ProgressDetail>,
+    #[serde(default)]
+    error: Option<String>,
+    /// The output of loads, e.g. `Loaded image: nginx:latest`
+    #[serde(default)]
+    stream: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProgressDetail {
+    #[serde(default)]
+    current: Option<u64>,
+    #[serde(default)]
+    total: Option<u64>,
+}
+
+/// Talk to the Docker Engine API of the daemon instead of running the cli, podman serves the same API on its socket
+pub(crate) struct DockerApi {
+    host: DockerHost,
+    /// The `X-Registry-Auth` of the registries logged in to, the daemon keeps no credentials by itself
+    auths: Mutex<HashMap<String, String>>,
+}
+
+impl DockerApi {
+    pub(crate) fn new(host: DockerHost) -> DockerApi {
+        DockerApi {
+            host,
+            auths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send the request over a new connection, only a failure to reach the daemon is an error here
+    async fn send(&self, method: Method, path: &str, registry_auth: Option<String>, body: Option<&Value>)
+                  -> Result<Response<Incoming>, DockermirError> {
+        trace!("docker api: {} {}", method, path);
+        let mut builder = Request::builder().method(method).uri(path).header(HOST, "docker");
+        if let Some(registry_auth) = registry_auth {
+            builder = builder.header("X-Registry-Auth", registry_auth);
+        }
+        let content = match body {
+            Some(body) => {
+                builder = builder.header(CONTENT_TYPE, "application/json");
+                body.to_string().into_bytes()
+            }
+            None => vec![],
+        };
+        let request = builder.body(Full::new(Bytes::from(content))).map_err(|e| DockermirError::DockerDaemonError {
+            host: self.host.to_string(),
+            error: e.to_string(),
+        })?;
+        send_request(&self.host, request).await
+    }
+
+    fn registry_auth(&self, registry: &str) -> String {
+        self.auths.lock().unwrap().get(registry).cloned().unwrap_or_else(|| ANONYMOUS_REGISTRY_AUTH.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl ContainerEngine for DockerApi {
+    fn kind(&self) -> ContainerEngineKind {
+        ContainerEngineKind::DockerApi
+    }
+
+    async fn login(&self, registry: &str, credential: &RegistryCredential) -> Result<(), DockermirError> {
+        // the daemon checks the credential against the registry, the pulls carry it in X-Registry-Auth
+        let response = self.send(Method::POST, "/auth", None, Some(&auth_config(registry, credential))).await?;
+        if !response.status().is_success() {
+            return Err(DockermirError::DockerLoginError {
+                registry: registry.to_owned(),
+                error: error_message(response).await,
+            });
+        }
+        self.auths.lock().unwrap().insert(registry.to_string(), registry_auth(registry, Some(credential)));
+        Ok(())
+    }
+
+    async fn pull(&self, input: &DockermirPullInput, progress: &PullProgress) -> Result<(), DockermirError> {
+        let pull_error = |error: String| DockermirError::DockerPullError {
+            source_image: input.source_image.clone(),
+            mirror_image: input.mirror_image.clone(),
+            error,
+        };
+        let reference = ImageReference::parse(&input.mirror_image)?;
+        let mut query = format!("fromImage={}&tag={}", encode(&format!("{}/{}", reference.registry, reference.repository)),
+                                encode(reference.reference()));
+        if let Some(platform) = &input.platform {
+            query.push_str(&format!("&platform={}", encode(&platform.to_string())));
+        }
+        let response = self.send(Method::POST, &format!("/images/create?{}", query),
+                                 Some(self.registry_auth(&reference.registry)), None).await?;
+        if !response.status().is_success() {
+            return Err(pull_error(error_message(response).await));
+        }
+        read_progress(response, progress).await.map_err(pull_error)
+    }
+
+    async fn tag(&self, input: &DockermirPullInput) -> Result<(), DockermirError> {
+        let tag_error = |error: String| DockermirError::DockerTagError {
+            source_image: input.source_image.clone(),
+            mirror_image: input.mirror_image.clone(),
+            error,
+        };
+        let target = ImageReference::parse(&input.source_image)?;
+        let tag = match (&target.tag, &target.digest) {
+            (Some(tag), None) => tag,
+            _ => return Err(tag_error("only tags can be added to an image, not digests".to_string())),
+        };
+        let path = format!("/images/{}/tag?repo={}&tag={}", input.mirror_image,
+                           encode(&format!("{}/{}", target.registry, target.repository)), encode(tag));
+        let response = self.send(Method::POST, &path, None, None).await?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(tag_error(error_message(response).await)),
+        }
+    }
+
+    async fn rmi(&self, image: &str) -> Result<(), DockermirError> {
+        let response = self.send(Method::DELETE, &format!("/images/{}", image), None, None).await?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(DockermirError::DockerRemoveImageError {
+                image: image.to_owned(),
+                error: error_message(response).await,
+            }),
+        }
+    }
+
+    async fn push(&self, image: &str, progress: &PullProgress) -> Result<(), DockermirError> {
+        let push_error = |error: String| DockermirError::DockerPushError {
+            image: image.to_owned(),
+            error,
+        };
+        let reference = ImageReference::parse(image)?;
+        let path = format!("/images/{}/{}/push?tag={}", reference.registry, reference.repository, encode(reference.reference()));
+        let response = self.send(Method::POST, &path, Some(self.registry_auth(&reference.registry)), None).await?;
+        if !response.status().is_success() {
+            return Err(push_error(error_message(response).await));
+        }
+        read_progress(response, progress).await.map_err(push_error)
+    }
+
+    /// The arguments are those of the `docker build` cli, which have no counterpart in `POST /build` for every flag
+    async fn build(&self, _args: &[String]) -> Result<(), DockermirError> {
+        Err(DockermirError::EngineUnsupported {
+            engine: ContainerEngineKind::DockerApi.to_string(),
+            operation: "build".to_string(),
+        })
+    }
+
+    async fn load(&self, archive: &Path, progress: &PullProgress) -> Result<(), DockermirError> {
+        let load_error = |error: String| DockermirError::DockerLoadError {
+            archive: archive.display().to_string(),
+            error,
+        };
+        let file = tokio::fs::File::open(archive).await.map_err(|e| load_error(e.to_string()))?;
+        // the archive is streamed in chunks, image archives easily outgrow the memory
+        let chunks = futures_util::stream::unfold(file, |mut file| async move {
+            let mut buffer = vec![0; LOAD_CHUNK_SIZE];
+            match file.read(&mut buffer).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    buffer.truncate(read);
+                    Some((Ok::<_, std::io::Error>(Frame::data(Bytes::from(buffer))), file))
+                }
+                Err(e) => Some((Err(e), file)),
+            }
+        });
+        trace!("docker api: POST /images/load");
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/images/load?quiet=0")
+            .header(HOST, "docker")
+            .header(CONTENT_TYPE, "application/x-tar")
+            .body(StreamBody::new(chunks))
+            .map_err(|e| load_error(e.to_string()))?;
+        let response = send_request(&self.host, request).await?;
+        if !response.status().is_success() {
+            return Err(load_error(error_message(response).await));
+        }
+        read_progress(response, progress).await.map_err(load_error)
+    }
+
+    async fn inspect(&self, image: &str) -> Result<Option<ImageInspect>, DockermirError> {
+        let inspect_error = |error: String| DockermirError::DockerInspectError {
+            image: image.to_owned(),
+            error,
+        };
+        let response = self.send(Method::GET, &format!("/images/{}/json", image), None, None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let body = response.into_body().collect().await.map_err(|e| inspect_error(e.to_string()))?.to_bytes();
+                serde_json::from_slice(&body).map(Some).map_err(|e| inspect_error(e.to_string()))
+            }
+            _ => Err(inspect_error(error_message(response).await)),
+        }
+    }
+}
+
+/// Send the request to the daemon over a new connection, upgraded connections like `attach` keep working afterwards
+pub(crate) async fn send_request<B>(host: &DockerHost, request: Request<B>) -> Result<Response<Incoming>, DockermirError>
+    where B: hyper::body::Body + Send + 'static,
+          B::Data: Send,
+          B::Error: Into<Box<dyn std::error::Error + Send + Sync>> {
+    let daemon_error = |error: String| DockermirError::DockerDaemonError {
+        host: host.to_string(),
+        error,
+    };
+    let stream = host.connect().await.map_err(|e| daemon_error(e.to_string()))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await
+        .map_err(|e| daemon_error(e.to_string()))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.with_upgrades().await {
+            trace!("docker api connection closed, error: {}", e);
+        }
+    });
+    sender.send_request(request).await.map_err(|e| daemon_error(e.to_string()))
+}
+
+fn auth_config(registry: &str, credential: &RegistryCredential) -> Value {
+    // the daemon does not take `<token>` as a username, the token has a field of its own
+    if credential.is_identity_token() {
+        return json!({
+            "identitytoken": credential.secret,
+            "serveraddress": registry,
+        });
+    }
+    json!({
+        "username": credential.username,
+        "password": credential.secret,
+        "serveraddress": registry,
+    })
+}
+
+/// The `X-Registry-Auth` header of pulls and pushes, anonymous without a credential
+pub(crate) fn registry_auth(registry: &str, credential: Option<&RegistryCredential>) -> String {
+    match credential {
+        Some(credential) => URL_SAFE.encode(auth_config(registry, credential).to_string()),
+        None => ANONYMOUS_REGISTRY_AUTH.to_string(),
+    }
+}
+
+/// The status and the message of the daemon, e.g. `404 Not Found, No such image: nginx:latest`
+pub(crate) async fn error_message(response: Response<Incoming>) -> String {
+    let status = response.status();
+    let body = response.into_body().collect().await.map(|body| body.to_bytes().to_vec()).unwrap_or_default();
+    let message = serde_json::from_slice::<ErrorResponse>(&body)
+        .map(|error| error.message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(&body).trim().to_string());
+    format!("{}, {}", status, message)
+}
+
+/// Feed the progress stream of a pull or push into the progress, the daemon reports failures inside the stream
+async fn read_progress(response: Response<Incoming>, progress: &PullProgress) -> Result<(), String> {
+    let mut body = response.into_body();
+    let mut buffer = vec![];
+    let mut transferred = HashMap::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| e.to_string())?;
+        let Some(data) = frame.data_ref() else {
+            continue;
+        };
+        buffer.extend_from_slice(data);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            report_message(&line, progress, &mut transferred)?;
+        }
+    }
+    report_message(&buffer, progress, &mut transferred)
+}
+
+fn report_message(line: &[u8], progress: &PullProgress, transferred: &mut HashMap<String, u64>) -> Result<(), String> {
+    let line = line.trim_ascii();
+    if line.is_empty() {
+        return Ok(());
+    }
+    let message: JsonMessage = serde_json::from_slice(line)
+        .map_err(|e| format!("invalid progress message: {}, {}", String::from_utf8_lossy(line), e))?;
+    if let Some(error) = message.error {
+        return Err(error);
+    }
+    let status = message.status.unwrap_or_default();
+    match message.id.as_deref() {
+        Some(id) if is_layer_id(id) => {
+            let detail = message.progress_detail.unwrap_or(ProgressDetail { current: None, total: None });
+            match detail.current {
+                Some(current) if status == "Downloading" || status == "Pushing" => {
+                    progress.start_layer(id, detail.total);
+                    let previous = transferred.insert(id.to_string(), current).unwrap_or_default();
+                    progress.advance(id, current.saturating_sub(previous));
+                }
+                _ if is_layer_done(&status) => progress.finish_layer(id, &status),
+                _ => progress.set_status(id, &status),
+            }
+        }
+        Some(id) => progress.engine_output(&format!("{}: {}", id, status)),
+        None => progress.engine_output(message.stream.as_deref().unwrap_or(&status)),
+    }
+    Ok(())
+}
+
+fn is_layer_id(id: &str) -> bool {
+    id.len() >= 12 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_layer_done(status: &str) -> bool {
+    matches!(status, "Pull complete" | "Already exists" | "Pushed" | "Layer already exists") || status.starts_with("Mounted from")
+}
+
+/// Percent encode a query value, image names keep their `/`, `:` and `@`
+pub(crate) fn encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The decoded value of the query parameter, the docker cli encodes the `/` and `:` of image names
+pub(crate) fn query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode(value))
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        // the digits are read as bytes, the value may carry a multi-byte character right after the `%`
+        let escaped = (bytes[index] == b'%' && index + 2 < bytes.len())
+            .then(|| (bytes[index + 1] as char).to_digit(16).zip((bytes[index + 2] as char).to_digit(16)))
+            .flatten()
+            .map(|(high, low)| (high * 16 + low) as u8);
+        match (escaped, bytes[index]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
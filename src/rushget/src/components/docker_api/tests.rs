@@ -0,0 +1,191 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::credentials::{CredentialSource, IDENTITY_TOKEN_USERNAME};
+use crate::components::test_docker_daemon::{TestDockerDaemon, TEST_LAYER_ID};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+#[case("unix:///var/run/docker.sock", DockerHost::Unix(PathBuf::from("/var/run/docker.sock")))]
+#[case("tcp://127.0.0.1:2375", DockerHost::Tcp("127.0.0.1:2375".to_string()))]
+#[case("tcp://docker:2375/", DockerHost::Tcp("docker:2375".to_string()))]
+#[case("ssh://builder@build-host", DockerHost::Ssh { destination: "builder@build-host".to_string(), port: None })]
+#[case("ssh://builder@build-host:2222", DockerHost::Ssh { destination: "builder@build-host".to_string(), port: Some(2222) })]
+fn parse_docker_host(_init_logger: (), #[case] host: &str, #[case] expected: DockerHost) {
+    let parsed = DockerHost::parse(host).unwrap();
+    assert_eq!(parsed, expected);
+    assert_eq!(parsed.to_string(), host.trim_end_matches('/'));
+}
+
+#[rstest]
+#[case("npipe:////./pipe/docker_engine")]
+#[case("tcp://")]
+#[case("ssh://builder@build-host:ssh")]
+fn parse_invalid_docker_host(_init_logger: (), #[case] host: &str) {
+    assert!(matches!(DockerHost::parse(host), Err(DockermirError::DockerDaemonError { .. })));
+}
+
+fn input(source: &str, mirror: &str) -> DockermirPullInput {
+    DockermirPullInput::new(source.to_string(), mirror.to_string())
+}
+
+#[rstest]
+#[case("fromImage=mcr.microsoft.com%2Fdotnet%2Fsdk&tag=8.0", "fromImage", Some("mcr.microsoft.com/dotnet/sdk"))]
+#[case("fromImage=nginx&tag=sha256%3Aabc", "tag", Some("sha256:abc"))]
+#[case("fromImage=nginx%", "fromImage", Some("nginx%"))]
+#[case("fromImage=%é", "fromImage", Some("%é"))]
+#[case("fromImage=%2é", "fromImage", Some("%2é"))]
+#[case("fromImage=nginx", "tag", None)]
+fn query_values(_init_logger: (), #[case] query: &str, #[case] name: &str, #[case] expected: Option<&str>) {
+    assert_eq!(query_value(query, name).as_deref(), expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_tag_and_remove(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.add_remote("registry.example.com/newbe36524/sdk:8.0", "sha256:sdk");
+    let api = DockerApi::new(daemon.host());
+    let mirror = "registry.example.com/newbe36524/sdk:8.0";
+    let progress = PullProgress::with_tty("test", false);
+
+    api.pull(&input("mcr.microsoft.com/dotnet/sdk:8.0", mirror).with_platform(Some("linux/arm64".parse().unwrap())), &progress).await.unwrap();
+    assert_eq!(progress.layer_bytes(TEST_LAYER_ID), Some((100, Some(100))));
+    api.tag(&input("mcr.microsoft.com/dotnet/sdk:8.0", mirror)).await.unwrap();
+    api.rmi(mirror).await.unwrap();
+
+    assert_eq!(daemon.local_tags(), vec!["mcr.microsoft.com/dotnet/sdk:8.0"]);
+    let inspect = api.inspect("mcr.microsoft.com/dotnet/sdk:8.0").await.unwrap().unwrap();
+    assert_eq!(inspect.id, "sha256:sdk");
+    assert!(api.inspect(mirror).await.unwrap().is_none());
+    assert_eq!(daemon.requests()[..3], [
+        "POST /images/create?fromImage=registry.example.com/newbe36524/sdk&tag=8.0&platform=linux/arm64".to_string(),
+        format!("POST /images/{}/tag?repo=mcr.microsoft.com/dotnet/sdk&tag=8.0", mirror),
+        format!("DELETE /images/{}", mirror),
+    ]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_reports_daemon_errors(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    let api = DockerApi::new(daemon.host());
+    let progress = PullProgress::with_tty("test", false);
+
+    let result = api.pull(&input("nginx", "registry.example.com/library/nginx"), &progress).await;
+    match result {
+        Err(DockermirError::DockerPullError { error, .. }) => assert!(error.starts_with("404 Not Found, manifest for"), "{}", error),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // the daemon answers 200 and reports the failure inside the progress stream
+    daemon.add_remote("registry.example.com/library/nginx", "sha256:nginx");
+    daemon.fail_pulls("unexpected EOF");
+    let result = api.pull(&input("nginx", "registry.example.com/library/nginx"), &progress).await;
+    match result {
+        Err(DockermirError::DockerPullError { error, .. }) => assert_eq!(error, "unexpected EOF"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(daemon.local_tags().is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn tag_and_remove_missing_image(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    let api = DockerApi::new(daemon.host());
+
+    let result = api.tag(&input("nginx:ci", "nginx")).await;
+    assert!(matches!(result, Err(DockermirError::DockerTagError { ref error, .. }) if error.contains("No such image")), "{:?}", result);
+    let result = api.rmi("nginx").await;
+    assert!(matches!(result, Err(DockermirError::DockerRemoveImageError { ref error, .. }) if error.contains("No such image")), "{:?}", result);
+
+    daemon.add_local("nginx", "sha256:nginx");
+    api.tag(&input("nginx:ci", "nginx")).await.unwrap();
+    assert_eq!(daemon.local_tags(), vec!["docker.io/library/nginx:ci", "docker.io/library/nginx:latest"]);
+    let result = api.tag(&input(&format!("nginx@sha256:{}", "0".repeat(64)), "nginx")).await;
+    assert!(matches!(result, Err(DockermirError::DockerTagError { .. })), "{:?}", result);
+}
+
+#[rstest]
+#[tokio::test]
+async fn login_passes_credential_to_pulls(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.require_credential("robot", "secret");
+    daemon.add_remote("registry.example.com/team/app:1.0", "sha256:app");
+    let api = DockerApi::new(daemon.host());
+    let credential = |secret: &str| RegistryCredential {
+        username: "robot".to_string(),
+        secret: secret.to_string(),
+        source: CredentialSource::DockerConfig,
+    };
+
+    let result = api.login("registry.example.com", &credential("wrong")).await;
+    assert!(matches!(result, Err(DockermirError::DockerLoginError { ref error, .. }) if error.starts_with("401")), "{:?}", result);
+
+    api.login("registry.example.com", &credential("secret")).await.unwrap();
+    api.pull(&input("app", "registry.example.com/team/app:1.0"), &PullProgress::with_tty("test", false)).await.unwrap();
+    api.push("registry.example.com/team/app:1.0", &PullProgress::with_tty("test", false)).await.unwrap();
+
+    let auths = daemon.registry_auths();
+    assert_eq!(auths.len(), 2);
+    for auth in auths {
+        assert_eq!(auth["username"], "robot");
+        assert_eq!(auth["password"], "secret");
+        assert_eq!(auth["serveraddress"], "registry.example.com");
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn login_passes_identity_token_to_pulls(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.require_credential(IDENTITY_TOKEN_USERNAME, "refresh-token");
+    daemon.add_remote("registry.example.com/team/app:1.0", "sha256:app");
+    let api = DockerApi::new(daemon.host());
+    let credential = RegistryCredential {
+        username: IDENTITY_TOKEN_USERNAME.to_string(),
+        secret: "refresh-token".to_string(),
+        source: CredentialSource::DockerConfig,
+    };
+
+    api.login("registry.example.com", &credential).await.unwrap();
+    api.pull(&input("app", "registry.example.com/team/app:1.0"), &PullProgress::with_tty("test", false)).await.unwrap();
+
+    let auths = daemon.registry_auths();
+    assert_eq!(auths, vec![json!({ "identitytoken": "refresh-token", "serveraddress": "registry.example.com" })]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn daemon_unreachable(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let api = DockerApi::new(DockerHost::Unix(dir.path().join("docker.sock")));
+
+    let result = api.pull(&input("nginx", "registry.example.com/library/nginx"), &PullProgress::with_tty("test", false)).await;
+
+    // not a mirror failure, the next mirror would fail the same way
+    match result {
+        Err(e @ DockermirError::DockerDaemonError { .. }) => assert!(!e.is_mirror_failure()),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn build_unsupported(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    let api = DockerApi::new(daemon.host());
+
+    let result = api.build(&[".".to_string()]).await;
+
+    assert!(matches!(result, Err(DockermirError::EngineUnsupported { ref operation, .. }) if operation == "build"), "{:?}", result);
+    assert!(daemon.requests().is_empty());
+}
@@ -1,57 +1,213 @@
-
-
-
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use crate::components::container_engine::{ContainerEngine, ContainerEngineKind, ImageInspect};
+use crate::components::credentials::RegistryCredential;
+use crate::components::progress::PullProgress;
+use crate::components::registry::Platform;
 use crate::error::DockermirError;
 use anyhow::Result;
-use cmd_lib::run_cmd;
 
-pub(crate) struct DockerExec {}
+/// Run the engine through its command line, which is the same for docker, podman and nerdctl
+pub(crate) struct DockerExec {
+    kind: ContainerEngineKind,
+}
 
 impl DockerExec {
-    pub(crate) fn new() -> DockerExec {
-        DockerExec {}
+    pub(crate) fn new(kind: ContainerEngineKind) -> DockerExec {
+        DockerExec {
+            kind,
+        }
+    }
+
+    /// Run the command without blocking the runtime, the error contains the stderr of the engine
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>, String> {
+        trace!("run: {} {}", self.kind.program(), args.join(" "));
+        let output = Command::new(self.kind.program())
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| e.to_string())?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(format!("{}, {}", output.status, String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    /// Run the command with the stdout and stderr of rg, for commands whose output is meant for the user
+    async fn run_attached(&self, args: &[&str]) -> Result<(), String> {
+        trace!("run: {} {}", self.kind.program(), args.join(" "));
+        let status = Command::new(self.kind.program())
+            .args(args)
+            .status()
+            .await
+            .map_err(|e| e.to_string())?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(status.to_string())
+        }
+    }
+
+    /// Run the command and feed every line of stdout to the progress while it is running
+    async fn run_with_progress(&self, args: &[&str], progress: &PullProgress) -> Result<(), String> {
+        trace!("run: {} {}", self.kind.program(), args.join(" "));
+        let mut child = Command::new(self.kind.program())
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        // read stderr at the same time, the engine blocks once either pipe is full
+        let read_stdout = async {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                progress.engine_output(&line);
+            }
+        };
+        let read_stderr = async {
+            let mut content = String::new();
+            let _ = stderr.read_to_string(&mut content).await;
+            content
+        };
+        let (_, stderr) = tokio::join!(read_stdout, read_stderr);
+        let status = child.wait().await.map_err(|e| e.to_string())?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{}, {}", status, stderr.trim()))
+        }
     }
-    pub(crate) fn pull(&self, input: &DockermirPullInput) -> Result<(), DockermirError> {
-        let mirror_image = input.mirror_image.clone();
-        let result = run_cmd!(docker pull $mirror_image);
+}
+
+#[async_trait::async_trait]
+impl ContainerEngine for DockerExec {
+    fn kind(&self) -> ContainerEngineKind {
+        self.kind
+    }
+
+    async fn login(&self, registry: &str, credential: &RegistryCredential) -> Result<(), DockermirError> {
+        let login_error = |error: String| DockermirError::DockerLoginError {
+            registry: registry.to_owned(),
+            error,
+        };
+        trace!("run: {} login --username {} --password-stdin {}", self.kind.program(), credential.username, registry);
+        // the secret goes through stdin, so it never shows up in the process list
+        let mut child = Command::new(self.kind.program())
+            .args(["login", "--username", &credential.username, "--password-stdin", registry])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| login_error(e.to_string()))?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(credential.secret.as_bytes()).await.map_err(|e| login_error(e.to_string()))?;
+        drop(stdin);
+        let output = child.wait_with_output().await.map_err(|e| login_error(e.to_string()))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(login_error(format!("{}, {}", output.status, String::from_utf8_lossy(&output.stderr).trim())))
+        }
+    }
+
+    async fn pull(&self, input: &DockermirPullInput, progress: &PullProgress) -> Result<(), DockermirError> {
+        let platform = input.platform.as_ref().map(|platform| platform.to_string());
+        let mut args = vec!["pull"];
+        if let Some(platform) = &platform {
+            args.extend(["--platform", platform]);
+        }
+        args.push(&input.mirror_image);
+        let result = self.run_with_progress(&args, progress).await;
         match result {
             Ok(_) => Ok(()),
             Err(e) => Err(DockermirError::DockerPullError {
                 source_image: input.source_image.clone(),
                 mirror_image: input.mirror_image.clone(),
-                error: e.to_string(),
+                error: e,
             }),
         }
     }
-    pub(crate) fn tag(&self, input: &DockermirPullInput) -> Result<(), DockermirError> {
-        let source_image = input.source_image.clone();
-        let mirror_image = input.mirror_image.clone();
-        let result = run_cmd!(docker tag $mirror_image $source_image);
+
+    async fn tag(&self, input: &DockermirPullInput) -> Result<(), DockermirError> {
+        let result = self.run(&["tag", &input.mirror_image, &input.source_image]).await;
         match result {
             Ok(_) => Ok(()),
             Err(e) => Err(DockermirError::DockerTagError {
                 source_image: input.source_image.clone(),
                 mirror_image: input.mirror_image.clone(),
-                error: e.to_string(),
+                error: e,
             }),
         }
     }
 
-    pub(crate) fn rmi(&self, image: &str) -> Result<(), DockermirError> {
-        let result = run_cmd!(docker rmi $image);
+    async fn rmi(&self, image: &str) -> Result<(), DockermirError> {
+        let result = self.run(&["rmi", image]).await;
         match result {
             Ok(_) => Ok(()),
             Err(e) => Err(DockermirError::DockerRemoveImageError {
                 image: image.to_owned(),
-                error: e.to_string(),
+                error: e,
             }),
         }
     }
+
+    async fn push(&self, image: &str, progress: &PullProgress) -> Result<(), DockermirError> {
+        self.run_with_progress(&["push", image], progress).await
+            .map_err(|error| DockermirError::DockerPushError {
+                image: image.to_owned(),
+                error,
+            })
+    }
+
+    async fn build(&self, args: &[String]) -> Result<(), DockermirError> {
+        let mut build_args = vec!["build"];
+        build_args.extend(args.iter().map(|arg| arg.as_str()));
+        self.run_attached(&build_args).await
+            .map_err(|error| DockermirError::DockerBuildError { error })
+    }
+
+    async fn load(&self, archive: &Path, progress: &PullProgress) -> Result<(), DockermirError> {
+        let path = archive.display().to_string();
+        self.run_with_progress(&["load", "-i", &path], progress).await
+            .map_err(|error| DockermirError::DockerLoadError {
+                archive: path.to_owned(),
+                error,
+            })
+    }
+
+    async fn inspect(&self, image: &str) -> Result<Option<ImageInspect>, DockermirError> {
+        let inspect_error = |error: String| DockermirError::DockerInspectError {
+            image: image.to_owned(),
+            error,
+        };
+        let output = match self.run(&["image", "inspect", image]).await {
+            Ok(output) => output,
+            Err(e) => {
+                // docker says "No such image", podman says "image not known", nerdctl says "not found"
+                let lower = e.to_lowercase();
+                if lower.contains("no such image") || lower.contains("not known") || lower.contains("not found") {
+                    return Ok(None);
+                }
+                return Err(inspect_error(e));
+            }
+        };
+        let images: Vec<ImageInspect> = serde_json::from_slice(&output)
+            .map_err(|e| inspect_error(e.to_string()))?;
+        Ok(images.into_iter().next())
+    }
 }
 
 pub(crate) struct DockermirPullInput {
-    source_image: String,
-    mirror_image: String,
+    pub(crate) source_image: String,
+    pub(crate) mirror_image: String,
+    /// The platform to pull from a multi-arch image, the platform of the engine if not set
+    pub(crate) platform: Option<Platform>,
 }
 
 impl DockermirPullInput {
@@ -59,6 +215,12 @@ impl DockermirPullInput {
         DockermirPullInput {
             source_image,
             mirror_image,
+            platform: None,
         }
     }
-}
\ No newline at end of file
+
+    pub(crate) fn with_platform(mut self, platform: Option<Platform>) -> DockermirPullInput {
+        self.platform = platform;
+        self
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use crate::components::registry::{Descriptor, ImageIndex, MEDIA_TYPE_OCI_INDEX};
+use crate::error::DockermirError;
+
+pub(crate) const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+pub(crate) const ANNOTATION_IMAGE_NAME: &str = "io.containerd.image.name";
+
+const OCI_LAYOUT_CONTENT: &str = r#"{"imageLayoutVersion":"1.0.0"}"#;
+
+/// A directory in the OCI image layout format, see https://github.com/opencontainers/image-spec/blob/main/image-layout.md
+pub(crate) struct OciLayout {
+    root: PathBuf,
+}
+
+impl OciLayout {
+    pub(crate) fn open_or_create(root: &Path) -> Result<OciLayout, DockermirError> {
+        let layout = OciLayout {
+            root: root.to_path_buf(),
+        };
+        fs::create_dir_all(root.join("blobs").join("sha256")).map_err(|e| layout.error(root, e))?;
+        let oci_layout = root.join("oci-layout");
+        if !oci_layout.exists() {
+            fs::write(&oci_layout, OCI_LAYOUT_CONTENT).map_err(|e| layout.error(&oci_layout, e))?;
+        }
+        Ok(layout)
+    }
+
+    fn error(&self, path: &Path, e: impl ToString) -> DockermirError {
+        DockermirError::OciLayoutError {
+            path: path.display().to_string(),
+            error: e.to_string(),
+        }
+    }
+
+    pub(crate) fn blob_path(&self, digest: &str) -> Result<PathBuf, DockermirError> {
+        match digest.split_once(':') {
+            Some((algorithm, encoded)) if !encoded.is_empty() && !encoded.contains(['/', '\\', '.']) => {
+                Ok(self.root.join("blobs").join(algorithm).join(encoded))
+            }
+            _ => Err(self.error(&self.root, format!("invalid digest: {}", digest))),
+        }
+    }
+
+    pub(crate) fn contains_blob(&self, digest: &str) -> bool {
+        self.blob_path(digest).map(|path| path.exists()).unwrap_or(false)
+    }
+
+    pub(crate) fn write_blob(&self, digest: &str, content: &[u8]) -> Result<(), DockermirError> {
+        let path = self.blob_path(digest)?;
+        fs::write(&path, content).map_err(|e| self.error(&path, e))
+    }
+
+    pub(crate) fn read_index(&self) -> Result<ImageIndex, DockermirError> {
+        let path = self.root.join("index.json");
+        if !path.exists() {
+            return Ok(ImageIndex {
+                schema_version: 2,
+                media_type: Some(MEDIA_TYPE_OCI_INDEX.to_string()),
+                manifests: vec![],
+            });
+        }
+        let content = fs::read(&path).map_err(|e| self.error(&path, e))?;
+        serde_json::from_slice(&content).map_err(|e| self.error(&path, e))
+    }
+
+    /// Path to download a blob to before it is verified, unique so that concurrent pulls of a shared layer do not collide
+    pub(crate) fn partial_blob_path(&self, digest: &str) -> Result<PathBuf, DockermirError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = self.blob_path(digest)?;
+        let suffix = format!("{}.{}.partial", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        Ok(path.with_extension(suffix))
+    }
+
+    /// Add the manifest to index.json under the image name, replacing the entry previously stored with that name
+    pub(crate) fn add_manifest(&self, descriptor: &Descriptor, image_name: &str, ref_name: &str) -> Result<(), DockermirError> {
+        // images pulled concurrently into the same layout all update index.json
+        static INDEX_LOCK: Mutex<()> = Mutex::new(());
+        let _guard = INDEX_LOCK.lock().unwrap();
+        let mut index = self.read_index()?;
+        index.manifests.retain(|existing| {
+            existing.annotations.as_ref().and_then(|a| a.get(ANNOTATION_IMAGE_NAME)).map(|name| name.as_str()) != Some(image_name)
+        });
+        let mut descriptor = descriptor.clone();
+        let mut annotations = descriptor.annotations.unwrap_or_default();
+        annotations.insert(ANNOTATION_IMAGE_NAME.to_string(), image_name.to_string());
+        annotations.insert(ANNOTATION_REF_NAME.to_string(), ref_name.to_string());
+        descriptor.annotations = Some(annotations);
+        index.manifests.push(descriptor);
+
+        let path = self.root.join("index.json");
+        let content = serde_json::to_vec_pretty(&index).map_err(|e| self.error(&path, e))?;
+        fs::write(&path, content).map_err(|e| self.error(&path, e))
+    }
+}
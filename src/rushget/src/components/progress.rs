@@ -0,0 +1,241 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// How often a layer reports its bytes when stdout is not a terminal
+const PLAIN_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// All pulls share one set of bars, so the pulls of a batch are drawn below each other
+fn multi_progress() -> &'static MultiProgress {
+    static MULTI: OnceLock<MultiProgress> = OnceLock::new();
+    MULTI.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout()))
+}
+
+/// Logger which hides the bars while writing a record, so log lines do not tear the bars apart
+struct ProgressLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            multi_progress().suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub(crate) fn init_logger(level: LevelFilter) {
+    let inner = env_logger::builder().filter_level(level).build();
+    let max_level = inner.filter();
+    if log::set_boxed_logger(Box::new(ProgressLogger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+struct LayerProgress {
+    bar: Option<ProgressBar>,
+    current: u64,
+    total: Option<u64>,
+    started: Instant,
+    reported: Instant,
+}
+
+pub(crate) struct PullProgress {
+    image: String,
+    tty: bool,
+    layers: Mutex<HashMap<String, LayerProgress>>,
+}
+
+impl PullProgress {
+    pub(crate) fn new(image: &str) -> PullProgress {
+        Self::with_tty(image, std::io::stdout().is_terminal())
+    }
+
+    pub(crate) fn with_tty(image: &str, tty: bool) -> PullProgress {
+        PullProgress {
+            image: image.to_string(),
+            tty,
+            layers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn start_layer(&self, id: &str, total: Option<u64>) {
+        let id = short_id(id);
+        let mut layers = self.layers.lock().unwrap();
+        if let Some(layer) = layers.get_mut(id) {
+            if let (Some(bar), Some(total)) = (&layer.bar, total) {
+                bar.set_length(total);
+                bar.set_style(bytes_style());
+            }
+            layer.total = total.or(layer.total);
+            return;
+        }
+        let bar = self.tty.then(|| {
+            let bar = match total {
+                Some(total) => ProgressBar::new(total).with_style(bytes_style()),
+                None => {
+                    let bar = ProgressBar::new_spinner().with_style(status_style());
+                    bar.enable_steady_tick(Duration::from_millis(120));
+                    bar
+                }
+            };
+            multi_progress().add(bar.with_prefix(id.to_string()))
+        });
+        let now = Instant::now();
+        layers.insert(id.to_string(), LayerProgress {
+            bar,
+            current: 0,
+            total,
+            started: now,
+            reported: now,
+        });
+    }
+
+    pub(crate) fn advance(&self, id: &str, bytes: u64) {
+        let id = short_id(id);
+        let mut layers = self.layers.lock().unwrap();
+        let Some(layer) = layers.get_mut(id) else {
+            return;
+        };
+        layer.current += bytes;
+        match &layer.bar {
+            Some(bar) => bar.inc(bytes),
+            None if layer.reported.elapsed() >= PLAIN_REPORT_INTERVAL => {
+                layer.reported = Instant::now();
+                info!("{} {}: {}", self.image, id, plain_bytes(layer));
+            }
+            None => {}
+        }
+    }
+
+    pub(crate) fn set_status(&self, id: &str, status: &str) {
+        self.start_layer(id, None);
+        let id = short_id(id);
+        let layers = self.layers.lock().unwrap();
+        match layers.get(id).and_then(|layer| layer.bar.as_ref()) {
+            Some(bar) => bar.set_message(status.to_string()),
+            None => info!("{} {}: {}", self.image, id, status),
+        }
+    }
+
+    pub(crate) fn finish_layer(&self, id: &str, status: &str) {
+        self.start_layer(id, None);
+        let id = short_id(id);
+        let mut layers = self.layers.lock().unwrap();
+        let Some(layer) = layers.get_mut(id) else {
+            return;
+        };
+        match &layer.bar {
+            Some(bar) => {
+                if bar.length().is_none() {
+                    bar.set_style(status_style());
+                }
+                bar.finish_with_message(status.to_string());
+            }
+            None if layer.current > 0 => info!("{} {}: {}, {} in {}", self.image, id, status,
+                HumanBytes(layer.current), HumanDuration(layer.started.elapsed())),
+            None => info!("{} {}: {}", self.image, id, status),
+        }
+    }
+
+    pub(crate) fn engine_output(&self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        match parse_engine_line(line) {
+            Some(EngineLine::Status(id, status)) => self.set_status(id, status),
+            Some(EngineLine::Done(id, status)) => self.finish_layer(id, status),
+            None if self.tty => {
+                let _ = multi_progress().println(line);
+            }
+            None => info!("{}", line),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn layer_bytes(&self, id: &str) -> Option<(u64, Option<u64>)> {
+        self.layers.lock().unwrap().get(short_id(id)).map(|layer| (layer.current, layer.total))
+    }
+}
+
+impl Drop for PullProgress {
+    fn drop(&mut self) {
+        for layer in self.layers.get_mut().unwrap().values() {
+            if let Some(bar) = &layer.bar {
+                bar.finish_and_clear();
+                multi_progress().remove(bar);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum EngineLine<'a> {
+    Status(&'a str, &'a str),
+    Done(&'a str, &'a str),
+}
+
+/// Docker and nerdctl print `<id>: <status>`, podman prints `Copying blob <id> <status>`
+fn parse_engine_line(line: &str) -> Option<EngineLine<'_>> {
+    if let Some(rest) = line.strip_prefix("Copying blob ").or_else(|| line.strip_prefix("Copying config ")) {
+        let (id, status) = rest.split_once(' ').unwrap_or((rest, ""));
+        let status = status.trim();
+        return Some(if status.starts_with("done") || status.starts_with("skipped") {
+            EngineLine::Done(id, status)
+        } else {
+            EngineLine::Status(id, if status.is_empty() { "Copying" } else { status })
+        });
+    }
+    let (id, status) = line.split_once(": ")?;
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    if id.len() < 12 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(match status {
+        "Pull complete" | "Already exists" | "done" => EngineLine::Done(id, status),
+        _ => EngineLine::Status(id, status),
+    })
+}
+
+/// Layers are known by the first 12 hex digits of their digest, like docker shows them
+fn short_id(id: &str) -> &str {
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    &id[..id.len().min(12)]
+}
+
+fn plain_bytes(layer: &LayerProgress) -> String {
+    let elapsed = layer.started.elapsed().as_secs_f64();
+    let speed = if elapsed > 0.0 { (layer.current as f64 / elapsed) as u64 } else { 0 };
+    match layer.total {
+        Some(total) => {
+            let eta = Duration::from_secs(total.saturating_sub(layer.current).checked_div(speed).unwrap_or_default());
+            format!("{} / {} ({}/s, ETA {})", HumanBytes(layer.current), HumanBytes(total), HumanBytes(speed), HumanDuration(eta))
+        }
+        None => format!("{} ({}/s)", HumanBytes(layer.current), HumanBytes(speed)),
+    }
+}
+
+fn bytes_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix} [{bar:30.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {msg}")
+        .unwrap()
+        .progress_chars("=> ")
+}
+
+fn status_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix} {spinner} {msg} {elapsed}").unwrap()
+}
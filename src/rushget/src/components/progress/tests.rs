@@ -0,0 +1,49 @@
+use log::LevelFilter;
+use rstest::*;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+#[case("a2abf6c4d29d: Pulling fs layer", Some(EngineLine::Status("a2abf6c4d29d", "Pulling fs layer")))]
+#[case("a2abf6c4d29d: Downloading", Some(EngineLine::Status("a2abf6c4d29d", "Downloading")))]
+#[case("a2abf6c4d29d: Pull complete", Some(EngineLine::Done("a2abf6c4d29d", "Pull complete")))]
+#[case("a2abf6c4d29d: Already exists", Some(EngineLine::Done("a2abf6c4d29d", "Already exists")))]
+#[case("Copying blob sha256:a2abf6c4d29d43a4bf9fbb769f524d0fb36a2edab49819c1bf3e76f409f953ea", Some(EngineLine::Status("sha256:a2abf6c4d29d43a4bf9fbb769f524d0fb36a2edab49819c1bf3e76f409f953ea", "Copying")))]
+#[case("Copying blob a2abf6c4d29d done", Some(EngineLine::Done("a2abf6c4d29d", "done")))]
+#[case("Copying blob a2abf6c4d29d skipped: already exists", Some(EngineLine::Done("a2abf6c4d29d", "skipped: already exists")))]
+#[case("6.0: Pulling from newbe36524/sdk", None)]
+#[case("Digest: sha256:a2abf6c4d29d43a4bf9fbb769f524d0fb36a2edab49819c1bf3e76f409f953ea", None)]
+#[case("Status: Downloaded newer image for newbe36524/sdk:6.0", None)]
+fn parse_engine_lines(_init_logger: (), #[case]line: &str, #[case]expected: Option<EngineLine>) {
+    assert_eq!(parse_engine_line(line), expected);
+}
+
+#[rstest]
+fn engine_output_tracks_layers(_init_logger: ()) {
+    let progress = PullProgress::with_tty("newbe36524/sdk:6.0", false);
+    for line in ["6.0: Pulling from newbe36524/sdk", "a2abf6c4d29d: Pulling fs layer", "a2abf6c4d29d: Pull complete",
+        "Copying blob sha256:b3bb4c3a1fd1e1b5da2b56b6b0e1e4a0e0d0b7b3a0e93b0b7ba59c1fa7cf0b5e done"] {
+        progress.engine_output(line);
+    }
+    assert_eq!(progress.layer_bytes("a2abf6c4d29d"), Some((0, None)));
+    assert_eq!(progress.layer_bytes("sha256:b3bb4c3a1fd1"), Some((0, None)));
+    assert_eq!(progress.layers.lock().unwrap().len(), 2);
+}
+
+#[rstest]
+fn advance_counts_bytes(_init_logger: ()) {
+    let progress = PullProgress::with_tty("newbe36524/sdk:6.0", false);
+    let digest = "sha256:a2abf6c4d29d43a4bf9fbb769f524d0fb36a2edab49819c1bf3e76f409f953ea";
+    progress.start_layer(digest, Some(100));
+    progress.advance(digest, 40);
+    progress.advance(digest, 60);
+    progress.finish_layer(digest, "Download complete");
+    assert_eq!(progress.layer_bytes(digest), Some((100, Some(100))));
+}
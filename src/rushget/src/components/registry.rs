@@ -0,0 +1,631 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, LINK, LOCATION, WWW_AUTHENTICATE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use crate::components::credentials::RegistryCredential;
+use crate::components::oci_layout::OciLayout;
+use crate::components::progress::PullProgress;
+use crate::docker::reference::{ImageReference, DOCKER_HUB_REGISTRY};
+use crate::error::DockermirError;
+
+pub(crate) const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub(crate) const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub(crate) const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+pub(crate) const MEDIA_TYPE_DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+const DOCKER_HUB_ENDPOINT: &str = "registry-1.docker.io";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Platform {
+    pub(crate) os: String,
+    pub(crate) architecture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) variant: Option<String>,
+}
+
+impl Platform {
+    /// The platform of the current machine, as container runtimes would pick it by default
+    pub(crate) fn host() -> Platform {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            "x86" => "386",
+            "arm" => "arm",
+            other => other,
+        };
+        let os = match std::env::consts::OS {
+            "windows" => "windows",
+            _ => "linux",
+        };
+        Platform {
+            os: os.to_string(),
+            architecture: architecture.to_string(),
+            variant: None,
+        }
+    }
+
+    pub(crate) fn matches(&self, other: &Platform) -> bool {
+        // arm64 images rarely carry their only variant, `linux/arm64/v8` still has to find them
+        let default_variant = self.architecture == "arm64" && self.variant.as_deref() == Some("v8") && other.variant.is_none();
+        self.os == other.os
+            && self.architecture == other.architecture
+            && (self.variant.is_none() || self.variant == other.variant || default_variant)
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    /// `os/arch[/variant]` as in `docker pull --platform`, e.g. `linux/arm64/v8`
+    fn from_str(platform: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = platform.trim().split('/').collect();
+        match parts.as_slice() {
+            [os, architecture] | [os, architecture, _] if !os.is_empty() && !architecture.is_empty() => Ok(Platform {
+                os: os.to_lowercase(),
+                architecture: architecture.to_lowercase(),
+                variant: parts.get(2).filter(|variant| !variant.is_empty()).map(|variant| variant.to_lowercase()),
+            }),
+            _ => Err(format!("invalid platform: {}, expected os/arch[/variant]", platform)),
+        }
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.variant {
+            Some(variant) => write!(f, "{}/{}/{}", self.os, self.architecture, variant),
+            None => write!(f, "{}/{}", self.os, self.architecture),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Descriptor {
+    pub(crate) media_type: String,
+    pub(crate) digest: String,
+    pub(crate) size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) platform: Option<Platform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) annotations: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageManifest {
+    pub(crate) schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) media_type: Option<String>,
+    pub(crate) config: Descriptor,
+    pub(crate) layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageIndex {
+    pub(crate) schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) media_type: Option<String>,
+    pub(crate) manifests: Vec<Descriptor>,
+}
+
+/// The raw manifest returned by the registry, kept as bytes so that the digest stays stable
+#[derive(Debug, Clone)]
+pub(crate) struct FetchedManifest {
+    pub(crate) media_type: String,
+    pub(crate) digest: String,
+    pub(crate) content: Vec<u8>,
+}
+
+impl FetchedManifest {
+    pub(crate) fn is_index(&self) -> bool {
+        self.media_type == MEDIA_TYPE_OCI_INDEX || self.media_type == MEDIA_TYPE_DOCKER_MANIFEST_LIST
+    }
+
+    pub(crate) fn descriptor(&self) -> Descriptor {
+        Descriptor {
+            media_type: self.media_type.clone(),
+            digest: self.digest.clone(),
+            size: self.content.len() as u64,
+            platform: None,
+            annotations: None,
+        }
+    }
+
+    pub(crate) fn as_index(&self) -> Result<ImageIndex, DockermirError> {
+        serde_json::from_slice(&self.content).map_err(|e| DockermirError::InvalidManifest {
+            image: self.digest.clone(),
+            error: e.to_string(),
+        })
+    }
+
+    pub(crate) fn as_image(&self) -> Result<ImageManifest, DockermirError> {
+        serde_json::from_slice(&self.content).map_err(|e| DockermirError::InvalidManifest {
+            image: self.digest.clone(),
+            error: e.to_string(),
+        })
+    }
+}
+
+/// The registry and the username and secret of the credential a client was created with
+pub(crate) type ClientKey = (String, Option<(String, String)>);
+
+pub(crate) fn client_key(registry: &str, credential: Option<&RegistryCredential>) -> ClientKey {
+    (registry.to_string(), credential.map(|credential| (credential.username.clone(), credential.secret.clone())))
+}
+
+pub(crate) fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+/// Client of the OCI distribution API, so images can be fetched without a docker daemon
+pub(crate) struct RegistryClient {
+    client: Client,
+    /// The authorization header of each registry and repository, once the challenge is answered
+    authorizations: Mutex<HashMap<String, String>>,
+    credentials: HashMap<String, RegistryCredential>,
+}
+
+impl RegistryClient {
+    pub(crate) fn new() -> RegistryClient {
+        RegistryClient {
+            client: Client::new(),
+            authorizations: Mutex::new(HashMap::new()),
+            credentials: HashMap::new(),
+        }
+    }
+
+    /// Authenticate to the registry with the credential, anonymous access is used otherwise
+    pub(crate) fn with_credential(mut self, registry: &str, credential: Option<RegistryCredential>) -> RegistryClient {
+        if let Some(credential) = credential {
+            self.credentials.insert(registry.to_string(), credential);
+        }
+        self
+    }
+
+    fn base_url(registry: &str) -> String {
+        let host = if registry == DOCKER_HUB_REGISTRY { DOCKER_HUB_ENDPOINT } else { registry };
+        // registries on the local machine are served over plain http, just like docker treats them as insecure
+        let scheme = if host.starts_with("localhost") || host.starts_with("127.0.0.1") || host.starts_with("[::1]") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{}://{}/v2", scheme, host)
+    }
+
+    fn manifest_url(reference: &ImageReference) -> String {
+        format!("{}/{}/manifests/{}", Self::base_url(&reference.registry), reference.repository, reference.reference())
+    }
+
+    fn blob_url(reference: &ImageReference, digest: &str) -> String {
+        format!("{}/{}/blobs/{}", Self::base_url(&reference.registry), reference.repository, digest)
+    }
+
+    /// Send the request, answering a bearer token or basic challenge from the registry when there is one
+    async fn send<F>(&self, reference: &ImageReference, url: &str, build: F) -> Result<Response, DockermirError>
+        where F: Fn(&Client) -> RequestBuilder {
+        self.try_send(reference, url, |client| Ok(build(client))).await
+    }
+
+    /// Like `send`, for requests whose body can fail to be built
+    async fn try_send<F>(&self, reference: &ImageReference, url: &str, build: F) -> Result<Response, DockermirError>
+        where F: Fn(&Client) -> Result<RequestBuilder, DockermirError> {
+        let authorization_key = format!("{}/{}", reference.registry, reference.repository);
+        let cached_authorization = self.authorizations.lock().unwrap().get(&authorization_key).cloned();
+        let mut request = build(&self.client)?;
+        if let Some(authorization) = cached_authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let response = request.send().await.map_err(|e| DockermirError::RegistryRequestError {
+            url: url.to_string(),
+            error: e.to_string(),
+        })?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response.headers().get(WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(AuthChallenge::parse)
+            .ok_or_else(|| DockermirError::RegistryUnauthorized { url: url.to_string() })?;
+        let credential = self.credentials.get(&reference.registry);
+        let authorization = if challenge.scheme.eq_ignore_ascii_case("bearer") {
+            trace!("Registry requires bearer token for url: {}, challenge: {:?}", url, challenge);
+            format!("Bearer {}", self.fetch_token(reference, &challenge, credential).await?)
+        } else if challenge.scheme.eq_ignore_ascii_case("basic") {
+            let credential = credential.ok_or_else(|| DockermirError::RegistryUnauthorized { url: url.to_string() })?;
+            trace!("Registry requires basic auth for url: {}", url);
+            format!("Basic {}", STANDARD.encode(format!("{}:{}", credential.username, credential.secret)))
+        } else {
+            return Err(DockermirError::RegistryUnauthorized { url: url.to_string() });
+        };
+        self.authorizations.lock().unwrap().insert(authorization_key, authorization.clone());
+        let response = build(&self.client)?.header(AUTHORIZATION, authorization).send().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url: url.to_string(),
+                error: e.to_string(),
+            })?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(DockermirError::RegistryUnauthorized { url: url.to_string() });
+        }
+        Ok(response)
+    }
+
+    /// Get a token from the realm of the challenge, with the credential if there is one and anonymously otherwise
+    async fn fetch_token(&self, reference: &ImageReference, challenge: &AuthChallenge, credential: Option<&RegistryCredential>)
+                         -> Result<String, DockermirError> {
+        let realm = challenge.params.get("realm")
+            .ok_or_else(|| DockermirError::RegistryUnauthorized { url: reference.to_string() })?;
+        let scope = challenge.params.get("scope").cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", reference.repository));
+        let mut query = vec![("scope", scope)];
+        if let Some(service) = challenge.params.get("service") {
+            query.push(("service", service.clone()));
+        }
+        let request = match credential {
+            // identity tokens are exchanged through the OAuth2 refresh token grant
+            Some(credential) if credential.is_identity_token() => {
+                let mut form = query.clone();
+                form.extend([
+                    ("grant_type", "refresh_token".to_string()),
+                    ("refresh_token", credential.secret.clone()),
+                    ("client_id", "rushget".to_string()),
+                ]);
+                self.client.post(realm).form(&form)
+            }
+            Some(credential) => self.client.get(realm).query(&query).basic_auth(&credential.username, Some(&credential.secret)),
+            None => self.client.get(realm).query(&query),
+        };
+        let response = request.send().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url: realm.to_string(),
+                error: e.to_string(),
+            })?;
+        if !response.status().is_success() {
+            return Err(DockermirError::RegistryUnauthorized { url: realm.to_string() });
+        }
+        let token: TokenResponse = response.json().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url: realm.to_string(),
+                error: e.to_string(),
+            })?;
+        token.token.or(token.access_token)
+            .ok_or_else(|| DockermirError::RegistryUnauthorized { url: realm.to_string() })
+    }
+
+    fn check_status(url: &str, response: &Response) -> Result<(), DockermirError> {
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status == StatusCode::NOT_FOUND {
+            Err(DockermirError::RegistryNotFound { url: url.to_string() })
+        } else {
+            Err(DockermirError::RegistryUnexpectedStatus {
+                url: url.to_string(),
+                status: status.as_u16(),
+            })
+        }
+    }
+
+    fn manifest_accept() -> String {
+        [
+            MEDIA_TYPE_OCI_INDEX,
+            MEDIA_TYPE_OCI_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+        ].join(", ")
+    }
+
+    /// The digest of the manifest asked with HEAD, which registries like docker hub do not count as a pull,
+    /// None if the registry does not send `Docker-Content-Digest`
+    pub(crate) async fn fetch_manifest_digest(&self, reference: &ImageReference) -> Result<Option<String>, DockermirError> {
+        let url = Self::manifest_url(reference);
+        trace!("Checking manifest: {}", url);
+        let accept = Self::manifest_accept();
+        let response = self.send(reference, &url, |client| client.head(&url).header(ACCEPT, &accept)).await?;
+        Self::check_status(&url, &response)?;
+        Ok(response.headers().get("Docker-Content-Digest")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string()))
+    }
+
+    /// The platform in the config blob of the image, for images which are not part of an index
+    pub(crate) async fn fetch_image_platform(&self, reference: &ImageReference, image: &ImageManifest) -> Result<Platform, DockermirError> {
+        let url = Self::blob_url(reference, &image.config.digest);
+        let response = self.open_blob(reference, &image.config.digest, false).await?;
+        response.json().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url,
+                error: e.to_string(),
+            })
+    }
+
+    pub(crate) async fn fetch_manifest(&self, reference: &ImageReference) -> Result<FetchedManifest, DockermirError> {
+        let url = Self::manifest_url(reference);
+        trace!("Fetching manifest: {}", url);
+        let accept = Self::manifest_accept();
+        let response = self.send(reference, &url, |client| client.get(&url).header(ACCEPT, &accept)).await?;
+        Self::check_status(&url, &response)?;
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or(value).trim().to_string());
+        let content = response.bytes().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url: url.clone(),
+                error: e.to_string(),
+            })?
+            .to_vec();
+        let digest = sha256_digest(&content);
+        if let Some(expected) = reference.digest.as_ref().filter(|expected| **expected != digest) {
+            return Err(DockermirError::ImageDigestMismatch {
+                image: reference.to_string(),
+                expected: expected.clone(),
+                actual: digest,
+            });
+        }
+        let media_type = match content_type {
+            Some(media_type) if media_type != "application/json" && !media_type.is_empty() => media_type,
+            // some registries do not send a useful content type, fall back to the mediaType in the body
+            _ => serde_json::from_slice::<serde_json::Value>(&content).ok()
+                .and_then(|value| value.get("mediaType").and_then(|v| v.as_str()).map(|v| v.to_string()))
+                .unwrap_or_else(|| MEDIA_TYPE_OCI_MANIFEST.to_string()),
+        };
+        Ok(FetchedManifest {
+            media_type,
+            digest,
+            content,
+        })
+    }
+
+    /// Fetch the image manifest, picking the entry for the platform when the reference points to an index
+    pub(crate) async fn fetch_image_manifest(&self, reference: &ImageReference, platform: &Platform)
+                                             -> Result<(FetchedManifest, ImageManifest), DockermirError> {
+        let manifest = self.fetch_manifest(reference).await?;
+        let manifest = if manifest.is_index() {
+            let index = manifest.as_index()?;
+            let selected = index.manifests.iter()
+                .find(|descriptor| descriptor.platform.as_ref().map(|p| platform.matches(p)).unwrap_or(false))
+                .ok_or_else(|| DockermirError::PlatformNotFound {
+                    image: reference.to_string(),
+                    platform: platform.to_string(),
+                })?;
+            trace!("Selected manifest {} for platform {} from index {}", selected.digest, platform, manifest.digest);
+            self.fetch_manifest(&reference.with_digest(&selected.digest)).await?
+        } else {
+            manifest
+        };
+        let image = manifest.as_image()?;
+        Ok((manifest, image))
+    }
+
+    /// Request the blob and hand out the response as it is, so that the caller can stream it somewhere else
+    pub(crate) async fn open_blob(&self, reference: &ImageReference, digest: &str, head: bool) -> Result<Response, DockermirError> {
+        let url = Self::blob_url(reference, digest);
+        trace!("Opening blob: {}", url);
+        let response = self.send(reference, &url, |client| if head { client.head(&url) } else { client.get(&url) }).await?;
+        Self::check_status(&url, &response)?;
+        Ok(response)
+    }
+
+    /// Download the blob into the target file through the partial file, verifying its digest before the target shows up
+    pub(crate) async fn download_blob(&self, reference: &ImageReference, descriptor: &Descriptor, partial: &Path, target: &Path,
+                                      progress: &PullProgress) -> Result<(), DockermirError> {
+        let url = Self::blob_url(reference, &descriptor.digest);
+        trace!("Downloading blob: {}", url);
+        let mut response = self.send(reference, &url, |client| client.get(&url)).await?;
+        Self::check_status(&url, &response)?;
+
+        let layout_error = |e: std::io::Error| DockermirError::OciLayoutError {
+            path: target.display().to_string(),
+            error: e.to_string(),
+        };
+        let mut file = tokio::fs::File::create(partial).await.map_err(layout_error)?;
+        progress.start_layer(&descriptor.digest, Some(descriptor.size));
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = response.chunk().await
+            .map_err(|e| DockermirError::RegistryRequestError {
+                url: url.clone(),
+                error: e.to_string(),
+            })? {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(layout_error)?;
+            progress.advance(&descriptor.digest, chunk.len() as u64);
+        }
+        file.flush().await.map_err(layout_error)?;
+        drop(file);
+
+        let actual = format!("sha256:{:x}", hasher.finalize());
+        if actual != descriptor.digest {
+            let _ = tokio::fs::remove_file(partial).await;
+            return Err(DockermirError::BlobDigestMismatch {
+                expected: descriptor.digest.clone(),
+                actual,
+            });
+        }
+        tokio::fs::rename(partial, target).await.map_err(layout_error)?;
+        progress.finish_layer(&descriptor.digest, "Download complete");
+        Ok(())
+    }
+
+    /// Pull the image into the OCI image layout, returns the descriptor of the image manifest
+    pub(crate) async fn pull_into_layout(&self, reference: &ImageReference, platform: &Platform, layout: &OciLayout,
+                                         progress: &PullProgress) -> Result<Descriptor, DockermirError> {
+        let (manifest, image) = self.fetch_image_manifest(reference, platform).await?;
+        for descriptor in std::iter::once(&image.config).chain(image.layers.iter()) {
+            if layout.contains_blob(&descriptor.digest) {
+                trace!("Blob {} already exists in layout, skip", descriptor.digest);
+                progress.finish_layer(&descriptor.digest, "Already exists");
+                continue;
+            }
+            let target = layout.blob_path(&descriptor.digest)?;
+            let partial = layout.partial_blob_path(&descriptor.digest)?;
+            self.download_blob(reference, descriptor, &partial, &target, progress).await?;
+        }
+        layout.write_blob(&manifest.digest, &manifest.content)?;
+        let mut descriptor = manifest.descriptor();
+        descriptor.platform = Some(platform.clone());
+        Ok(descriptor)
+    }
+
+    /// Whether the repository has the blob, a registry answering not found for HEAD does not have it
+    pub(crate) async fn blob_exists(&self, reference: &ImageReference, digest: &str) -> Result<bool, DockermirError> {
+        match self.open_blob(reference, digest, true).await {
+            Ok(_) => Ok(true),
+            Err(DockermirError::RegistryNotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Upload the blob in the file with a monolithic upload, the upload session is started first so the push token is in place
+    pub(crate) async fn upload_blob(&self, reference: &ImageReference, digest: &str, file: &Path) -> Result<(), DockermirError> {
+        let url = format!("{}/{}/blobs/uploads/", Self::base_url(&reference.registry), reference.repository);
+        // opened before the upload session is started, so a missing file does not leave the session behind
+        let staged = std::fs::File::open(file)
+            .and_then(|staged| staged.metadata().map(|metadata| (staged, metadata.len())));
+        let (staged, size) = staged.map_err(|e| DockermirError::RegistryRequestError {
+            url: url.clone(),
+            error: format!("failed to open the blob file {}: {}", file.display(), e),
+        })?;
+        trace!("Starting blob upload: {}", url);
+        let response = self.send(reference, &url, |client| client.post(&url).header(CONTENT_LENGTH, 0)).await?;
+        Self::check_status(&url, &response)?;
+        let location = response.headers().get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| DockermirError::RegistryRequestError {
+                url: url.clone(),
+                error: "upload location is missing".to_string(),
+            })?;
+        let location = Self::absolute_url(&reference.registry, location);
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let upload_url = format!("{}{}digest={}", location, separator, digest);
+        trace!("Uploading blob: {}", upload_url);
+        let response = self.try_send(reference, &upload_url, |client| {
+            // a retried request reads the file again from the start
+            let body = staged.try_clone()
+                .and_then(|mut body| body.seek(SeekFrom::Start(0)).map(|_| body))
+                .map_err(|e| DockermirError::RegistryRequestError {
+                    url: upload_url.clone(),
+                    error: format!("failed to read the blob file {}: {}", file.display(), e),
+                })?;
+            Ok(client.put(&upload_url)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(CONTENT_LENGTH, size)
+                .body(Body::from(tokio::fs::File::from_std(body))))
+        }).await?;
+        Self::check_status(&upload_url, &response)
+    }
+
+    /// Push the manifest as it is, so its digest stays the same in the target repository
+    pub(crate) async fn push_manifest(&self, reference: &ImageReference, manifest: &FetchedManifest) -> Result<(), DockermirError> {
+        let url = Self::manifest_url(reference);
+        trace!("Pushing manifest: {}", url);
+        let response = self.send(reference, &url, |client| {
+            client.put(&url)
+                .header(CONTENT_TYPE, &manifest.media_type)
+                .body(manifest.content.clone())
+        }).await?;
+        Self::check_status(&url, &response)
+    }
+
+    /// All tags of the repository, following the `Link` header of paginated answers
+    pub(crate) async fn list_tags(&self, reference: &ImageReference) -> Result<Vec<String>, DockermirError> {
+        let mut url = format!("{}/{}/tags/list", Self::base_url(&reference.registry), reference.repository);
+        let mut tags = vec![];
+        loop {
+            trace!("Listing tags: {}", url);
+            let response = self.send(reference, &url, |client| client.get(&url)).await?;
+            Self::check_status(&url, &response)?;
+            let next = response.headers().get(LINK)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|link| link.split_once('<'))
+                .and_then(|(_, rest)| rest.split_once('>'))
+                .map(|(next, _)| Self::absolute_url(&reference.registry, next));
+            let list: TagList = response.json().await
+                .map_err(|e| DockermirError::RegistryRequestError {
+                    url: url.clone(),
+                    error: e.to_string(),
+                })?;
+            tags.extend(list.tags.unwrap_or_default());
+            match next {
+                Some(next) => url = next,
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    /// Registries answer with locations relative to their host
+    fn absolute_url(registry: &str, location: &str) -> String {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return location.to_string();
+        }
+        let base = Self::base_url(registry);
+        format!("{}{}", base.strip_suffix("/v2").unwrap_or(&base), location)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TagList {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Parsed `WWW-Authenticate` header, e.g. `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`
+#[derive(Debug)]
+struct AuthChallenge {
+    scheme: String,
+    params: HashMap<String, String>,
+}
+
+impl AuthChallenge {
+    fn parse(header: &str) -> Option<AuthChallenge> {
+        let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+        let mut params = HashMap::new();
+        let mut key = String::new();
+        let mut value = String::new();
+        let mut in_value = false;
+        let mut in_quotes = false;
+        for c in rest.chars() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                '=' if !in_value && !in_quotes => in_value = true,
+                ',' if !in_quotes => {
+                    params.insert(key.trim().to_lowercase(), value.clone());
+                    key.clear();
+                    value.clear();
+                    in_value = false;
+                }
+                _ if in_value => value.push(c),
+                _ => key.push(c),
+            }
+        }
+        if !key.trim().is_empty() {
+            params.insert(key.trim().to_lowercase(), value);
+        }
+        if scheme.is_empty() {
+            return None;
+        }
+        Some(AuthChallenge {
+            scheme: scheme.to_string(),
+            params,
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::credentials::CredentialSource;
+use crate::components::test_registry::TestRegistry;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+fn parse_auth_challenge(_init_logger: ()) {
+    let challenge = AuthChallenge::parse(r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#).unwrap();
+    assert_eq!(challenge.scheme, "Bearer");
+    assert_eq!(challenge.params["realm"], "https://auth.docker.io/token");
+    assert_eq!(challenge.params["service"], "registry.docker.io");
+    assert_eq!(challenge.params["scope"], "repository:library/nginx:pull");
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_into_layout_with_token(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    registry.require_token();
+    let (_, images) = registry.push_index("newbe36524/sdk", "6.0", &[("linux", "amd64"), ("linux", "arm64")]);
+    let dir = tempfile::tempdir().unwrap();
+    let layout = OciLayout::open_or_create(dir.path()).unwrap();
+    let reference = ImageReference::parse(&format!("{}/newbe36524/sdk:6.0", registry.host())).unwrap();
+    let platform = Platform {
+        os: "linux".to_string(),
+        architecture: "arm64".to_string(),
+        variant: None,
+    };
+
+    let progress = PullProgress::with_tty(&reference.to_string(), false);
+    let descriptor = RegistryClient::new().pull_into_layout(&reference, &platform, &layout, &progress).await.unwrap();
+
+    let arm64 = &images[1];
+    assert_eq!(descriptor.digest, arm64.digest);
+    assert!(layout.contains_blob(&arm64.digest));
+    assert!(layout.contains_blob(&arm64.config.digest));
+    assert!(arm64.layers.iter().all(|layer| layout.contains_blob(&layer.digest)));
+    assert!(!layout.contains_blob(&images[0].layers[0].digest));
+    assert!(registry.requests().iter().any(|request| request == "GET /token"));
+    for layer in &arm64.layers {
+        assert_eq!(progress.layer_bytes(&layer.digest), Some((layer.size, Some(layer.size))));
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn fetch_manifest_not_found(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let reference = ImageReference::parse(&format!("{}/newbe36524/sdk:missing", registry.host())).unwrap();
+    let result = RegistryClient::new().fetch_manifest(&reference).await;
+    assert!(matches!(result, Err(DockermirError::RegistryNotFound { .. })));
+}
+
+#[rstest]
+#[tokio::test]
+async fn fetch_image_manifest_missing_platform(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    registry.push_index("newbe36524/sdk", "6.0", &[("linux", "amd64")]);
+    let reference = ImageReference::parse(&format!("{}/newbe36524/sdk:6.0", registry.host())).unwrap();
+    let platform = Platform {
+        os: "windows".to_string(),
+        architecture: "amd64".to_string(),
+        variant: None,
+    };
+    let result = RegistryClient::new().fetch_image_manifest(&reference, &platform).await;
+    assert!(matches!(result, Err(DockermirError::PlatformNotFound { .. })));
+}
+
+#[rstest]
+#[case("linux/amd64", "linux", "amd64", None)]
+#[case("linux/arm64/v8", "linux", "arm64", Some("v8"))]
+#[case("Windows/AMD64", "windows", "amd64", None)]
+fn parse_platform(_init_logger: (), #[case]platform: &str, #[case]os: &str, #[case]architecture: &str, #[case]variant: Option<&str>) {
+    let platform: Platform = platform.parse().unwrap();
+    assert_eq!(platform, Platform {
+        os: os.to_string(),
+        architecture: architecture.to_string(),
+        variant: variant.map(|variant| variant.to_string()),
+    });
+}
+
+#[rstest]
+#[case("linux")]
+#[case("linux/")]
+#[case("linux/arm/v7/extra")]
+fn parse_invalid_platform(_init_logger: (), #[case]platform: &str) {
+    assert!(platform.parse::<Platform>().is_err());
+}
+
+#[rstest]
+#[case("linux/arm64", None, true)]
+#[case("linux/arm64/v8", None, true)]
+#[case("linux/arm/v7", Some("v6"), false)]
+#[case("linux/arm", Some("v7"), true)]
+fn platform_matches_variant(_init_logger: (), #[case]requested: &str, #[case]variant: Option<&str>, #[case]expected: bool) {
+    let requested: Platform = requested.parse().unwrap();
+    let available = Platform {
+        os: requested.os.clone(),
+        architecture: requested.architecture.clone(),
+        variant: variant.map(|variant| variant.to_string()),
+    };
+    assert_eq!(requested.matches(&available), expected);
+}
+
+#[rstest]
+#[case::token(false)]
+#[case::basic(true)]
+#[tokio::test]
+async fn fetch_manifest_with_credential(_init_logger: (), #[case]basic: bool) {
+    let registry = TestRegistry::start().await;
+    if basic {
+        registry.require_basic_auth("robot", "secret");
+    } else {
+        registry.require_credential("robot", "secret");
+    }
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer"]);
+    let reference = ImageReference::parse(&format!("{}/newbe36524/sdk:6.0", registry.host())).unwrap();
+
+    let anonymous = RegistryClient::new().fetch_manifest(&reference).await;
+    assert!(matches!(anonymous, Err(DockermirError::RegistryUnauthorized { .. })));
+
+    let credential = RegistryCredential {
+        username: "robot".to_string(),
+        secret: "secret".to_string(),
+        source: CredentialSource::Config,
+    };
+    let client = RegistryClient::new().with_credential(&reference.registry, Some(credential));
+    assert_eq!(client.fetch_manifest(&reference).await.unwrap().digest, image.digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn upload_blob_missing_file(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let reference = ImageReference::parse(&format!("{}/newbe36524/sdk:6.0", registry.host())).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = RegistryClient::new().upload_blob(&reference, &sha256_digest(b"layer"), &dir.path().join("missing")).await;
+
+    assert!(matches!(&result, Err(DockermirError::RegistryRequestError { error, .. }) if error.contains("failed to open the blob file")), "{:?}", result);
+    assert!(registry.requests().iter().all(|request| !request.starts_with("POST ") && !request.starts_with("PUT ")), "{:?}", registry.requests());
+}
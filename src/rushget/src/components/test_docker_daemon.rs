@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{CONNECTION, UPGRADE};
+use hyper::http::request::Parts;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::net::UnixListener;
+use crate::components::credentials::IDENTITY_TOKEN_USERNAME;
+use crate::components::docker_api::{query_value, DockerHost};
+use crate::docker::reference::ImageReference;
+
+pub(crate) const TEST_LAYER_ID: &str = "0123456789ab";
+
+struct LocalImage {
+    id: String,
+    tags: Vec<String>,
+}
+
+#[derive(Default)]
+struct TestDockerDaemonState {
+    remote: HashMap<String, String>,
+    local: Vec<LocalImage>,
+    /// `(username, password)` accepted by `/auth`
+    credential: Option<(String, String)>,
+    /// Every pull fails with this error inside the progress stream, like a registry failing halfway
+    pull_error: Option<String>,
+    requests: Vec<String>,
+    registry_auths: Vec<Value>,
+}
+
+pub(crate) struct TestDockerDaemon {
+    socket: PathBuf,
+    _dir: TempDir,
+    state: Arc<Mutex<TestDockerDaemonState>>,
+}
+
+impl TestDockerDaemon {
+    pub(crate) async fn start() -> TestDockerDaemon {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let state = Arc::new(Mutex::new(TestDockerDaemonState::default()));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => return,
+                };
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(state.clone(), request));
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).with_upgrades().await;
+                });
+            }
+        });
+        TestDockerDaemon {
+            socket,
+            _dir: dir,
+            state,
+        }
+    }
+
+    pub(crate) fn host(&self) -> DockerHost {
+        DockerHost::Unix(self.socket.clone())
+    }
+
+    pub(crate) fn add_remote(&self, image: &str, id: &str) {
+        self.state.lock().unwrap().remote.insert(canonical(image), id.to_string());
+    }
+
+    pub(crate) fn add_local(&self, image: &str, id: &str) {
+        self.state.lock().unwrap().local.push(LocalImage {
+            id: id.to_string(),
+            tags: vec![canonical(image)],
+        });
+    }
+
+    pub(crate) fn require_credential(&self, username: &str, password: &str) {
+        self.state.lock().unwrap().credential = Some((username.to_string(), password.to_string()));
+    }
+
+    pub(crate) fn fail_pulls(&self, error: &str) {
+        self.state.lock().unwrap().pull_error = Some(error.to_string());
+    }
+
+    pub(crate) fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub(crate) fn registry_auths(&self) -> Vec<Value> {
+        self.state.lock().unwrap().registry_auths.clone()
+    }
+
+    pub(crate) fn local_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.state.lock().unwrap().local.iter().flat_map(|image| image.tags.clone()).collect();
+        tags.sort();
+        tags
+    }
+}
+
+fn canonical(image: &str) -> String {
+    ImageReference::parse(image).map(|reference| reference.to_string()).unwrap_or_else(|_| image.to_string())
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+fn stream_response(messages: &[Value]) -> Response<Full<Bytes>> {
+    let body: String = messages.iter().map(|message| format!("{}\r\n", message)).collect();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+fn not_found(message: String) -> Response<Full<Bytes>> {
+    json_response(StatusCode::NOT_FOUND, &json!({ "message": message }))
+}
+
+/// The image id and the tags of every image in the `manifest.json` of a `docker save` archive
+fn archive_manifests(archive: &[u8]) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.path().map_err(|e| e.to_string())?.to_str() != Some("manifest.json") {
+            continue;
+        }
+        let manifests: Vec<Value> = serde_json::from_reader(entry).map_err(|e| e.to_string())?;
+        return Ok(manifests.iter()
+            .map(|manifest| {
+                let config = manifest["Config"].as_str().unwrap_or_default();
+                let id = format!("sha256:{}", config.rsplit('/').next().unwrap_or_default());
+                let tags = manifest["RepoTags"].as_array().into_iter().flatten()
+                    .filter_map(|tag| tag.as_str().map(|tag| tag.to_string()))
+                    .collect();
+                (id, tags)
+            })
+            .collect());
+    }
+    Err("invalid tar header: manifest.json not found".to_string())
+}
+
+async fn handle(state: Arc<Mutex<TestDockerDaemonState>>, mut request: Request<Incoming>)
+                -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path().ends_with("/attach") && request.headers().contains_key(UPGRADE) {
+        state.lock().unwrap().requests.push(format!("{} {}", request.method(), request.uri()));
+        let upgrade = hyper::upgrade::on(&mut request);
+        // the attached container echoes its stdin, like `cat`
+        tokio::spawn(async move {
+            if let Ok(upgraded) = upgrade.await {
+                let (mut reader, mut writer) = tokio::io::split(TokioIo::new(upgraded));
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            }
+        });
+        return Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "tcp")
+            .body(Full::default())
+            .unwrap());
+    }
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await.map(|body| body.to_bytes().to_vec()).unwrap_or_default();
+    Ok(respond_to(&state, &parts, body))
+}
+
+fn respond_to(state: &Mutex<TestDockerDaemonState>, request: &Parts, body: Vec<u8>) -> Response<Full<Bytes>> {
+    let mut state = state.lock().unwrap();
+    let path = request.uri.path().to_string();
+    let query = request.uri.query().unwrap_or_default().to_string();
+    state.requests.push(match query.is_empty() {
+        true => format!("{} {}", request.method, path),
+        false => format!("{} {}?{}", request.method, path, query),
+    });
+    if let Some(auth) = request.headers.get("X-Registry-Auth").and_then(|value| value.to_str().ok()) {
+        let decoded = URL_SAFE.decode(auth).ok().and_then(|auth| serde_json::from_slice(&auth).ok()).unwrap_or(Value::Null);
+        state.registry_auths.push(decoded);
+    }
+
+    if request.method == Method::POST && path == "/auth" {
+        let auth: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let accepted = match &state.credential {
+            Some((username, password)) if username == IDENTITY_TOKEN_USERNAME => auth["identitytoken"] == *password,
+            Some((username, password)) => auth["username"] == *username && auth["password"] == *password,
+            None => true,
+        };
+        return match accepted {
+            true => json_response(StatusCode::OK, &json!({ "Status": "Login Succeeded" })),
+            false => json_response(StatusCode::UNAUTHORIZED, &json!({ "message": "unauthorized: incorrect username or password" })),
+        };
+    }
+    if request.method == Method::POST && path == "/images/create" {
+        let from_image = query_value(&query, "fromImage").unwrap_or_default();
+        let tag = query_value(&query, "tag").unwrap_or_else(|| "latest".to_string());
+        let image = match tag.contains(':') {
+            true => canonical(&format!("{}@{}", from_image, tag)),
+            false => canonical(&format!("{}:{}", from_image, tag)),
+        };
+        if let Some(error) = state.pull_error.clone() {
+            return stream_response(&[
+                json!({ "status": format!("Pulling from {}", from_image), "id": tag }),
+                json!({ "errorDetail": { "message": error }, "error": error }),
+            ]);
+        }
+        let Some(id) = state.remote.get(&image).cloned() else {
+            return not_found(format!("manifest for {} not found: manifest unknown", image));
+        };
+        match state.local.iter_mut().find(|local| local.id == id) {
+            Some(local) if !local.tags.contains(&image) => local.tags.push(image.clone()),
+            Some(_) => {}
+            None => state.local.push(LocalImage { id, tags: vec![image.clone()] }),
+        }
+        return stream_response(&[
+            json!({ "status": format!("Pulling from {}", from_image), "id": tag }),
+            json!({ "status": "Downloading", "progressDetail": { "current": 50, "total": 100 }, "id": TEST_LAYER_ID }),
+            json!({ "status": "Downloading", "progressDetail": { "current": 100, "total": 100 }, "id": TEST_LAYER_ID }),
+            json!({ "status": "Pull complete", "progressDetail": {}, "id": TEST_LAYER_ID }),
+            json!({ "status": format!("Status: Downloaded newer image for {}", image) }),
+        ]);
+    }
+    if request.method == Method::POST && path == "/images/load" {
+        let manifests = match archive_manifests(&body) {
+            Ok(manifests) => manifests,
+            Err(e) => return json_response(StatusCode::INTERNAL_SERVER_ERROR, &json!({ "message": e })),
+        };
+        let mut messages = vec![];
+        for (id, tags) in manifests {
+            for tag in &tags {
+                messages.push(json!({ "stream": format!("Loaded image: {}\n", tag) }));
+            }
+            let tags: Vec<String> = tags.iter().map(|tag| canonical(tag)).collect();
+            for local in state.local.iter_mut() {
+                local.tags.retain(|tag| !tags.contains(tag));
+            }
+            match state.local.iter_mut().find(|local| local.id == id) {
+                Some(local) => local.tags.extend(tags),
+                None => state.local.push(LocalImage { id, tags }),
+            }
+        }
+        return stream_response(&messages);
+    }
+    let Some(rest) = path.strip_prefix("/images/") else {
+        return not_found(format!("page not found: {}", path));
+    };
+    let find = |state: &TestDockerDaemonState, name: &str| {
+        let name = canonical(name);
+        state.local.iter().position(|local| local.tags.contains(&name) || local.id == name)
+    };
+    match request.method {
+        Method::GET if rest.ends_with("/json") => {
+            let name = rest.trim_end_matches("/json");
+            match find(&state, name) {
+                Some(index) => {
+                    let local = &state.local[index];
+                    json_response(StatusCode::OK, &json!({ "Id": local.id, "RepoTags": local.tags, "RepoDigests": [] }))
+                }
+                None => not_found(format!("No such image: {}", name)),
+            }
+        }
+        Method::POST if rest.ends_with("/tag") => {
+            let name = rest.trim_end_matches("/tag");
+            let target = canonical(&format!("{}:{}", query_value(&query, "repo").unwrap_or_default(),
+                                            query_value(&query, "tag").unwrap_or_else(|| "latest".to_string())));
+            match find(&state, name) {
+                Some(index) => {
+                    for local in state.local.iter_mut() {
+                        local.tags.retain(|tag| *tag != target);
+                    }
+                    state.local[index].tags.push(target);
+                    json_response(StatusCode::CREATED, &Value::Null)
+                }
+                None => not_found(format!("No such image: {}", name)),
+            }
+        }
+        Method::POST if rest.ends_with("/push") => {
+            let name = format!("{}:{}", rest.trim_end_matches("/push"), query_value(&query, "tag").unwrap_or_default());
+            match find(&state, &name) {
+                Some(_) => stream_response(&[
+                    json!({ "status": format!("The push refers to repository [{}]", rest.trim_end_matches("/push")) }),
+                    json!({ "status": "Pushing", "progressDetail": { "current": 100, "total": 100 }, "id": TEST_LAYER_ID }),
+                    json!({ "status": "Pushed", "progressDetail": {}, "id": TEST_LAYER_ID }),
+                ]),
+                None => not_found(format!("An image does not exist locally with the tag: {}", name)),
+            }
+        }
+        Method::DELETE => {
+            let name = canonical(rest);
+            match find(&state, rest) {
+                Some(index) => {
+                    state.local[index].tags.retain(|tag| *tag != name);
+                    if state.local[index].tags.is_empty() {
+                        state.local.remove(index);
+                    }
+                    json_response(StatusCode::OK, &json!([{ "Untagged": name }]))
+                }
+                None => not_found(format!("No such image: {}", rest)),
+            }
+        }
+        _ => not_found(format!("page not found: {}", path)),
+    }
+}
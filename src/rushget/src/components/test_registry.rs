@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::http::request::Parts;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use crate::components::registry::{sha256_digest, Descriptor, ImageIndex, ImageManifest, Platform,
+                                  MEDIA_TYPE_OCI_INDEX, MEDIA_TYPE_OCI_MANIFEST};
+
+const TEST_TOKEN: &str = "test-token";
+
+#[derive(Default)]
+struct TestRegistryState {
+    manifests: HashMap<(String, String), (String, Vec<u8>)>,
+    blobs: HashMap<String, Vec<u8>>,
+    require_token: bool,
+    /// `user:password` required by the token endpoint, or by the registry itself when `basic_auth` is set
+    credential: Option<String>,
+    basic_auth: bool,
+    requests: Vec<String>,
+    delay: Option<Duration>,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+pub(crate) struct TestImage {
+    pub(crate) digest: String,
+    pub(crate) config: Descriptor,
+    pub(crate) layers: Vec<Descriptor>,
+}
+
+pub(crate) struct TestRegistry {
+    addr: SocketAddr,
+    state: Arc<Mutex<TestRegistryState>>,
+}
+
+impl TestRegistry {
+    pub(crate) async fn start() -> TestRegistry {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(TestRegistryState::default()));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => return,
+                };
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(state.clone(), addr, request));
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        TestRegistry { addr, state }
+    }
+
+    pub(crate) fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub(crate) fn require_token(&self) {
+        self.state.lock().unwrap().require_token = true;
+    }
+
+    pub(crate) fn require_credential(&self, username: &str, password: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.require_token = true;
+        state.credential = Some(format!("{}:{}", username, password));
+    }
+
+    /// Answer with a basic challenge instead of a token, like a plain htpasswd protected registry
+    pub(crate) fn require_basic_auth(&self, username: &str, password: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.basic_auth = true;
+        state.credential = Some(format!("{}:{}", username, password));
+    }
+
+    pub(crate) fn delay(&self, delay: Duration) {
+        self.state.lock().unwrap().delay = Some(delay);
+    }
+
+    pub(crate) fn max_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_in_flight
+    }
+
+    pub(crate) fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub(crate) fn put_blob(&self, media_type: &str, content: &[u8]) -> Descriptor {
+        let digest = sha256_digest(content);
+        self.state.lock().unwrap().blobs.insert(digest.clone(), content.to_vec());
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size: content.len() as u64,
+            platform: None,
+            annotations: None,
+        }
+    }
+
+    pub(crate) fn put_manifest(&self, repository: &str, tag: &str, media_type: &str, content: &[u8]) -> String {
+        let digest = sha256_digest(content);
+        for reference in [tag, digest.as_str()] {
+            self.put_manifest_at(repository, reference, media_type, content);
+        }
+        digest
+    }
+
+    /// Store the manifest under any reference, even a digest which does not match the content like a tampered mirror
+    pub(crate) fn put_manifest_at(&self, repository: &str, reference: &str, media_type: &str, content: &[u8]) {
+        self.state.lock().unwrap().manifests
+            .insert((repository.to_string(), reference.to_string()), (media_type.to_string(), content.to_vec()));
+    }
+
+    pub(crate) fn push_image(&self, repository: &str, tag: &str, layers: &[&str]) -> TestImage {
+        let config = self.put_blob("application/vnd.oci.image.config.v1+json",
+                                   format!(r#"{{"architecture":"amd64","os":"linux","tag":"{}"}}"#, tag).as_bytes());
+        let layers: Vec<Descriptor> = layers.iter()
+            .map(|layer| self.put_blob("application/vnd.oci.image.layer.v1.tar", layer.as_bytes()))
+            .collect();
+        let manifest = ImageManifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_OCI_MANIFEST.to_string()),
+            config: config.clone(),
+            layers: layers.clone(),
+        };
+        let digest = self.put_manifest(repository, tag, MEDIA_TYPE_OCI_MANIFEST, &serde_json::to_vec(&manifest).unwrap());
+        TestImage { digest, config, layers }
+    }
+
+    pub(crate) fn push_index(&self, repository: &str, tag: &str, platforms: &[(&str, &str)]) -> (String, Vec<TestImage>) {
+        let mut images = vec![];
+        let mut manifests = vec![];
+        for (os, architecture) in platforms {
+            let image = self.push_image(repository, &format!("{}-{}-{}", tag, os, architecture),
+                                        &[&format!("{} {} {}", tag, os, architecture)]);
+            let content = self.state.lock().unwrap().manifests[&(repository.to_string(), image.digest.clone())].1.clone();
+            manifests.push(Descriptor {
+                media_type: MEDIA_TYPE_OCI_MANIFEST.to_string(),
+                digest: image.digest.clone(),
+                size: content.len() as u64,
+                platform: Some(Platform {
+                    os: os.to_string(),
+                    architecture: architecture.to_string(),
+                    variant: None,
+                }),
+                annotations: None,
+            });
+            images.push(image);
+        }
+        let index = ImageIndex {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_OCI_INDEX.to_string()),
+            manifests,
+        };
+        let digest = self.put_manifest(repository, tag, MEDIA_TYPE_OCI_INDEX, &serde_json::to_vec(&index).unwrap());
+        (digest, images)
+    }
+}
+
+fn respond(status: StatusCode, content_type: Option<&str>, body: Vec<u8>) -> Response<Full<Bytes>> {
+    let mut builder = Response::builder().status(status);
+    if let Some(content_type) = content_type {
+        builder = builder.header("Content-Type", content_type);
+    }
+    builder.body(Full::new(Bytes::from(body))).unwrap()
+}
+
+async fn handle(state: Arc<Mutex<TestRegistryState>>, addr: SocketAddr, request: Request<Incoming>)
+                -> Result<Response<Full<Bytes>>, Infallible> {
+    let delay = {
+        let mut state = state.lock().unwrap();
+        state.in_flight += 1;
+        state.max_in_flight = state.max_in_flight.max(state.in_flight);
+        state.delay
+    };
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await.map(|body| body.to_bytes().to_vec()).unwrap_or_default();
+    let response = respond_to(&state, addr, &parts, body);
+    state.lock().unwrap().in_flight -= 1;
+    Ok(response)
+}
+
+fn respond_to(state: &Mutex<TestRegistryState>, addr: SocketAddr, request: &Parts, body: Vec<u8>) -> Response<Full<Bytes>> {
+    let path = request.uri.path().to_string();
+    let mut state = state.lock().unwrap();
+    state.requests.push(format!("{} {}", request.method, path));
+
+    let authorization = request.headers.get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let basic = state.credential.as_ref().map(|credential| format!("Basic {}", STANDARD.encode(credential)));
+    if path == "/token" {
+        if basic.is_some() && basic.as_deref() != Some(authorization.as_str()) {
+            return respond(StatusCode::UNAUTHORIZED, None, vec![]);
+        }
+        return respond(StatusCode::OK, Some("application/json"), format!(r#"{{"token":"{}"}}"#, TEST_TOKEN).into_bytes());
+    }
+    let Some(rest) = path.strip_prefix("/v2/") else {
+        return respond(StatusCode::NOT_FOUND, None, vec![]);
+    };
+    let (repository, kind, reference) = if let Some((repository, reference)) = rest.rsplit_once("/manifests/") {
+        (repository, "manifests", reference)
+    } else if let Some((repository, reference)) = rest.rsplit_once("/blobs/") {
+        (repository, "blobs", reference)
+    } else if let Some(repository) = rest.strip_suffix("/tags/list") {
+        (repository, "tags", "")
+    } else {
+        ("", "", "")
+    };
+
+    if state.basic_auth && basic.as_deref() != Some(authorization.as_str()) {
+        let mut response = respond(StatusCode::UNAUTHORIZED, None, vec![]);
+        response.headers_mut().insert("WWW-Authenticate", r#"Basic realm="test-registry""#.parse().unwrap());
+        return response;
+    }
+    let authorized = authorization == format!("Bearer {}", TEST_TOKEN);
+    if state.require_token && !authorized {
+        let actions = if request.method == Method::GET || request.method == Method::HEAD { "pull" } else { "pull,push" };
+        let challenge = format!(r#"Bearer realm="http://{}/token",service="test-registry",scope="repository:{}:{}""#, addr, repository, actions);
+        let mut response = respond(StatusCode::UNAUTHORIZED, None, vec![]);
+        response.headers_mut().insert("WWW-Authenticate", challenge.parse().unwrap());
+        return response;
+    }
+
+    let head = request.method == Method::HEAD;
+    let response = match (&request.method, kind) {
+        (&Method::PUT, "manifests") => {
+            let media_type = request.headers.get("Content-Type").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+            let digest = sha256_digest(&body);
+            for reference in [reference, digest.as_str()] {
+                state.manifests.insert((repository.to_string(), reference.to_string()), (media_type.clone(), body.clone()));
+            }
+            let mut response = respond(StatusCode::CREATED, None, vec![]);
+            response.headers_mut().insert("Docker-Content-Digest", digest.parse().unwrap());
+            response
+        }
+        (&Method::POST, "blobs") if reference == "uploads/" => {
+            let mut response = respond(StatusCode::ACCEPTED, None, vec![]);
+            let location = format!("/v2/{}/blobs/uploads/{}", repository, state.requests.len());
+            response.headers_mut().insert("Location", location.parse().unwrap());
+            response
+        }
+        (&Method::PUT, "blobs") if reference.starts_with("uploads/") => {
+            let expected = request.uri.query().unwrap_or_default()
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("digest="))
+                .unwrap_or_default();
+            if sha256_digest(&body) != expected {
+                return respond(StatusCode::BAD_REQUEST, None, br#"{"errors":[{"code":"DIGEST_INVALID"}]}"#.to_vec());
+            }
+            state.blobs.insert(expected.to_string(), body);
+            respond(StatusCode::CREATED, None, vec![])
+        }
+        (_, "tags") => {
+            let mut tags: Vec<&String> = state.manifests.keys()
+                .filter(|(name, reference)| name == repository && !reference.contains(':'))
+                .map(|(_, reference)| reference)
+                .collect();
+            tags.sort();
+            respond(StatusCode::OK, Some("application/json"), serde_json::json!({ "name": repository, "tags": tags }).to_string().into_bytes())
+        }
+        (_, kind) => match kind {
+            "manifests" => match state.manifests.get(&(repository.to_string(), reference.to_string())) {
+                Some((media_type, content)) => {
+                    let mut response = respond(StatusCode::OK, Some(media_type), if head { vec![] } else { content.clone() });
+                    response.headers_mut().insert("Docker-Content-Digest", sha256_digest(content).parse().unwrap());
+                    response
+                }
+                None => respond(StatusCode::NOT_FOUND, None, vec![]),
+            },
+            "blobs" => match state.blobs.get(reference) {
+                Some(content) => respond(StatusCode::OK, Some("application/octet-stream"), if head { vec![] } else { content.clone() }),
+                None => respond(StatusCode::NOT_FOUND, None, vec![]),
+            },
+            _ if rest.is_empty() => respond(StatusCode::OK, Some("application/json"), b"{}".to_vec()),
+            _ => respond(StatusCode::NOT_FOUND, None, vec![]),
+        },
+    };
+    response
+}
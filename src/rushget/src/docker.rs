@@ -1,22 +1,76 @@
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
+pub(crate) mod compose;
+pub(crate) mod configure;
+pub(crate) mod freshness;
+pub(crate) mod lock;
+#[cfg(unix)]
+pub(crate) mod proxy_socket;
+pub(crate) mod reference;
+pub(crate) mod relay;
+pub(crate) mod save;
+pub(crate) mod serve;
+pub(crate) mod sync;
 
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use indicatif::HumanBytes;
+use std::sync::Arc;
 use regex::Regex;
-use crate::components::config::{DockerMirrorRule, DockerMirrorRuleset, RushGetConfig};
-use crate::components::docker_exec::{DockerExec, DockermirPullInput};
+use tokio::sync::Semaphore;
+use tokio::task::{Id, JoinError, JoinSet};
+use crate::components::config::{DockerMirrorRule, DockerMirrorRuleset, PostPullConfig, RushGetConfig};
+use crate::components::container_engine::{create_engine, ContainerEngine, ContainerEngineKind};
+use crate::components::credentials::{CredentialResolver, RegistryCredential};
+use crate::components::docker_exec::DockermirPullInput;
+use crate::components::oci_layout::OciLayout;
+use crate::components::progress::PullProgress;
+use crate::components::registry::{Platform, RegistryClient};
 use crate::components::RushGetTask;
+use crate::docker::freshness::{check_freshness, Freshness};
+use crate::docker::lock::{locked_candidate, LockFile};
+use crate::docker::reference::ImageReference;
 use crate::error::DockermirError;
 
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DockerPullOptions {
+    /// Pull with the native registry client into this OCI image layout directory, no docker daemon required
+    pub(crate) oci_layout: Option<PathBuf>,
+    /// The container engine to pull with, overrides the engine in config
+    pub(crate) engine: Option<ContainerEngineKind>,
+    /// Pull from the original registry when all mirrors failed
+    pub(crate) fallback_to_upstream: bool,
+    /// How many images are pulled at the same time in a batch, overrides the concurrency in config
+    pub(crate) concurrency: Option<usize>,
+    /// The platform to pick from multi-arch images, the platform of the host or engine if not set
+    pub(crate) platform: Option<Platform>,
+    /// Skip mirrors whose digest is behind upstream
+    pub(crate) require_fresh: bool,
+    /// Pull even when the local image store has the image of the mirror already
+    pub(crate) force: bool,
+    /// Keep the mirror name of the pulled image, added to the post pull config
+    pub(crate) keep_mirror_tag: bool,
+    /// Extra names of the pulled image, added to the tags of the post pull config
+    pub(crate) tags: Vec<String>,
+    /// Pull the mirror and digest recorded in this lock file, failing when the lock is out of date
+    pub(crate) locked: Option<PathBuf>,
+}
+
+const DEFAULT_PULL_CONCURRENCY: usize = 4;
+
 pub(crate) struct DockerPullTask {
     image: String,
     config: RushGetConfig,
+    options: DockerPullOptions,
 }
 
 impl DockerPullTask {
-    pub(crate) fn new(config: RushGetConfig, image: String) -> Self {
+    pub(crate) fn new(config: RushGetConfig, image: String, options: DockerPullOptions) -> Self {
         DockerPullTask {
             image,
             config,
+            options,
         }
     }
 }
@@ -24,85 +78,628 @@ impl DockerPullTask {
 #[async_trait::async_trait]
 impl RushGetTask for DockerPullTask {
     async fn run(self) -> Result<(), DockermirError> {
-        let mirror_image = map_mirror_by_configuration(&self.image, &self.config)?;
-        info!("Pull image: {} from mirror: {}", self.image, &mirror_image.mirror_image);
-        trace!("hit ruleset: {:?}", &mirror_image.hit_ruleset);
-        trace!("hit rule: {:?}", &mirror_image.hit_rule);
-        let exec = DockerExec::new();
-        exec.pull(&DockermirPullInput::new(self.image.to_string(), mirror_image.mirror_image.to_owned()))?;
-        exec.tag(&DockermirPullInput::new(self.image.to_string(), mirror_image.mirror_image.to_owned()))?;
-        exec.rmi(&mirror_image.mirror_image)?;
-        info!("Successfully pull image: {}", self.image);
+        let (image, candidates) = match &self.options.locked {
+            Some(lock_file) => {
+                let candidate = locked_candidate(&LockFile::read(lock_file)?, &self.image, &self.config)?;
+                (candidate.source_image.clone(), vec![candidate])
+            }
+            None => (self.image.clone(), map_mirror_candidates(&self.image, &self.config)?),
+        };
+        let fallback_to_upstream = self.options.fallback_to_upstream || self.config.docker.fallback_to_upstream;
+        if candidates.is_empty() && !fallback_to_upstream {
+            return Err(DockermirError::MismatchAllRule);
+        }
+        let engine = match &self.options.oci_layout {
+            Some(_) => None,
+            None => {
+                let engine = create_engine(ContainerEngineKind::resolve(self.options.engine, &self.config)?)?;
+                trace!("pull with container engine: {}", engine.kind());
+                Some(engine)
+            }
+        };
+
+        let post_pull = self.post_pull();
+        // try every matched mirror in the order of the config, the first one serving the image wins
+        let resolver = CredentialResolver::new();
+        let mut attempts = vec![];
+        for mirror_image in &candidates {
+            info!("Pull image: {} from mirror: {}", image, mirror_image.mirror_image);
+            trace!("hit ruleset: {:?}", mirror_image.hit_ruleset);
+            trace!("hit rule: {:?}", mirror_image.hit_rule);
+            // a mirror whose credential can not be resolved is a failed attempt, the next mirror may still serve the image
+            let resolved = match ImageReference::parse(&mirror_image.mirror_image) {
+                Ok(reference) => resolver.resolve(&reference.registry, mirror_image.hit_ruleset.auth.as_ref()).await
+                    .map(|credential| (reference, credential)),
+                Err(e) => Err(e),
+            };
+            let (mirror_reference, credential) = match resolved {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    warn!("Skip mirror: {} of image: {}, error: {}", mirror_image.mirror_image, image, e);
+                    attempts.push(format!("{} ({}/{}): {}", mirror_image.mirror_image, mirror_image.hit_ruleset.name, mirror_image.hit_rule.name, e));
+                    continue;
+                }
+            };
+            let fresh = match self.options.require_fresh {
+                true => ensure_fresh(&resolver, &mirror_image.source_reference, &mirror_reference, credential.clone()).await,
+                false => Ok(()),
+            };
+            let progress = PullProgress::new(&image);
+            let result = match (fresh, &engine) {
+                (Err(e), _) => Err(e),
+                (Ok(_), Some(engine)) => {
+                    if !self.options.force && is_present_locally(engine.as_ref(), mirror_image, credential.as_ref(),
+                                                                 self.options.platform.as_ref()).await {
+                        info!("Image: {} is already present locally with the digest of the mirror, skip pulling", image);
+                        return add_extra_tags(engine.as_ref(), &mirror_image.local_image(), &mirror_image.source_reference,
+                                              &post_pull.tags).await;
+                    }
+                    pull_with_engine(engine.as_ref(), mirror_image, credential.as_ref(), self.options.platform.as_ref(),
+                                     &post_pull, &progress).await
+                }
+                (Ok(_), None) => pull_into_oci_layout(&mirror_reference, &mirror_image.source_reference, credential,
+                                                      &self.platform(), self.oci_layout(), &progress).await,
+            };
+            match result {
+                Ok(_) => {
+                    info!("Successfully pull image: {}", image);
+                    return Ok(());
+                }
+                Err(e) if e.is_mirror_failure() => {
+                    warn!("Failed to pull image: {} from mirror: {}, error: {}", image, mirror_image.mirror_image, e);
+                    attempts.push(format!("{} ({}/{}): {}", mirror_image.mirror_image, mirror_image.hit_ruleset.name, mirror_image.hit_rule.name, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if fallback_to_upstream {
+            info!("Pull image: {} from upstream as the last resort", image);
+            let source_reference = ImageReference::parse(&image)?;
+            let progress = PullProgress::new(&image);
+            // like for the mirrors, a credential which can not be resolved is recorded as the failed attempt
+            let result = match (resolver.resolve(&source_reference.registry, None).await, &engine) {
+                (Err(e), _) => Err(e),
+                (Ok(credential), Some(engine)) => match login_if_required(engine.as_ref(), &source_reference.registry, credential.as_ref()).await {
+                    Ok(_) => {
+                        let input = DockermirPullInput::new(image.to_owned(), image.to_owned())
+                            .with_platform(self.options.platform.clone());
+                        match engine.pull(&input, &progress).await {
+                            Ok(_) => add_extra_tags(engine.as_ref(), &image, &source_reference, &post_pull.tags).await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    Err(e) => Err(e),
+                },
+                (Ok(credential), None) => pull_into_oci_layout(&source_reference, &source_reference, credential, &self.platform(),
+                                                               self.oci_layout(), &progress).await,
+            };
+            match result {
+                Ok(_) => {
+                    info!("Successfully pull image: {} from upstream", image);
+                    return Ok(());
+                }
+                Err(e) => attempts.push(format!("{} (upstream): {}", image, e)),
+            }
+        }
+        Err(DockermirError::AllMirrorsFailed {
+            source_image: image,
+            attempts,
+        })
+    }
+}
+
+impl DockerPullTask {
+    fn oci_layout(&self) -> &Path {
+        self.options.oci_layout.as_deref().unwrap()
+    }
+
+    fn platform(&self) -> Platform {
+        self.options.platform.clone().unwrap_or_else(Platform::host)
+    }
+
+    /// The post pull config with the options of the command line on top
+    fn post_pull(&self) -> PostPullConfig {
+        let config = &self.config.docker.post_pull;
+        PostPullConfig {
+            keep_mirror_tag: self.options.keep_mirror_tag || config.keep_mirror_tag,
+            tags: config.tags.iter().chain(self.options.tags.iter()).cloned().collect(),
+        }
+    }
+}
+
+/// Fail with a mirror failure when the mirror is behind upstream, so the next mirror or upstream is tried.
+/// A mirror whose freshness can not be told is used as usual.
+async fn ensure_fresh(resolver: &CredentialResolver, source: &ImageReference, mirror: &ImageReference,
+                      credential: Option<RegistryCredential>) -> Result<(), DockermirError> {
+    let source_client = RegistryClient::new()
+        .with_credential(&source.registry, resolver.resolve(&source.registry, None).await?);
+    let mirror_client = RegistryClient::new().with_credential(&mirror.registry, credential);
+    match check_freshness(&source_client, source, &mirror_client, mirror).await {
+        Freshness::Stale { mirror_digest, upstream_digest } => Err(DockermirError::MirrorStale {
+            mirror_image: mirror.to_string(),
+            mirror_digest,
+            upstream_digest,
+        }),
+        Freshness::Unknown(reason) => {
+            warn!("Freshness of mirror {} is unknown, pull it anyway: {}", mirror, reason);
+            Ok(())
+        }
+        Freshness::Fresh | Freshness::MirrorMissing => Ok(()),
+    }
+}
+
+/// Login with the credentials which the engine does not know by itself, e.g. those in the rushget config
+async fn login_if_required(engine: &dyn ContainerEngine, registry: &str, credential: Option<&RegistryCredential>) -> Result<(), DockermirError> {
+    match credential {
+        // the daemon behind the engine api does not read the docker config, only the cli does
+        // `docker login` takes no identity token, only the engine api has a field for it
+        Some(credential) if credential.is_identity_token() && credential.requires_login()
+            && engine.kind() != ContainerEngineKind::DockerApi => {
+            Err(DockermirError::DockerLoginError {
+                registry: registry.to_owned(),
+                error: "an identity token can only be passed on with the docker-api engine".to_string(),
+            })
+        }
+        Some(credential) if credential.requires_login() || engine.kind() == ContainerEngineKind::DockerApi => {
+            trace!("Login to registry: {} as {}", registry, credential.username);
+            engine.login(registry, credential).await
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn pull_with_engine(engine: &dyn ContainerEngine, mirror_image: &ImageMirrorData, credential: Option<&RegistryCredential>,
+                          platform: Option<&Platform>, post_pull: &PostPullConfig, progress: &PullProgress) -> Result<(), DockermirError> {
+    let mirror_reference = ImageReference::parse(&mirror_image.mirror_image)?;
+    if let Some(platform) = platform {
+        // a mirror which synced only some platforms of the index is skipped, instead of the engine failing late
+        RegistryClient::new().with_credential(&mirror_reference.registry, credential.cloned())
+            .fetch_image_manifest(&mirror_reference, platform).await?;
+    }
+    login_if_required(engine, &mirror_reference.registry, credential).await?;
+    let input = DockermirPullInput::new(mirror_image.source_image.to_owned(), mirror_image.mirror_image.to_owned())
+        .with_platform(platform.cloned());
+    engine.pull(&input, progress).await?;
+    verify_pulled_digest(engine, mirror_image).await?;
+    match mirror_image.tag_target() {
+        Some(tag_target) => {
+            engine.tag(&DockermirPullInput::new(tag_target.to_owned(), mirror_image.mirror_image.to_owned())).await?;
+            if !post_pull.keep_mirror_tag {
+                engine.rmi(&mirror_image.mirror_image).await?;
+            }
+        }
+        None => {
+            warn!("Image {} is pinned by digest only, it is kept as {} since digests can not be tagged",
+                mirror_image.source_image, mirror_image.mirror_image);
+        }
+    }
+    let local_image = mirror_image.local_image();
+    add_extra_tags(engine, &local_image, &mirror_image.source_reference, &post_pull.tags).await?;
+    if let Some(image) = engine.inspect(&local_image).await? {
+        trace!("image: {} id: {}, tags: {:?}", local_image, image.id, image.repo_tags);
+    }
+    Ok(())
+}
+
+/// Tag the local image with the extra names, the templates are rendered with the source image
+async fn add_extra_tags(engine: &dyn ContainerEngine, local_image: &str, source_reference: &ImageReference,
+                        tags: &[String]) -> Result<(), DockermirError> {
+    for template in tags {
+        let tag = source_reference.render_target(template)?.to_string();
+        engine.tag(&DockermirPullInput::new(tag, local_image.to_owned())).await?;
+    }
+    Ok(())
+}
+
+/// Whether the local image store has the image which the mirror serves, so the pull, tag and rmi can be skipped.
+/// The repo digests are compared first, the image id is the digest of the config for docker and podman.
+/// Anything which can not be told, e.g. an unreachable mirror, means the image is pulled as usual.
+async fn is_present_locally(engine: &dyn ContainerEngine, mirror_image: &ImageMirrorData, credential: Option<&RegistryCredential>,
+                            platform: Option<&Platform>) -> bool {
+    let local_image = mirror_image.local_image();
+    let local = match engine.inspect(&local_image).await {
+        Ok(Some(local)) => local,
+        Ok(None) => return false,
+        Err(e) => {
+            trace!("Failed to inspect local image: {}, error: {}", local_image, e);
+            return false;
+        }
+    };
+    let Ok(mirror_reference) = ImageReference::parse(&mirror_image.mirror_image) else {
+        return false;
+    };
+    let client = RegistryClient::new().with_credential(&mirror_reference.registry, credential.cloned());
+    let mirror_digest = match &mirror_image.source_reference.digest {
+        Some(digest) => Some(digest.clone()),
+        None => client.fetch_manifest_digest(&mirror_reference).await.unwrap_or_else(|e| {
+            trace!("Failed to fetch the digest of mirror image: {}, error: {}", mirror_reference, e);
+            None
+        }),
+    };
+    let repo_digests = local.repo_digests.unwrap_or_default();
+    if let Some(mirror_digest) = &mirror_digest {
+        if repo_digests.iter().any(|repo_digest| repo_digest.ends_with(&format!("@{}", mirror_digest))) {
+            return true;
+        }
+    }
+    // podman prints the id without the algorithm
+    let local_id = match local.id.contains(':') {
+        true => local.id,
+        false => format!("sha256:{}", local.id),
+    };
+    let platform = platform.cloned().unwrap_or_else(Platform::host);
+    match client.fetch_image_manifest(&mirror_reference, &platform).await {
+        Ok((_, image)) => image.config.digest == local_id,
+        Err(e) => {
+            trace!("Failed to fetch the manifest of mirror image: {}, error: {}", mirror_reference, e);
+            false
+        }
+    }
+}
+
+/// Make sure the mirror served the digest the user asked for, a stale or tampered mirror would serve something else
+async fn verify_pulled_digest(engine: &dyn ContainerEngine, mirror_image: &ImageMirrorData) -> Result<(), DockermirError> {
+    let Some(expected) = &mirror_image.source_reference.digest else {
+        return Ok(());
+    };
+    let repo_digests = engine.inspect(&mirror_image.mirror_image).await?
+        .and_then(|image| image.repo_digests)
+        .unwrap_or_default();
+    if repo_digests.iter().any(|repo_digest| repo_digest.ends_with(&format!("@{}", expected))) {
+        trace!("Verified digest of image: {}, digest: {}", mirror_image.mirror_image, expected);
+        return Ok(());
+    }
+    if let Err(e) = engine.rmi(&mirror_image.mirror_image).await {
+        warn!("Failed to remove mismatched image: {}, error: {}", mirror_image.mirror_image, e);
+    }
+    Err(DockermirError::ImageDigestMismatch {
+        image: mirror_image.mirror_image.clone(),
+        expected: expected.clone(),
+        actual: repo_digests.join(", "),
+    })
+}
+
+async fn pull_into_oci_layout(mirror_reference: &ImageReference, source_reference: &ImageReference, credential: Option<RegistryCredential>,
+                              platform: &Platform, root: &Path, progress: &PullProgress) -> Result<(), DockermirError> {
+    let client = RegistryClient::new().with_credential(&mirror_reference.registry, credential);
+    let layout = OciLayout::open_or_create(root)?;
+    let descriptor = client.pull_into_layout(mirror_reference, platform, &layout, progress).await?;
+    // record the image under the source name, just like `docker tag` does for the engine
+    let ref_name = source_reference.tag.as_deref().unwrap_or(source_reference.reference());
+    layout.add_manifest(&descriptor, &source_reference.to_string(), ref_name)
+}
+
+/// The index of the image the pull task was spawned for, with a panic of the task as its failure
+fn batch_result(images: &[String], indexes: &HashMap<Id, usize>, joined: Result<(Id, Result<(), DockermirError>), JoinError>)
+                -> (usize, Result<(), DockermirError>) {
+    match joined {
+        Ok((id, result)) => (indexes[&id], result),
+        Err(e) => {
+            let index = indexes[&e.id()];
+            (index, Err(DockermirError::PullTaskPanicked {
+                image: images[index].clone(),
+                error: e.to_string(),
+            }))
+        }
+    }
+}
+
+/// Pull a batch of images, e.g. all images of a compose file, and report the result of each image
+pub(crate) struct DockerBatchPullTask {
+    images: Vec<String>,
+    config: RushGetConfig,
+    options: DockerPullOptions,
+}
+
+impl DockerBatchPullTask {
+    pub(crate) fn new(config: RushGetConfig, images: Vec<String>, options: DockerPullOptions) -> Self {
+        DockerBatchPullTask {
+            images,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerBatchPullTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let concurrency = self.options.concurrency
+            .or(self.config.docker.concurrency)
+            .unwrap_or(DEFAULT_PULL_CONCURRENCY)
+            .max(1);
+        info!("Pull {} images with concurrency {}: {:?}", self.images.len(), concurrency, self.images);
+
+        // every image is pulled in its own task, the semaphore keeps at most `concurrency` of them running
+        let semaphore = Arc::new(Semaphore::new(concurrency));
+        let mut tasks = JoinSet::new();
+        // the index is kept outside of the task, so a panicking pull is still reported for its image
+        let mut indexes = HashMap::new();
+        for (index, image) in self.images.iter().enumerate() {
+            let semaphore = semaphore.clone();
+            let task = DockerPullTask::new(self.config.clone(), image.to_owned(), self.options.clone());
+            let handle = tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                task.run().await
+            });
+            indexes.insert(handle.id(), index);
+        }
+        let mut results: Vec<Option<Result<(), DockermirError>>> = self.images.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next_with_id().await {
+            let (index, result) = batch_result(&self.images, &indexes, joined);
+            if let Err(e) = &result {
+                error!("Failed to pull image: {}, error: {}", self.images[index], e);
+            }
+            results[index] = Some(result);
+        }
+
+        info!("Summary of pulling {} images:", results.len());
+        for (image, result) in self.images.iter().zip(results.iter().flatten()) {
+            match result {
+                Ok(_) => info!("  [ok]     {}", image),
+                Err(e) => error!("  [failed] {}: {}", image, e),
+            }
+        }
+        let failed = results.iter().flatten().filter(|result| result.is_err()).count();
+        if failed > 0 {
+            return Err(DockermirError::BatchPullFailed {
+                failed,
+                total: results.len(),
+            });
+        }
         Ok(())
     }
 }
 
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DockerCheckOptions {
+    /// Only match the rules, the registries are not asked
+    pub(crate) offline: bool,
+    /// Also check that the mirrored image has this platform
+    pub(crate) platform: Option<Platform>,
+}
+
 pub(crate) struct DockerCheckTask {
     image: String,
     config: RushGetConfig,
+    options: DockerCheckOptions,
 }
 
 impl DockerCheckTask {
-    pub(crate) fn new(config: RushGetConfig, image: String) -> Self {
+    pub(crate) fn new(config: RushGetConfig, image: String, options: DockerCheckOptions) -> Self {
         DockerCheckTask {
             image,
             config,
+            options,
         }
     }
 }
 
+/// What a mirror registry knows about the mapped reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MirrorStatus {
+    Exists {
+        digest: String,
+        /// The config and layers of an image, None for an index
+        size: Option<u64>,
+        platforms: Vec<Platform>,
+    },
+    Missing,
+    AuthRequired,
+    Unreachable(String),
+}
+
+impl Display for MirrorStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MirrorStatus::Exists { digest, size, platforms } => {
+                write!(f, "exists, digest: {}", digest)?;
+                if let Some(size) = size {
+                    write!(f, ", size: {}", HumanBytes(*size))?;
+                }
+                let platforms: Vec<String> = platforms.iter().map(|platform| platform.to_string()).collect();
+                write!(f, ", platforms: [{}]", platforms.join(", "))
+            }
+            MirrorStatus::Missing => write!(f, "missing"),
+            MirrorStatus::AuthRequired => write!(f, "auth required"),
+            MirrorStatus::Unreachable(error) => write!(f, "unreachable, {}", error),
+        }
+    }
+}
 
 #[async_trait::async_trait]
 impl RushGetTask for DockerCheckTask {
     async fn run(self) -> Result<(), DockermirError> {
-        let mirror_image = map_mirror_by_configuration(&self.image, &self.config);
-        match mirror_image {
-            Ok(mirror_image) => {
-                info!("Image match, it will be pull from mirror: {}", &mirror_image.mirror_image);
-                trace!("Image: {} is matched with ruleset: {:?}, rule: {:?}", self.image, &mirror_image.hit_ruleset, &mirror_image.hit_rule);
-                Ok(())
+        let candidates = match map_mirror_candidates(&self.image, &self.config) {
+            Ok(candidates) if !candidates.is_empty() => candidates,
+            Ok(_) | Err(DockermirError::MismatchAllRule) => {
+                error!("Image: {} is not matched with any ruleset", self.image);
+                return Err(DockermirError::MismatchAllRule);
+            }
+            Err(e) => return Err(e),
+        };
+        info!("Image match, it will be pull from mirror: {}", candidates[0].mirror_image);
+        for (index, candidate) in candidates.iter().enumerate() {
+            trace!("Image: {} is matched with ruleset: {:?}, rule: {:?}", self.image, candidate.hit_ruleset, candidate.hit_rule);
+            if index > 0 {
+                info!("Fallback mirror: {}, ruleset: {}, rule: {}", candidate.mirror_image, candidate.hit_ruleset.name, candidate.hit_rule.name);
+            }
+        }
+        if self.options.offline {
+            return Ok(());
+        }
+
+        let resolver = CredentialResolver::new();
+        let source_reference = ImageReference::parse(&self.image)?;
+        let upstream_digest = match &source_reference.digest {
+            Some(digest) => Some(digest.clone()),
+            None => {
+                let credential = resolver.resolve(&source_reference.registry, None).await?;
+                let client = RegistryClient::new().with_credential(&source_reference.registry, credential);
+                match client.fetch_manifest_digest(&source_reference).await {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        warn!("Upstream {} is not reachable, the mirrors are not compared with it, error: {}", source_reference, e);
+                        None
+                    }
+                }
+            }
+        };
+
+        let mut serving = 0;
+        for candidate in &candidates {
+            let mirror_reference = ImageReference::parse(&candidate.mirror_image)?;
+            let credential = match resolver.resolve(&mirror_reference.registry, candidate.hit_ruleset.auth.as_ref()).await {
+                Ok(credential) => credential,
+                Err(e) => {
+                    warn!("Mirror {} ({}): {}", candidate.mirror_image, candidate.hit_ruleset.name, e);
+                    continue;
+                }
+            };
+            let client = RegistryClient::new().with_credential(&mirror_reference.registry, credential);
+            let status = check_mirror(&client, &mirror_reference).await;
+            let MirrorStatus::Exists { digest, platforms, .. } = &status else {
+                warn!("Mirror {} ({}): {}", candidate.mirror_image, candidate.hit_ruleset.name, status);
+                continue;
+            };
+            info!("Mirror {} ({}): {}", candidate.mirror_image, candidate.hit_ruleset.name, status);
+            match &upstream_digest {
+                Some(upstream) if upstream == digest => info!("Mirror {} is up to date with upstream", candidate.mirror_image),
+                Some(upstream) => warn!("Mirror {} differs from upstream {}, upstream digest: {}", candidate.mirror_image, source_reference, upstream),
+                None => {}
             }
-            Err(e) => {
-                error!("Image: {} is not matched with any ruleset, error: {}", self.image, e);
-                Err(e)
+            if let Some(platform) = &self.options.platform {
+                if !platforms.is_empty() && !platforms.iter().any(|available| platform.matches(available)) {
+                    warn!("Mirror {} has no {} image", candidate.mirror_image, platform);
+                    continue;
+                }
             }
+            serving += 1;
+        }
+        if serving == 0 {
+            return Err(DockermirError::MirrorImageMissing { source_image: self.image });
         }
+        Ok(())
     }
 }
 
+/// HEAD the manifest of the mirror, then fetch it for the platforms and the size when it is there
+pub(crate) async fn check_mirror(client: &RegistryClient, reference: &ImageReference) -> MirrorStatus {
+    let status = |e: DockermirError| match e {
+        DockermirError::RegistryNotFound { .. } => MirrorStatus::Missing,
+        DockermirError::RegistryUnauthorized { .. } => MirrorStatus::AuthRequired,
+        e => MirrorStatus::Unreachable(e.to_string()),
+    };
+    if let Err(e) = client.fetch_manifest_digest(reference).await {
+        return status(e);
+    }
+    let manifest = match client.fetch_manifest(reference).await {
+        Ok(manifest) => manifest,
+        Err(e) => return status(e),
+    };
+    let details = if manifest.is_index() {
+        manifest.as_index().map(|index| {
+            (None, index.manifests.into_iter().filter_map(|descriptor| descriptor.platform).collect())
+        })
+    } else {
+        match manifest.as_image() {
+            Ok(image) => {
+                let size = image.config.size + image.layers.iter().map(|layer| layer.size).sum::<u64>();
+                let platforms = match client.fetch_image_platform(reference, &image).await {
+                    Ok(platform) => vec![platform],
+                    Err(e) => {
+                        trace!("Failed to read the platform of {}, error: {}", reference, e);
+                        vec![]
+                    }
+                };
+                Ok((Some(size), platforms))
+            }
+            Err(e) => Err(e),
+        }
+    };
+    match details {
+        Ok((size, platforms)) => MirrorStatus::Exists {
+            digest: manifest.digest,
+            size,
+            platforms,
+        },
+        Err(e) => status(e),
+    }
+}
+
+
+pub(crate) fn map_mirror_by_configuration(source: &str, config: &RushGetConfig) -> anyhow::Result<ImageMirrorData, DockermirError> {
+    map_mirror_candidates(source, config)?
+        .into_iter()
+        .next()
+        .ok_or(DockermirError::MismatchAllRule)
+}
+
+/// Map the image with every matched rule, in the order of rulesets and rules in the configuration
+fn map_mirror_candidates(source: &str, config: &RushGetConfig) -> anyhow::Result<Vec<ImageMirrorData>, DockermirError> {
+    // Rules are matched against the canonical form, so `nginx` and `docker.io/library/nginx:latest` hit the same rule.
+    // The digest is left out of the matched form and carried over to the mirror reference after the rule is applied.
+    let source_reference = ImageReference::parse(source)?;
+    let canonical = source_reference.without_digest().to_string();
+    trace!("canonical form of image {} is {}", source, canonical);
 
-fn map_mirror_by_configuration(source: &str, config: &RushGetConfig) -> anyhow::Result<ImageMirrorData, DockermirError> {
     // Get the ruleset from the configuration
     let ruleset = &config.docker.ruleset;
 
     // Create a regex object for each rule in the ruleset
+    let mut candidates = vec![];
     for ruleset in ruleset {
         for rule in &ruleset.rules {
             let regex = Regex::new(&rule.match_regex).unwrap();
-            if regex.is_match(&source) {
+            if regex.is_match(&canonical) {
                 let replacement = rule.replace_template.clone();
                 // replace ${mirror_host} into the mirror host
                 // replace ${mirror_namespace} into the mirror namespace
                 let replacement = replacement.replace("${mirror_host}", &ruleset.mirror_host);
                 let replacement = replacement.replace("${mirror_namespace}", &ruleset.mirror_namespace);
-                let mirror = regex.replace(&source, replacement).to_string();
-                return Ok(ImageMirrorData {
+                // replace ${registry}, ${repository}, ${tag} and ${digest} from the canonical reference
+                let replacement = source_reference.render_template(&replacement);
+                let mirror = regex.replace(&canonical, replacement).to_string();
+                let mirror = match &source_reference.digest {
+                    Some(digest) if !mirror.contains(digest.as_str()) => ImageReference::parse(&mirror)?.with_digest(digest).to_string(),
+                    _ => mirror,
+                };
+                candidates.push(ImageMirrorData {
                     hit_rule: rule.clone(),
                     hit_ruleset: ruleset.clone(),
                     source_image: source.to_string(),
+                    source_reference: source_reference.clone(),
                     mirror_image: mirror,
                 });
             } else {
-                trace!("Rule {} does not match image {}, regex: {}", rule.name, source, rule.match_regex);
+                trace!("Rule {} in ruleset {} does not match image {}, regex: {}", rule.name, ruleset.name, canonical, rule.match_regex);
             }
         }
     }
-    return Err(DockermirError::MismatchAllRule);
+    Ok(candidates)
 }
 
 pub(crate) struct ImageMirrorData {
     pub hit_rule: DockerMirrorRule,
     pub hit_ruleset: DockerMirrorRuleset,
     pub source_image: String,
+    pub source_reference: ImageReference,
     pub mirror_image: String,
 }
+
+impl ImageMirrorData {
+    /// The name to tag the pulled image with, none if the source is pinned by digest only
+    pub(crate) fn tag_target(&self) -> Option<String> {
+        match (&self.source_reference.digest, &self.source_reference.tag) {
+            (None, _) => Some(self.source_image.clone()),
+            (Some(_), Some(_)) => Some(self.source_reference.without_digest().to_string()),
+            (Some(_), None) => None,
+        }
+    }
+
+    /// The name of the image in the local image store after the pull
+    pub(crate) fn local_image(&self) -> String {
+        self.tag_target().unwrap_or_else(|| self.mirror_image.clone())
+    }
+}
@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use serde_yaml::Value;
+use crate::error::DockermirError;
+
+const COMPOSE_PROFILES_ENV: &str = "COMPOSE_PROFILES";
+
+/// Load the images of all services in the compose file which are enabled by the profiles.
+/// Variables are resolved from the environment first and then from the `.env` file next to the compose file.
+pub(crate) fn load_compose_images(path: &Path, profiles: &[String]) -> Result<Vec<String>, DockermirError> {
+    let compose_error = |error: String| DockermirError::ComposeFileError {
+        path: path.display().to_string(),
+        error,
+    };
+    let content = fs::read_to_string(path).map_err(|e| compose_error(e.to_string()))?;
+    let dot_env = path.parent()
+        .map(|dir| dir.join(".env"))
+        .filter(|dot_env| dot_env.exists())
+        .map(|dot_env| fs::read_to_string(dot_env).map(|content| parse_dot_env(&content)))
+        .transpose()
+        .map_err(|e| compose_error(e.to_string()))?
+        .unwrap_or_default();
+    let lookup = |name: &str| std::env::var(name).ok().or_else(|| dot_env.get(name).cloned());
+
+    let mut profiles = profiles.to_vec();
+    if let Some(env_profiles) = lookup(COMPOSE_PROFILES_ENV) {
+        profiles.extend(env_profiles.split(',').map(|profile| profile.trim().to_string()).filter(|profile| !profile.is_empty()));
+    }
+    compose_images(&content, &profiles, &lookup).map_err(compose_error)
+}
+
+fn compose_images(content: &str, profiles: &[String], lookup: &dyn Fn(&str) -> Option<String>) -> Result<Vec<String>, String> {
+    let compose: Value = serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+    let services = compose.get("services")
+        .and_then(|services| services.as_mapping())
+        .ok_or_else(|| "no services found".to_string())?;
+
+    let mut images: Vec<String> = vec![];
+    for (name, service) in services {
+        let name = name.as_str().unwrap_or_default();
+        let service_profiles: Vec<String> = service.get("profiles")
+            .and_then(|profiles| profiles.as_sequence())
+            .map(|profiles| profiles.iter()
+                .filter_map(|profile| profile.as_str())
+                .map(|profile| interpolate(profile, lookup))
+                .collect::<Result<Vec<_>, _>>())
+            .transpose()?
+            .unwrap_or_default();
+        // services without profiles are always enabled, others only when one of their profiles is active
+        let enabled = service_profiles.is_empty()
+            || profiles.iter().any(|profile| profile == "*" || service_profiles.contains(profile));
+        if !enabled {
+            trace!("Service {} is skipped, profiles: {:?}", name, service_profiles);
+            continue;
+        }
+        let Some(image) = service.get("image").and_then(|image| image.as_str()) else {
+            trace!("Service {} has no image, skip", name);
+            continue;
+        };
+        let image = interpolate(image, lookup).map_err(|e| format!("service {}: {}", name, e))?;
+        if image.is_empty() {
+            return Err(format!("service {}: image is empty after interpolation", name));
+        }
+        if !images.contains(&image) {
+            images.push(image);
+        }
+    }
+    Ok(images)
+}
+
+/// Interpolate `$VAR`, `${VAR}`, `${VAR:-default}`, `${VAR-default}`, `${VAR:?error}`, `${VAR?error}`,
+/// `${VAR:+replacement}` and `${VAR+replacement}` like compose does, `$$` is an escaped `$`
+pub(crate) fn interpolate(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = value.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some((_, '$')) => {
+                chars.next();
+                result.push('$');
+            }
+            Some((start, '{')) => {
+                let start = start + 1;
+                // find the matching brace, defaults may contain nested variables
+                let mut depth = 1;
+                let mut end = None;
+                for (i, c) in value[start..].char_indices() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                end = Some(start + i);
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or_else(|| format!("unclosed variable in: {}", value))?;
+                result.push_str(&interpolate_braced(&value[start..end], lookup)?);
+                while chars.peek().map(|(i, _)| *i <= end).unwrap_or(false) {
+                    chars.next();
+                }
+            }
+            Some((_, c)) if c.is_ascii_alphabetic() || *c == '_' => {
+                let start = index + 1;
+                let mut end = start;
+                while let Some((i, c)) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || *c == '_' {
+                        end = i + c.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                result.push_str(&lookup(&value[start..end]).unwrap_or_default());
+            }
+            _ => result.push('$'),
+        }
+    }
+    Ok(result)
+}
+
+fn interpolate_braced(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let name_end = expression.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(expression.len());
+    let (name, modifier) = expression.split_at(name_end);
+    if name.is_empty() {
+        return Err(format!("invalid variable: ${{{}}}", expression));
+    }
+    let value = lookup(name);
+    let set_and_not_empty = value.as_ref().map(|value| !value.is_empty()).unwrap_or(false);
+    let resolved = if let Some(default) = modifier.strip_prefix(":-") {
+        if set_and_not_empty { value.unwrap() } else { interpolate(default, lookup)? }
+    } else if let Some(default) = modifier.strip_prefix('-') {
+        match value {
+            Some(value) => value,
+            None => interpolate(default, lookup)?,
+        }
+    } else if let Some(error) = modifier.strip_prefix(":?") {
+        if set_and_not_empty { value.unwrap() } else { return Err(format!("required variable {} is missing: {}", name, error)); }
+    } else if let Some(error) = modifier.strip_prefix('?') {
+        value.ok_or_else(|| format!("required variable {} is missing: {}", name, error))?
+    } else if let Some(replacement) = modifier.strip_prefix(":+") {
+        if set_and_not_empty { interpolate(replacement, lookup)? } else { String::new() }
+    } else if let Some(replacement) = modifier.strip_prefix('+') {
+        if value.is_some() { interpolate(replacement, lookup)? } else { String::new() }
+    } else if modifier.is_empty() {
+        value.unwrap_or_default()
+    } else {
+        return Err(format!("invalid variable: ${{{}}}", expression));
+    };
+    Ok(resolved)
+}
+
+fn parse_dot_env(content: &str) -> HashMap<String, String> {
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("export ").unwrap_or(line).split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
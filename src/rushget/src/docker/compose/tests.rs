@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use rstest::*;
+use super::*;
+
+pub const COMPOSE_YAML: &str = include_str!("docker-compose.yml");
+
+fn lookup_from(variables: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let variables: HashMap<String, String> = variables.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    move |name| variables.get(name).cloned()
+}
+
+#[rstest]
+#[case("plain", "plain")]
+#[case("$$escaped", "$escaped")]
+#[case("$TAG", "6.0")]
+#[case("${TAG}-alpine", "6.0-alpine")]
+#[case("${MISSING:-default}", "default")]
+#[case("${EMPTY:-default}", "default")]
+#[case("${EMPTY-default}", "")]
+#[case("${MISSING-${TAG}}", "6.0")]
+#[case("${TAG:+set}", "set")]
+#[case("${MISSING+set}", "")]
+#[case("${MISSING}", "")]
+fn interpolate_variables(#[case] value: &str, #[case] expected: &str) {
+    let lookup = lookup_from(&[("TAG", "6.0"), ("EMPTY", "")]);
+    assert_eq!(interpolate(value, &lookup).unwrap(), expected);
+}
+
+#[rstest]
+#[case("${MISSING:?must be set}")]
+#[case("${EMPTY:?must not be empty}")]
+#[case("${TAG")]
+fn interpolate_errors(#[case] value: &str) {
+    let lookup = lookup_from(&[("TAG", "6.0"), ("EMPTY", "")]);
+    assert!(interpolate(value, &lookup).is_err());
+}
+
+#[rstest]
+fn images_without_profiles() {
+    let lookup = lookup_from(&[("DOTNET_VERSION", "6.0"), ("MSSQL_TAG", "2019-latest")]);
+    let images = compose_images(COMPOSE_YAML, &[], &lookup).unwrap();
+    assert_eq!(images, vec![
+        "mcr.microsoft.com/dotnet/aspnet:6.0",
+        "mcr.microsoft.com/dotnet/runtime:6.0",
+        "mcr.microsoft.com/mssql/server:2019-latest",
+    ]);
+}
+
+#[rstest]
+#[case(&["debug"], &["mcr.microsoft.com/dotnet/sdk:6.0", "redis:7"])]
+#[case(&["cache"], &["redis:7"])]
+#[case(&["*"], &["mcr.microsoft.com/dotnet/sdk:6.0", "redis:7"])]
+fn images_with_profiles(#[case] profiles: &[&str], #[case] extra: &[&str]) {
+    let lookup = lookup_from(&[("DOTNET_VERSION", "6.0"), ("MSSQL_TAG", "2019-latest")]);
+    let profiles: Vec<String> = profiles.iter().map(|profile| profile.to_string()).collect();
+    let images = compose_images(COMPOSE_YAML, &profiles, &lookup).unwrap();
+    assert_eq!(&images[3..], extra);
+}
+
+#[rstest]
+fn load_with_dot_env() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("docker-compose.yml");
+    std::fs::write(&path, COMPOSE_YAML).unwrap();
+    std::fs::write(dir.path().join(".env"), "# versions\nDOTNET_VERSION=7.0\nMSSQL_TAG=\"2022-latest\"\n").unwrap();
+    let images = load_compose_images(&path, &[]).unwrap();
+    assert_eq!(images[1], "mcr.microsoft.com/dotnet/runtime:7.0");
+    assert_eq!(images[2], "mcr.microsoft.com/mssql/server:2022-latest");
+}
+
+#[rstest]
+fn load_missing_file() {
+    let result = load_compose_images(Path::new("not-exists/docker-compose.yml"), &[]);
+    assert!(matches!(result, Err(DockermirError::ComposeFileError { .. })));
+}
@@ -0,0 +1,357 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use similar::TextDiff;
+use crate::components::config::RushGetConfig;
+use crate::components::RushGetTask;
+use crate::docker::reference::DOCKER_HUB_REGISTRY;
+use crate::error::DockermirError;
+
+const DOCKER_DAEMON_JSON: &str = "etc/docker/daemon.json";
+const CONTAINERD_CERTS_D: &str = "etc/containerd/certs.d";
+const PODMAN_REGISTRIES_CONF: &str = "etc/containers/registries.conf";
+const BACKUP_SUFFIX: &str = "rg-backup";
+/// Marks files which did not exist before, so revert removes them instead of restoring a backup
+const CREATED_SUFFIX: &str = "rg-created";
+const BLOCK_BEGIN: &str = "# BEGIN rg docker configure";
+const BLOCK_END: &str = "# END rg docker configure";
+
+/// The container runtime whose native mirror configuration is generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ContainerRuntime {
+    /// `registry-mirrors` in daemon.json, docker only mirrors docker hub
+    Docker,
+    /// `hosts.toml` in certs.d, one directory per registry
+    Containerd,
+    /// `[[registry.mirror]]` in registries.conf, also used by cri-o and buildah
+    Podman,
+}
+
+impl Display for ContainerRuntime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerRuntime::Docker => write!(f, "docker"),
+            ContainerRuntime::Containerd => write!(f, "containerd"),
+            ContainerRuntime::Podman => write!(f, "podman"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DockerConfigureOptions {
+    pub(crate) runtime: ContainerRuntime,
+    /// The address of `rg docker serve`, which maps the images through the rulesets
+    pub(crate) proxy: String,
+    /// Prefix of the configuration paths, e.g. the root of a mounted image
+    pub(crate) root: PathBuf,
+    /// Only show the diff
+    pub(crate) dry_run: bool,
+    /// Restore the files from their backups
+    pub(crate) revert: bool,
+}
+
+/// Point the mirror configuration of the container runtime at `rg docker serve`,
+/// so that every pull of the runtime goes through the mirrors of the rulesets
+pub(crate) struct DockerConfigureTask {
+    config: RushGetConfig,
+    options: DockerConfigureOptions,
+}
+
+impl DockerConfigureTask {
+    pub(crate) fn new(config: RushGetConfig, options: DockerConfigureOptions) -> Self {
+        DockerConfigureTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerConfigureTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        if self.options.revert {
+            return revert(self.options.runtime, &self.options.root, self.options.dry_run);
+        }
+        let registries = mirrored_registries(&self.config);
+        if registries.is_empty() {
+            warn!("No registry can be derived from the rules of the config, nothing to configure");
+            return Ok(());
+        }
+        info!("Configure {} to pull {} through {}", self.options.runtime,
+            registries.iter().cloned().collect::<Vec<_>>().join(", "), self.options.proxy);
+        let changes = plan(self.options.runtime, &registries, &self.options.proxy, &self.options.root)?;
+        for change in &changes {
+            print!("{}", change.diff());
+        }
+        if changes.is_empty() {
+            info!("The configuration of {} is up to date", self.options.runtime);
+        } else if self.options.dry_run {
+            info!("Dry run, {} files are not written", changes.len());
+        } else {
+            for change in &changes {
+                change.apply()?;
+            }
+            info!("Written {} files, restart {} to load them, `--revert` restores the backups", changes.len(), self.options.runtime);
+        }
+        if self.options.runtime == ContainerRuntime::Containerd {
+            info!("containerd reads hosts.toml only when `config_path = \"/etc/containerd/certs.d\"` is set for its registry");
+        }
+        Ok(())
+    }
+}
+
+/// A file whose content is changed
+#[derive(Debug)]
+pub(crate) struct FileChange {
+    pub(crate) path: PathBuf,
+    pub(crate) old: Option<String>,
+    pub(crate) new: String,
+}
+
+impl FileChange {
+    pub(crate) fn diff(&self) -> String {
+        let old = self.old.as_deref().unwrap_or_default();
+        let path = self.path.display().to_string();
+        TextDiff::from_lines(old, &self.new)
+            .unified_diff()
+            .header(if self.old.is_some() { &path } else { "/dev/null" }, &path)
+            .to_string()
+    }
+
+    /// Write the file, keeping the first backup so that revert restores the state before rg touched it
+    pub(crate) fn apply(&self) -> Result<(), DockermirError> {
+        let configure_error = |e: std::io::Error| DockermirError::RuntimeConfigureError {
+            path: self.path.display().to_string(),
+            error: e.to_string(),
+        };
+        let backup = sibling(&self.path, BACKUP_SUFFIX);
+        let created = sibling(&self.path, CREATED_SUFFIX);
+        if !backup.exists() && !created.exists() {
+            match &self.old {
+                Some(_) => fs::copy(&self.path, &backup).map(|_| ()),
+                None => {
+                    if let Some(parent) = self.path.parent() {
+                        fs::create_dir_all(parent).map_err(configure_error)?;
+                    }
+                    fs::write(&created, "")
+                }
+            }.map_err(configure_error)?;
+        }
+        fs::write(&self.path, &self.new).map_err(configure_error)?;
+        info!("Written {}", self.path.display());
+        Ok(())
+    }
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), suffix))
+}
+
+/// The registries the rules apply to, taken from the literal host at the start of each `match_regex`
+pub(crate) fn mirrored_registries(config: &RushGetConfig) -> BTreeSet<String> {
+    let mut registries = BTreeSet::new();
+    for ruleset in &config.docker.ruleset {
+        for rule in &ruleset.rules {
+            match registry_of_regex(&rule.match_regex) {
+                Some(registry) => {
+                    registries.insert(registry);
+                }
+                None => warn!("Rule {} in ruleset {} does not start with a registry host, skip it: {}",
+                    rule.name, ruleset.name, rule.match_regex),
+            }
+        }
+    }
+    registries
+}
+
+/// `mcr\.microsoft\.com/dotnet/(.*)` is `mcr.microsoft.com`, None if the host part is not a literal
+fn registry_of_regex(regex: &str) -> Option<String> {
+    let regex = regex.strip_prefix('^').unwrap_or(regex);
+    let mut host = String::new();
+    let mut chars = regex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' => break,
+            '\\' => host.push(chars.next()?),
+            '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '$' | '^' => return None,
+            _ => host.push(c),
+        }
+    }
+    let is_host = host.contains('.') || host.contains(':') || host == "localhost";
+    is_host.then_some(host)
+}
+
+/// The files to change, files which are up to date are left out
+pub(crate) fn plan(runtime: ContainerRuntime, registries: &BTreeSet<String>, proxy: &str, root: &Path)
+                   -> Result<Vec<FileChange>, DockermirError> {
+    let proxy = proxy.trim_end_matches('/');
+    let proxy_url = if proxy.contains("://") { proxy.to_string() } else { format!("http://{}", proxy) };
+    let proxy_host = proxy_url.split_once("://").map(|(_, host)| host).unwrap_or(proxy);
+    let mut changes = vec![];
+    match runtime {
+        ContainerRuntime::Docker => {
+            if registries.iter().any(|registry| registry != DOCKER_HUB_REGISTRY) {
+                warn!("docker only mirrors docker hub, use `rg docker pull` or containerd for the other registries");
+            }
+            if registries.contains(DOCKER_HUB_REGISTRY) {
+                let path = root.join(DOCKER_DAEMON_JSON);
+                let old = read_existing(&path)?;
+                let new = docker_daemon_json(old.as_deref(), &proxy_url)
+                    .map_err(|error| DockermirError::RuntimeConfigureError { path: path.display().to_string(), error })?;
+                changes.push(FileChange { path, old, new });
+            }
+        }
+        ContainerRuntime::Containerd => {
+            for registry in registries {
+                let path = root.join(CONTAINERD_CERTS_D).join(registry).join("hosts.toml");
+                let old = read_existing(&path)?;
+                changes.push(FileChange { path, old, new: containerd_hosts_toml(registry, &proxy_url) });
+            }
+        }
+        ContainerRuntime::Podman => {
+            let path = root.join(PODMAN_REGISTRIES_CONF);
+            let old = read_existing(&path)?;
+            let new = podman_registries_conf(old.as_deref(), registries, proxy_host, proxy_url.starts_with("http://"));
+            changes.push(FileChange { path, old, new });
+        }
+    }
+    changes.retain(|change| change.old.as_deref() != Some(change.new.as_str()));
+    Ok(changes)
+}
+
+fn read_existing(path: &Path) -> Result<Option<String>, DockermirError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(path).map(Some).map_err(|e| DockermirError::RuntimeConfigureError {
+        path: path.display().to_string(),
+        error: e.to_string(),
+    })
+}
+
+/// Add the proxy to `registry-mirrors`, the other settings of the daemon are kept as they are
+fn docker_daemon_json(old: Option<&str>, proxy_url: &str) -> Result<String, String> {
+    let mut daemon: Value = match old {
+        Some(old) if !old.trim().is_empty() => serde_json::from_str(old).map_err(|e| e.to_string())?,
+        _ => Value::Object(Default::default()),
+    };
+    let daemon_object = daemon.as_object_mut().ok_or_else(|| "daemon.json is not an object".to_string())?;
+    let mirrors = daemon_object.entry("registry-mirrors").or_insert_with(|| Value::Array(vec![]));
+    let mirrors = mirrors.as_array_mut().ok_or_else(|| "registry-mirrors is not an array".to_string())?;
+    if !mirrors.iter().any(|mirror| mirror.as_str() == Some(proxy_url)) {
+        // the proxy goes first, the mirrors which were there before stay as fallbacks
+        mirrors.insert(0, Value::String(proxy_url.to_string()));
+    }
+    Ok(serde_json::to_string_pretty(&daemon).map_err(|e| e.to_string())? + "\n")
+}
+
+fn containerd_hosts_toml(registry: &str, proxy_url: &str) -> String {
+    let server = if registry == DOCKER_HUB_REGISTRY { "https://registry-1.docker.io".to_string() } else { format!("https://{}", registry) };
+    format!(r#"# generated by rg docker configure, the mirrors of {registry} are served by rg docker serve
+server = "{server}"
+
+[host."{proxy_url}"]
+  capabilities = ["pull", "resolve"]
+"#)
+}
+
+/// Replace the block of rg at the end of registries.conf, everything outside of the block is kept
+fn podman_registries_conf(old: Option<&str>, registries: &BTreeSet<String>, proxy_host: &str, insecure: bool) -> String {
+    let old = old.unwrap_or_default();
+    let mut content = match (old.find(BLOCK_BEGIN), old.find(BLOCK_END)) {
+        (Some(begin), Some(end)) if begin < end => {
+            let after = &old[end + BLOCK_END.len()..];
+            format!("{}{}", &old[..begin], after.strip_prefix('\n').unwrap_or(after))
+        }
+        _ => old.to_string(),
+    };
+    for registry in registries {
+        if content.contains(&format!("prefix = \"{}\"", registry)) || content.contains(&format!("location = \"{}\"", registry)) {
+            warn!("registries.conf has an entry of {} already, podman refuses duplicated entries", registry);
+        }
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(BLOCK_BEGIN);
+    content.push('\n');
+    for registry in registries {
+        content.push_str(&format!(r#"[[registry]]
+prefix = "{registry}"
+location = "{registry}"
+
+[[registry.mirror]]
+location = "{proxy_host}/{registry}"
+insecure = {insecure}
+
+"#));
+    }
+    content.push_str(BLOCK_END);
+    content.push('\n');
+    content
+}
+
+/// The files of the runtime which rg may have written
+fn configured_files(runtime: ContainerRuntime, root: &Path) -> Vec<PathBuf> {
+    match runtime {
+        ContainerRuntime::Docker => vec![root.join(DOCKER_DAEMON_JSON)],
+        ContainerRuntime::Podman => vec![root.join(PODMAN_REGISTRIES_CONF)],
+        ContainerRuntime::Containerd => {
+            let mut files: Vec<PathBuf> = fs::read_dir(root.join(CONTAINERD_CERTS_D)).into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path().join("hosts.toml"))
+                .collect();
+            files.sort();
+            files
+        }
+    }
+}
+
+/// Restore the backups, files which rg created are removed
+pub(crate) fn revert(runtime: ContainerRuntime, root: &Path, dry_run: bool) -> Result<(), DockermirError> {
+    let mut reverted = 0;
+    for path in configured_files(runtime, root) {
+        let configure_error = |e: std::io::Error| DockermirError::RuntimeConfigureError {
+            path: path.display().to_string(),
+            error: e.to_string(),
+        };
+        let backup = sibling(&path, BACKUP_SUFFIX);
+        let created = sibling(&path, CREATED_SUFFIX);
+        let current = read_existing(&path)?;
+        let change = if backup.exists() {
+            FileChange { path: path.clone(), old: current, new: fs::read_to_string(&backup).map_err(configure_error)? }
+        } else if created.exists() {
+            FileChange { path: path.clone(), old: current, new: String::new() }
+        } else {
+            continue;
+        };
+        print!("{}", change.diff());
+        reverted += 1;
+        if dry_run {
+            continue;
+        }
+        if backup.exists() {
+            fs::rename(&backup, &path).map_err(configure_error)?;
+        } else {
+            if path.exists() {
+                fs::remove_file(&path).map_err(configure_error)?;
+            }
+            fs::remove_file(&created).map_err(configure_error)?;
+            // the directory of the registry was created for the hosts.toml only
+            if let Some(parent) = path.parent().filter(|_| runtime == ContainerRuntime::Containerd) {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        info!("Reverted {}", path.display());
+    }
+    if reverted == 0 {
+        info!("Nothing to revert for {}", runtime);
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::ConfigLoader;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn registries() -> BTreeSet<String> {
+    let config = ConfigLoader::default().load_config_yaml(include_str!("../structured.yaml")).unwrap();
+    mirrored_registries(&config)
+}
+
+#[rstest]
+#[case("^docker\\.io/library/.*", Some("docker.io"))]
+#[case("mcr\\.microsoft\\.com/dotnet/(.*):(.*)", Some("mcr.microsoft.com"))]
+#[case("localhost:5000/app", Some("localhost:5000"))]
+#[case("^127\\.0\\.0\\.1:[0-9]+/dotnet/(.*)", None)]
+#[case("library/(.*)", None)]
+#[case("(.*)", None)]
+fn registry_of_regexes(_init_logger: (), #[case]regex: &str, #[case]expected: Option<&str>) {
+    assert_eq!(registry_of_regex(regex).as_deref(), expected);
+}
+
+#[rstest]
+fn docker_keeps_other_settings(_init_logger: ()) {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join(DOCKER_DAEMON_JSON);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, r#"{"log-driver": "json-file", "registry-mirrors": ["https://mirror.example.com"]}"#).unwrap();
+
+    let changes = plan(ContainerRuntime::Docker, &registries(), "http://127.0.0.1:5000", root.path()).unwrap();
+    assert_eq!(changes.len(), 1);
+    let daemon: Value = serde_json::from_str(&changes[0].new).unwrap();
+    assert_eq!(daemon["log-driver"], "json-file");
+    assert_eq!(daemon["registry-mirrors"], serde_json::json!(["http://127.0.0.1:5000", "https://mirror.example.com"]));
+    assert!(changes[0].diff().contains("+    \"http://127.0.0.1:5000\","));
+
+    changes[0].apply().unwrap();
+    let changes = plan(ContainerRuntime::Docker, &registries(), "http://127.0.0.1:5000", root.path()).unwrap();
+    assert!(changes.is_empty());
+}
+
+#[rstest]
+fn containerd_writes_hosts_toml(_init_logger: ()) {
+    let root = tempfile::tempdir().unwrap();
+    let changes = plan(ContainerRuntime::Containerd, &registries(), "127.0.0.1:5000", root.path()).unwrap();
+    assert_eq!(changes.len(), 2);
+    for change in &changes {
+        assert!(change.old.is_none());
+        change.apply().unwrap();
+    }
+
+    let hub = fs::read_to_string(root.path().join(CONTAINERD_CERTS_D).join("docker.io/hosts.toml")).unwrap();
+    assert!(hub.contains("server = \"https://registry-1.docker.io\""));
+    assert!(hub.contains("[host.\"http://127.0.0.1:5000\"]"));
+    let mcr = fs::read_to_string(root.path().join(CONTAINERD_CERTS_D).join("mcr.microsoft.com/hosts.toml")).unwrap();
+    assert!(mcr.contains("server = \"https://mcr.microsoft.com\""));
+
+    revert(ContainerRuntime::Containerd, root.path(), false).unwrap();
+    assert!(!root.path().join(CONTAINERD_CERTS_D).join("docker.io").exists());
+    assert!(!root.path().join(CONTAINERD_CERTS_D).join("mcr.microsoft.com").exists());
+}
+
+#[rstest]
+fn podman_replaces_its_block(_init_logger: ()) {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join(PODMAN_REGISTRIES_CONF);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let original = "unqualified-search-registries = [\"docker.io\"]\n";
+    fs::write(&path, original).unwrap();
+
+    let changes = plan(ContainerRuntime::Podman, &registries(), "http://127.0.0.1:5000", root.path()).unwrap();
+    changes[0].apply().unwrap();
+    let changes = plan(ContainerRuntime::Podman, &registries(), "http://127.0.0.1:6000", root.path()).unwrap();
+    changes[0].apply().unwrap();
+
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with(original));
+    assert_eq!(content.matches(BLOCK_BEGIN).count(), 1);
+    assert!(content.contains("location = \"127.0.0.1:6000/mcr.microsoft.com\"\ninsecure = true"));
+    assert!(!content.contains("127.0.0.1:5000"));
+
+    // the first backup is kept, so revert goes back to the file before rg
+    revert(ContainerRuntime::Podman, root.path(), false).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+}
+
+#[rstest]
+fn dry_run_revert_keeps_files(_init_logger: ()) {
+    let root = tempfile::tempdir().unwrap();
+    let changes = plan(ContainerRuntime::Docker, &registries(), "http://127.0.0.1:5000", root.path()).unwrap();
+    assert!(changes[0].diff().starts_with("--- /dev/null"));
+    changes[0].apply().unwrap();
+
+    let path = root.path().join(DOCKER_DAEMON_JSON);
+    revert(ContainerRuntime::Docker, root.path(), true).unwrap();
+    assert!(path.exists());
+    revert(ContainerRuntime::Docker, root.path(), false).unwrap();
+    assert!(!path.exists());
+}
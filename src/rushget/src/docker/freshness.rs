@@ -0,0 +1,140 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use crate::components::config::RushGetConfig;
+use crate::components::credentials::CredentialResolver;
+use crate::components::registry::RegistryClient;
+use crate::components::RushGetTask;
+use crate::docker::map_mirror_candidates;
+use crate::docker::reference::ImageReference;
+use crate::docker::sync::read_image_list;
+use crate::error::DockermirError;
+
+/// Compare the digests of the mirrors with upstream, moving tags like `sdk:8.0` drift when a mirror is not synced
+pub(crate) struct DockerFreshnessTask {
+    config: RushGetConfig,
+    /// Images, or files with one image per line
+    inputs: Vec<String>,
+}
+
+impl DockerFreshnessTask {
+    pub(crate) fn new(config: RushGetConfig, inputs: Vec<String>) -> Self {
+        DockerFreshnessTask {
+            config,
+            inputs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Freshness {
+    Fresh,
+    Stale {
+        mirror_digest: String,
+        upstream_digest: String,
+    },
+    MirrorMissing,
+    /// Upstream or the mirror could not be asked
+    Unknown(String),
+}
+
+impl Display for Freshness {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Freshness::Fresh => write!(f, "fresh"),
+            Freshness::Stale { mirror_digest, upstream_digest } => write!(f, "stale, mirror: {}, upstream: {}", mirror_digest, upstream_digest),
+            Freshness::MirrorMissing => write!(f, "missing in mirror"),
+            Freshness::Unknown(reason) => write!(f, "unknown, {}", reason),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerFreshnessTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let mut images = vec![];
+        for input in &self.inputs {
+            if Path::new(input).is_file() {
+                images.extend(read_image_list(Path::new(input))?);
+            } else {
+                images.push(input.to_owned());
+            }
+        }
+        let resolver = CredentialResolver::new();
+        let mut total = 0;
+        let mut stale = 0;
+        for image in &images {
+            let source = ImageReference::parse(image)?;
+            let source_client = RegistryClient::new()
+                .with_credential(&source.registry, resolver.resolve(&source.registry, None).await?);
+            let candidates = map_mirror_candidates(image, &self.config)?;
+            if candidates.is_empty() {
+                warn!("Image: {} is not matched with any ruleset", image);
+            }
+            for candidate in candidates {
+                let mirror = ImageReference::parse(&candidate.mirror_image)?;
+                let freshness = match resolver.resolve(&mirror.registry, candidate.hit_ruleset.auth.as_ref()).await {
+                    Ok(credential) => {
+                        let mirror_client = RegistryClient::new().with_credential(&mirror.registry, credential);
+                        check_freshness(&source_client, &source, &mirror_client, &mirror).await
+                    }
+                    Err(e) => Freshness::Unknown(e.to_string()),
+                };
+                total += 1;
+                match &freshness {
+                    Freshness::Fresh => info!("{} -> {} ({}): {}", image, candidate.mirror_image, candidate.hit_ruleset.name, freshness),
+                    Freshness::Stale { .. } => {
+                        stale += 1;
+                        warn!("{} -> {} ({}): {}", image, candidate.mirror_image, candidate.hit_ruleset.name, freshness);
+                    }
+                    _ => warn!("{} -> {} ({}): {}", image, candidate.mirror_image, candidate.hit_ruleset.name, freshness),
+                }
+            }
+        }
+        if stale > 0 {
+            return Err(DockermirError::StaleMirrors { stale, total });
+        }
+        Ok(())
+    }
+}
+
+/// Compare the digest upstream with the mirror, both asked with HEAD so docker hub does not count a pull.
+/// A mirror filled by pulling and pushing one platform serves an image of the upstream index, which is fresh as well.
+pub(crate) async fn check_freshness(source_client: &RegistryClient, source: &ImageReference,
+                                    mirror_client: &RegistryClient, mirror: &ImageReference) -> Freshness {
+    let upstream_digest = match &source.digest {
+        Some(digest) => digest.clone(),
+        None => match source_client.fetch_manifest_digest(source).await {
+            Ok(Some(digest)) => digest,
+            Ok(None) => return Freshness::Unknown(format!("{} sent no digest", source)),
+            Err(e) => return Freshness::Unknown(e.to_string()),
+        },
+    };
+    let mirror_digest = match mirror_client.fetch_manifest_digest(mirror).await {
+        Ok(Some(digest)) => digest,
+        Ok(None) => return Freshness::Unknown(format!("{} sent no digest", mirror)),
+        Err(DockermirError::RegistryNotFound { .. }) => return Freshness::MirrorMissing,
+        Err(e) => return Freshness::Unknown(e.to_string()),
+    };
+    if mirror_digest == upstream_digest {
+        return Freshness::Fresh;
+    }
+    match source_client.fetch_manifest(&source.with_digest(&upstream_digest)).await {
+        Ok(manifest) if manifest.is_index() => {
+            let in_index = manifest.as_index()
+                .map(|index| index.manifests.iter().any(|descriptor| descriptor.digest == mirror_digest))
+                .unwrap_or(false);
+            if in_index {
+                return Freshness::Fresh;
+            }
+        }
+        Ok(_) => {}
+        Err(e) => trace!("Failed to fetch the index of {}, error: {}", source, e),
+    }
+    Freshness::Stale {
+        mirror_digest,
+        upstream_digest,
+    }
+}
@@ -0,0 +1,120 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, RegistryAuthConfig};
+use crate::components::test_registry::TestRegistry;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn reference(registry: &TestRegistry, image: &str) -> ImageReference {
+    ImageReference::parse(&format!("{}/{}", registry.host(), image)).unwrap()
+}
+
+async fn freshness(upstream: &TestRegistry, mirror: &TestRegistry) -> Freshness {
+    let client = RegistryClient::new();
+    check_freshness(&client, &reference(upstream, "dotnet/sdk:8.0"), &client, &reference(mirror, "secondary/sdk:8.0")).await
+}
+
+#[rstest]
+#[tokio::test]
+async fn fresh_when_digests_match(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_image("dotnet/sdk", "8.0", &["layer"]);
+    mirror.push_image("secondary/sdk", "8.0", &["layer"]);
+
+    assert_eq!(freshness(&upstream, &mirror).await, Freshness::Fresh);
+}
+
+#[rstest]
+#[tokio::test]
+async fn stale_when_upstream_moved(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    let new = upstream.push_image("dotnet/sdk", "8.0", &["new layer"]);
+    let old = mirror.push_image("secondary/sdk", "8.0", &["old layer"]);
+
+    assert_eq!(freshness(&upstream, &mirror).await, Freshness::Stale {
+        mirror_digest: old.digest,
+        upstream_digest: new.digest,
+    });
+}
+
+#[rstest]
+#[tokio::test]
+async fn fresh_when_mirror_has_one_platform_of_index(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_index("dotnet/sdk", "8.0", &[("linux", "amd64"), ("linux", "arm64")]);
+    // a mirror filled by `docker pull` and `docker push` on an amd64 host
+    let platform_image = RegistryClient::new()
+        .fetch_manifest(&reference(&upstream, "dotnet/sdk:8.0-linux-amd64")).await.unwrap();
+    mirror.put_manifest("secondary/sdk", "8.0", &platform_image.media_type, &platform_image.content);
+
+    assert_eq!(freshness(&upstream, &mirror).await, Freshness::Fresh);
+
+    mirror.push_image("secondary/sdk", "8.0", &["old layer"]);
+    assert!(matches!(freshness(&upstream, &mirror).await, Freshness::Stale { .. }));
+}
+
+#[rstest]
+#[tokio::test]
+async fn missing_and_unknown(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    assert!(matches!(freshness(&upstream, &mirror).await, Freshness::Unknown(_)));
+
+    upstream.push_image("dotnet/sdk", "8.0", &["layer"]);
+    assert_eq!(freshness(&upstream, &mirror).await, Freshness::MirrorMissing);
+}
+
+#[rstest]
+#[tokio::test]
+async fn freshness_task_reports_stale_mirrors(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_image("dotnet/sdk", "8.0", &["new layer"]);
+    upstream.push_image("dotnet/runtime", "8.0", &["runtime"]);
+    mirror.push_image("secondary/sdk", "8.0", &["old layer"]);
+    mirror.push_image("secondary/runtime", "8.0", &["runtime"]);
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", &mirror.host())
+        .replace("${secondary}", &mirror.host());
+    let config = ConfigLoader::default().load_config_yaml(&yaml).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let list = dir.path().join("images.txt");
+    std::fs::write(&list, format!("# images of the build agents\n{0}/dotnet/sdk:8.0\n{0}/dotnet/runtime:8.0\n", upstream.host())).unwrap();
+
+    let result = DockerFreshnessTask::new(config, vec![list.display().to_string()]).run().await;
+
+    assert!(matches!(result, Err(DockermirError::StaleMirrors { stale: 1, total: 2 })), "{:?}", result);
+}
+
+#[rstest]
+#[tokio::test]
+async fn freshness_task_reports_unresolved_credential(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_image("dotnet/sdk", "8.0", &["new layer"]);
+    mirror.push_image("secondary/sdk", "8.0", &["old layer"]);
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", &mirror.host())
+        .replace("${secondary}", &mirror.host());
+    let mut config = ConfigLoader::default().load_config_yaml(&yaml).unwrap();
+    config.docker.ruleset[1].auth = Some(RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: "${env:RUSHGET_TEST_UNSET_PASSWORD}".to_string(),
+    });
+
+    // the freshness of the mirror is unknown, which is not stale
+    let result = DockerFreshnessTask::new(config, vec![format!("{}/dotnet/sdk:8.0", upstream.host())]).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert!(mirror.requests().is_empty());
+}
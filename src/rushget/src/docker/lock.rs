@@ -0,0 +1,180 @@
+#[cfg(test)]
+mod tests;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::components::config::RushGetConfig;
+use crate::components::credentials::CredentialResolver;
+use crate::components::registry::RegistryClient;
+use crate::components::RushGetTask;
+use crate::docker::{map_mirror_candidates, ImageMirrorData};
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+pub(crate) const DEFAULT_LOCK_FILE: &str = "rg.lock";
+const LOCK_FILE_VERSION: u32 = 1;
+const LOCK_FILE_HEADER: &str = "# Written by `rg docker lock`, pulled with `rg docker pull --locked`. Do not edit.\n";
+
+/// The mirror an image was resolved to when the lock was written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LockedImage {
+    /// The image as it was given, e.g. `mcr.microsoft.com/dotnet/sdk:8.0`
+    pub(crate) source: String,
+    pub(crate) ruleset: String,
+    pub(crate) rule: String,
+    /// The mirror reference the rule maps the image to
+    pub(crate) mirror: String,
+    /// The digest of the manifest the mirror served
+    pub(crate) digest: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LockFile {
+    pub(crate) version: u32,
+    pub(crate) images: Vec<LockedImage>,
+}
+
+impl LockFile {
+    pub(crate) fn read(path: &Path) -> Result<LockFile, DockermirError> {
+        let lock_error = |error: String| DockermirError::LockFileError {
+            path: path.display().to_string(),
+            error,
+        };
+        let content = fs::read_to_string(path).map_err(|e| lock_error(e.to_string()))?;
+        let lock: LockFile = serde_yaml::from_str(&content).map_err(|e| lock_error(e.to_string()))?;
+        if lock.version != LOCK_FILE_VERSION {
+            return Err(lock_error(format!("unsupported version {}, expected {}", lock.version, LOCK_FILE_VERSION)));
+        }
+        Ok(lock)
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<(), DockermirError> {
+        let lock_error = |error: String| DockermirError::LockFileError {
+            path: path.display().to_string(),
+            error,
+        };
+        let content = serde_yaml::to_string(self).map_err(|e| lock_error(e.to_string()))?;
+        fs::write(path, format!("{}{}", LOCK_FILE_HEADER, content)).map_err(|e| lock_error(e.to_string()))
+    }
+
+    /// The entry of the image, `nginx` and `docker.io/library/nginx:latest` find the same entry
+    pub(crate) fn find(&self, image: &str) -> Option<&LockedImage> {
+        let reference = ImageReference::parse(image).ok()?;
+        self.images.iter().find(|locked| ImageReference::parse(&locked.source).ok().as_ref() == Some(&reference))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DockerLockOptions {
+    /// The lock file to write
+    pub(crate) output: PathBuf,
+}
+
+/// Record the mirror and the digest each image resolves to, so later pulls get the same content
+pub(crate) struct DockerLockTask {
+    images: Vec<String>,
+    config: RushGetConfig,
+    options: DockerLockOptions,
+}
+
+impl DockerLockTask {
+    pub(crate) fn new(config: RushGetConfig, images: Vec<String>, options: DockerLockOptions) -> Self {
+        DockerLockTask {
+            images,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerLockTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let resolver = CredentialResolver::new();
+        let mut lock = LockFile {
+            version: LOCK_FILE_VERSION,
+            images: vec![],
+        };
+        for image in &self.images {
+            if lock.find(image).is_some() {
+                continue;
+            }
+            let locked = lock_image(&resolver, image, &self.config).await?;
+            info!("Locked image: {} to {}@{} ({}/{})", image, locked.mirror, locked.digest, locked.ruleset, locked.rule);
+            lock.images.push(locked);
+        }
+        lock.write(&self.options.output)?;
+        info!("Wrote {} images to {}", lock.images.len(), self.options.output.display());
+        Ok(())
+    }
+}
+
+/// Resolve the image like a pull does, the first mirror in the order of the config serving it wins
+pub(crate) async fn lock_image(resolver: &CredentialResolver, image: &str, config: &RushGetConfig) -> Result<LockedImage, DockermirError> {
+    let candidates = map_mirror_candidates(image, config)?;
+    if candidates.is_empty() {
+        return Err(DockermirError::MismatchAllRule);
+    }
+    let mut attempts = vec![];
+    for candidate in candidates {
+        let digest = resolve_digest(resolver, &candidate).await;
+        match digest {
+            Ok(digest) => return Ok(LockedImage {
+                source: image.to_string(),
+                ruleset: candidate.hit_ruleset.name,
+                rule: candidate.hit_rule.name,
+                mirror: candidate.mirror_image,
+                digest,
+            }),
+            Err(e) => {
+                warn!("Failed to resolve image: {} from mirror: {}, error: {}", image, candidate.mirror_image, e);
+                attempts.push(format!("{} ({}/{}): {}", candidate.mirror_image, candidate.hit_ruleset.name, candidate.hit_rule.name, e));
+            }
+        }
+    }
+    Err(DockermirError::AllMirrorsFailed {
+        source_image: image.to_string(),
+        attempts,
+    })
+}
+
+/// The digest the mirror serves, an unresolved credential is a failure of this mirror like a failed request
+async fn resolve_digest(resolver: &CredentialResolver, candidate: &ImageMirrorData) -> Result<String, DockermirError> {
+    let mirror = ImageReference::parse(&candidate.mirror_image)?;
+    let credential = resolver.resolve(&mirror.registry, candidate.hit_ruleset.auth.as_ref()).await?;
+    let client = RegistryClient::new().with_credential(&mirror.registry, credential);
+    match client.fetch_manifest_digest(&mirror).await? {
+        Some(digest) => Ok(digest),
+        // the registry does not send the digest with HEAD, it is computed from the manifest
+        None => client.fetch_manifest(&mirror).await.map(|manifest| manifest.digest),
+    }
+}
+
+/// The mirror of the lock pinned to the locked digest, an error when the image or the rules changed since the lock was written
+pub(crate) fn locked_candidate(lock: &LockFile, image: &str, config: &RushGetConfig) -> Result<ImageMirrorData, DockermirError> {
+    let outdated = |reason: String| DockermirError::LockOutdated {
+        image: image.to_string(),
+        reason,
+    };
+    let locked = lock.find(image).ok_or_else(|| outdated("the image is not in the lock".to_string()))?;
+    let is_locked_rule = |candidate: &ImageMirrorData| candidate.hit_ruleset.name == locked.ruleset && candidate.hit_rule.name == locked.rule;
+    let candidate = map_mirror_candidates(image, config)?
+        .into_iter()
+        .find(is_locked_rule)
+        .ok_or_else(|| outdated(format!("the rule {}/{} does not match the image anymore", locked.ruleset, locked.rule)))?;
+    if candidate.mirror_image != locked.mirror {
+        return Err(outdated(format!("the rule maps the image to {} now, the lock has {}", candidate.mirror_image, locked.mirror)));
+    }
+
+    // the tag is kept next to the digest, so the pulled image is tagged with the name asked for
+    let source = ImageReference::parse(image)?;
+    let pinned = ImageReference {
+        digest: Some(locked.digest.clone()),
+        ..source
+    };
+    map_mirror_candidates(&pinned.to_string(), config)?
+        .into_iter()
+        .find(is_locked_rule)
+        .ok_or_else(|| outdated(format!("the rule {}/{} does not match {}", locked.ruleset, locked.rule, pinned)))
+}
@@ -0,0 +1,173 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, RegistryAuthConfig};
+use crate::components::oci_layout::OciLayout;
+use crate::components::test_registry::TestRegistry;
+use crate::docker::{DockerPullOptions, DockerPullTask};
+use crate::docker::tests::FALLBACK_YAML;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn fallback_config(primary: &str, secondary: &str) -> RushGetConfig {
+    let yaml = FALLBACK_YAML.replace("${primary}", primary).replace("${secondary}", secondary);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+fn locked(source: &str, ruleset: &str, rule: &str, mirror: &str, digest: &str) -> LockedImage {
+    LockedImage {
+        source: source.to_string(),
+        ruleset: ruleset.to_string(),
+        rule: rule.to_string(),
+        mirror: mirror.to_string(),
+        digest: digest.to_string(),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn lock_first_serving_mirror(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join(DEFAULT_LOCK_FILE);
+
+    // the same image twice is locked once
+    let images = vec!["mcr.microsoft.com/dotnet/sdk:6.0".to_string(), "mcr.microsoft.com/dotnet/sdk:6.0".to_string()];
+    DockerLockTask::new(fallback_config(&primary.host(), &secondary.host()), images, DockerLockOptions { output: output.clone() })
+        .run().await.unwrap();
+
+    assert!(fs::read_to_string(&output).unwrap().starts_with(LOCK_FILE_HEADER));
+    assert_eq!(LockFile::read(&output).unwrap(), LockFile {
+        version: LOCK_FILE_VERSION,
+        images: vec![locked("mcr.microsoft.com/dotnet/sdk:6.0", "secondary", "mcr dotnet",
+                            &format!("{}/secondary/sdk:6.0", secondary.host()), &image.digest)],
+    });
+}
+
+#[rstest]
+#[tokio::test]
+async fn lock_skips_mirror_with_unresolved_credential(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let mut config = fallback_config(&primary.host(), &secondary.host());
+    config.docker.ruleset[0].auth = Some(RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: "${env:RUSHGET_TEST_UNSET_PASSWORD}".to_string(),
+    });
+
+    let locked = lock_image(&CredentialResolver::new(), "mcr.microsoft.com/dotnet/sdk:6.0", &config).await.unwrap();
+
+    assert_eq!(locked.ruleset, "secondary");
+    assert_eq!(locked.digest, image.digest);
+    assert!(primary.requests().is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn lock_fails_without_mirror(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join(DEFAULT_LOCK_FILE);
+
+    let result = DockerLockTask::new(fallback_config(&primary.host(), &secondary.host()),
+                                     vec!["mcr.microsoft.com/dotnet/sdk:6.0".to_string()],
+                                     DockerLockOptions { output: output.clone() }).run().await;
+
+    assert!(matches!(result, Err(DockermirError::AllMirrorsFailed { ref attempts, .. }) if attempts.len() == 3), "{:?}", result);
+    assert!(!output.exists());
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_locked_digest(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let config = fallback_config(&primary.host(), &secondary.host());
+    let dir = tempfile::tempdir().unwrap();
+    let lock_file = dir.path().join(DEFAULT_LOCK_FILE);
+    DockerLockTask::new(config.clone(), vec!["mcr.microsoft.com/dotnet/sdk:6.0".to_string()],
+                        DockerLockOptions { output: lock_file.clone() }).run().await.unwrap();
+
+    // the tag moves on in the mirror after the lock was written
+    let moved = secondary.push_image("secondary/sdk", "6.0", &["newer layer"]);
+    assert_ne!(moved.digest, image.digest);
+    let layout = dir.path().join("layout");
+    let primary_requests = primary.requests().len();
+    DockerPullTask::new(config, "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(layout.clone()),
+        locked: Some(lock_file),
+        ..Default::default()
+    }).run().await.unwrap();
+
+    let index = OciLayout::open_or_create(&layout).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests[0].digest, image.digest);
+    // only the locked mirror is asked
+    assert_eq!(primary.requests().len(), primary_requests);
+}
+
+#[rstest]
+fn locked_candidate_pinned(_init_logger: ()) {
+    let config = fallback_config("primary.io", "secondary.io");
+    let digest = format!("sha256:{}", "a".repeat(64));
+    let lock = LockFile {
+        version: LOCK_FILE_VERSION,
+        images: vec![locked("mcr.microsoft.com/dotnet/sdk:6.0", "secondary", "mcr dotnet", "secondary.io/secondary/sdk:6.0", &digest)],
+    };
+
+    let candidate = locked_candidate(&lock, "mcr.microsoft.com/dotnet/sdk:6.0", &config).unwrap();
+
+    assert_eq!(candidate.mirror_image, format!("secondary.io/secondary/sdk@{}", digest));
+    assert_eq!(candidate.source_image, format!("mcr.microsoft.com/dotnet/sdk:6.0@{}", digest));
+    assert_eq!(candidate.tag_target().as_deref(), Some("mcr.microsoft.com/dotnet/sdk:6.0"));
+}
+
+#[rstest]
+#[case("mcr.microsoft.com/dotnet/runtime:6.0", "secondary", "mcr dotnet", "secondary.io/secondary/sdk:6.0", "not in the lock")]
+#[case("mcr.microsoft.com/dotnet/sdk:6.0", "tertiary", "mcr dotnet", "secondary.io/secondary/sdk:6.0", "does not match the image anymore")]
+#[case("mcr.microsoft.com/dotnet/sdk:6.0", "secondary", "mcr dotnet", "mirror.io/secondary/sdk:6.0", "maps the image to secondary.io/secondary/sdk:6.0 now")]
+fn locked_candidate_outdated(_init_logger: (), #[case] source: &str, #[case] ruleset: &str, #[case] rule: &str,
+                             #[case] mirror: &str, #[case] reason: &str) {
+    let config = fallback_config("primary.io", "secondary.io");
+    let lock = LockFile {
+        version: LOCK_FILE_VERSION,
+        images: vec![locked(source, ruleset, rule, mirror, &format!("sha256:{}", "a".repeat(64)))],
+    };
+
+    let result = locked_candidate(&lock, "mcr.microsoft.com/dotnet/sdk:6.0", &config);
+
+    match result {
+        Err(DockermirError::LockOutdated { reason: actual, .. }) => assert!(actual.contains(reason), "{}", actual),
+        other => panic!("unexpected result: {:?}", other.map(|candidate| candidate.mirror_image)),
+    }
+}
+
+#[rstest]
+#[case("nginx", "docker.io/library/nginx:latest", true)]
+#[case("library/nginx:latest", "nginx", true)]
+#[case("nginx", "nginx:1.25", false)]
+fn find_canonical_form(_init_logger: (), #[case] source: &str, #[case] image: &str, #[case] found: bool) {
+    let lock = LockFile {
+        version: LOCK_FILE_VERSION,
+        images: vec![locked(source, "hub", "library", "mirror.io/library/nginx:latest", "sha256:0")],
+    };
+    assert_eq!(lock.find(image).is_some(), found);
+}
+
+#[rstest]
+fn read_unsupported_version(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(DEFAULT_LOCK_FILE);
+    fs::write(&path, "version: 2\nimages: []\n").unwrap();
+    assert!(matches!(LockFile::read(&path), Err(DockermirError::LockFileError { .. })));
+}
@@ -0,0 +1,313 @@
+#[cfg(test)]
+mod tests;
+
+use std::convert::Infallible;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{CONTENT_TYPE, UPGRADE};
+use hyper::http::request::Parts;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::net::UnixListener;
+use tokio::sync::mpsc::Sender;
+use crate::components::config::RushGetConfig;
+use crate::components::credentials::CredentialResolver;
+use crate::components::docker_api::{encode, error_message, query_value, registry_auth, send_request, DockerApi, DockerHost};
+use crate::components::docker_exec::DockermirPullInput;
+use crate::components::container_engine::ContainerEngine;
+use crate::components::RushGetTask;
+use crate::docker::{map_mirror_candidates, ImageMirrorData};
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type ProxyBody = BoxBody<Bytes, BoxError>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct DockerProxySocketOptions {
+    /// The unix socket to listen on, point `DOCKER_HOST` at it
+    pub(crate) listen: PathBuf,
+    /// The daemon to forward to, `DOCKER_HOST` or the default socket if not set
+    pub(crate) upstream: Option<String>,
+}
+
+/// Serve the Engine API on a unix socket in front of the daemon, the pulls of every client are fetched from the mirrors
+pub(crate) struct DockerProxySocketTask {
+    config: RushGetConfig,
+    options: DockerProxySocketOptions,
+}
+
+impl DockerProxySocketTask {
+    pub(crate) fn new(config: RushGetConfig, options: DockerProxySocketOptions) -> Self {
+        DockerProxySocketTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerProxySocketTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let listen = &self.options.listen;
+        let serve_error = |error: String| DockermirError::ServeError {
+            address: listen.display().to_string(),
+            error,
+        };
+        let upstream = match &self.options.upstream {
+            Some(upstream) => DockerHost::parse(upstream)?,
+            None => DockerHost::from_env()?,
+        };
+        if upstream == DockerHost::Unix(listen.to_owned()) {
+            return Err(serve_error("the proxy would forward to itself, set --upstream to the socket of the daemon".to_string()));
+        }
+        remove_stale_socket(listen).map_err(serve_error)?;
+        let listener = UnixListener::bind(listen).map_err(|e| serve_error(e.to_string()))?;
+        info!("Serving docker socket proxy on unix://{} in front of {}, press Ctrl+C to stop", listen.display(), upstream);
+        let proxy = Arc::new(SocketProxy::new(self.config, upstream));
+        tokio::select! {
+            _ = proxy.serve(listener) => {}
+            _ = tokio::signal::ctrl_c() => info!("Stop serving docker socket proxy"),
+        }
+        let _ = fs::remove_file(listen);
+        Ok(())
+    }
+}
+
+/// A socket left behind by a proxy which was killed is removed, any other file is kept
+fn remove_stale_socket(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path).map_err(|e| e.to_string()),
+        Ok(_) => Err("the path exists and is not a socket".to_string()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Forwards the Engine API to the daemon, `POST /images/create` is pulled from the mirrors and tagged with the name asked for
+pub(crate) struct SocketProxy {
+    config: RushGetConfig,
+    upstream: DockerHost,
+    resolver: CredentialResolver,
+}
+
+impl SocketProxy {
+    pub(crate) fn new(config: RushGetConfig, upstream: DockerHost) -> SocketProxy {
+        SocketProxy {
+            config,
+            upstream,
+            resolver: CredentialResolver::new(),
+        }
+    }
+
+    pub(crate) async fn serve(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection, error: {}", e);
+                    continue;
+                }
+            };
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let proxy = proxy.clone();
+                    async move { Ok::<_, Infallible>(proxy.handle(request).await) }
+                });
+                // attach and exec upgrade the connection into a raw stream
+                if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).with_upgrades().await {
+                    trace!("Connection closed with error: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle(self: Arc<Self>, request: Request<Incoming>) -> Response<ProxyBody> {
+        if let Some(source) = pull_source(&request) {
+            match map_mirror_candidates(&source, &self.config) {
+                Ok(candidates) if !candidates.is_empty() => return self.pull_through_mirrors(request, source, candidates),
+                Ok(_) => trace!("No rule matches image: {}, forward the pull", source),
+                Err(e) => trace!("Forward the pull of image: {}, error: {}", source, e),
+            }
+        }
+        self.forward(request).await
+    }
+
+    /// Send the request to the daemon as it is, an upgraded connection is joined with the connection of the daemon
+    async fn forward(&self, mut request: Request<Incoming>) -> Response<ProxyBody> {
+        trace!("Forward {} {}", request.method(), request.uri());
+        let client_upgrade = request.headers().contains_key(UPGRADE).then(|| hyper::upgrade::on(&mut request));
+        let mut response = match send_request(&self.upstream, request).await {
+            Ok(response) => response,
+            Err(e) => return error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+        };
+        if let (StatusCode::SWITCHING_PROTOCOLS, Some(client_upgrade)) = (response.status(), client_upgrade) {
+            let daemon_upgrade = hyper::upgrade::on(&mut response);
+            tokio::spawn(async move {
+                match tokio::try_join!(client_upgrade, daemon_upgrade) {
+                    Ok((client, daemon)) => {
+                        let _ = tokio::io::copy_bidirectional(&mut TokioIo::new(client), &mut TokioIo::new(daemon)).await;
+                    }
+                    Err(e) => warn!("Failed to upgrade connection, error: {}", e),
+                }
+            });
+        }
+        response.map(|body| body.map_err(|e| Box::new(e) as BoxError).boxed())
+    }
+
+    /// Answer with the progress stream right away, the mirrors are tried one after another behind it
+    fn pull_through_mirrors(self: Arc<Self>, request: Request<Incoming>, source: String, candidates: Vec<ImageMirrorData>)
+                            -> Response<ProxyBody> {
+        let (parts, _) = request.into_parts();
+        let (sender, receiver) = tokio::sync::mpsc::channel::<Bytes>(16);
+        tokio::spawn(async move { self.pull(parts, &source, &candidates, &sender).await });
+        let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|line| (Ok::<_, BoxError>(Frame::data(line)), receiver))
+        });
+        let mut response = Response::new(BodyExt::boxed(StreamBody::new(stream)));
+        response.headers_mut().insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        response
+    }
+
+    async fn pull(&self, parts: Parts, source: &str, candidates: &[ImageMirrorData], sender: &Sender<Bytes>) {
+        let platform = parts.uri.query().and_then(|query| query_value(query, "platform")).filter(|platform| !platform.is_empty());
+        for candidate in candidates {
+            match self.pull_from_mirror(candidate, platform.as_deref(), sender).await {
+                Ok(_) => {
+                    info!("Pulled image: {} from mirror: {}", source, candidate.mirror_image);
+                    send_message(sender, &json!({ "status": format!("Pulled {} from mirror {}", source, candidate.mirror_image) })).await;
+                    return;
+                }
+                Err(e) => warn!("Failed to pull image: {} from mirror: {}, error: {}", source, candidate.mirror_image, e),
+            }
+        }
+
+        // the daemon pulls from the registry asked for, the same as without the proxy
+        info!("Pull image: {} from upstream", source);
+        let request = Request::from_parts(parts, Empty::<Bytes>::new());
+        let response = match send_request(&self.upstream, request).await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => return send_error(sender, &error_message(response).await).await,
+            Err(e) => return send_error(sender, &e.to_string()).await,
+        };
+        let mut body = response.into_body();
+        while let Some(Ok(frame)) = body.frame().await {
+            if let Ok(data) = frame.into_data() {
+                if sender.send(data).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Pull the mirror image and tag it with the name asked for, the progress is passed on but not the error of a failed mirror
+    async fn pull_from_mirror(&self, candidate: &ImageMirrorData, platform: Option<&str>, sender: &Sender<Bytes>)
+                              -> Result<(), DockermirError> {
+        let mirror = ImageReference::parse(&candidate.mirror_image)?;
+        let pull_error = |error: String| DockermirError::DockerPullError {
+            source_image: candidate.source_image.to_owned(),
+            mirror_image: candidate.mirror_image.to_owned(),
+            error,
+        };
+        // an unresolved credential fails this mirror only, the next one or the upstream forward is tried
+        let credential = self.resolver.resolve(&mirror.registry, candidate.hit_ruleset.auth.as_ref()).await
+            .map_err(|e| pull_error(e.to_string()))?;
+        let mut path = format!("/images/create?fromImage={}&tag={}",
+                               encode(&format!("{}/{}", mirror.registry, mirror.repository)), encode(mirror.reference()));
+        if let Some(platform) = platform {
+            path.push_str(&format!("&platform={}", encode(platform)));
+        }
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(path)
+            .header("Host", "docker")
+            .header("X-Registry-Auth", registry_auth(&mirror.registry, credential.as_ref()))
+            .body(Full::new(Bytes::new()))
+            .map_err(|e| pull_error(e.to_string()))?;
+        let response = send_request(&self.upstream, request).await?;
+        if !response.status().is_success() {
+            return Err(pull_error(error_message(response).await));
+        }
+
+        let mut body = response.into_body();
+        let mut buffer = vec![];
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| pull_error(e.to_string()))?;
+            let Ok(data) = frame.into_data() else { continue };
+            buffer.extend_from_slice(&data);
+            while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let message: Value = serde_json::from_slice(&line).unwrap_or(Value::Null);
+                if let Some(error) = message.get("error").and_then(|error| error.as_str()) {
+                    return Err(pull_error(error.to_string()));
+                }
+                // a client which went away does not stop the pull
+                let _ = sender.send(Bytes::from(line)).await;
+            }
+        }
+
+        match candidate.tag_target() {
+            Some(tag_target) => {
+                let api = DockerApi::new(self.upstream.clone());
+                api.tag(&DockermirPullInput::new(tag_target, candidate.mirror_image.to_owned())).await?;
+                api.rmi(&candidate.mirror_image).await?;
+            }
+            None => warn!("Keep the image as {}, the image asked for is pinned by digest only", candidate.mirror_image),
+        }
+        Ok(())
+    }
+}
+
+/// The image of `POST /images/create?fromImage=<name>&tag=<tag>`, imports with `fromSrc` are left to the daemon
+fn pull_source<B>(request: &Request<B>) -> Option<String> {
+    if request.method() != Method::POST {
+        return None;
+    }
+    let path = request.uri().path();
+    // the clients prefix the path with the API version, e.g. `/v1.45/images/create`
+    let path = match path.strip_prefix("/v") {
+        Some(versioned) => versioned.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.'),
+        None => path,
+    };
+    if path != "/images/create" {
+        return None;
+    }
+    let query = request.uri().query()?;
+    if query_value(query, "fromSrc").is_some() {
+        return None;
+    }
+    let image = query_value(query, "fromImage").filter(|image| !image.is_empty())?;
+    let tag = query_value(query, "tag").unwrap_or_default();
+    Some(match tag.as_str() {
+        // without a tag the daemon pulls every tag of the repository, which no single mirror image stands for
+        "" if !image.contains('@') && !image.rsplit('/').next().unwrap_or_default().contains(':') => return None,
+        "" => image,
+        digest if digest.contains(':') => format!("{}@{}", image, digest),
+        tag => format!("{}:{}", image, tag),
+    })
+}
+
+async fn send_message(sender: &Sender<Bytes>, message: &Value) {
+    let _ = sender.send(Bytes::from(format!("{}\r\n", message))).await;
+}
+
+/// The pull already answered 200, so the failure is reported inside the progress stream like the daemon does
+async fn send_error(sender: &Sender<Bytes>, error: &str) {
+    send_message(sender, &json!({ "errorDetail": { "message": error }, "error": error })).await;
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<ProxyBody> {
+    let body = json!({ "message": message });
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())).map_err(|never| match never {}).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
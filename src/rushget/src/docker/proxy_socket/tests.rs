@@ -0,0 +1,167 @@
+use log::LevelFilter;
+use rstest::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::components::config::{ConfigLoader, RegistryAuthConfig};
+use crate::components::progress::PullProgress;
+use crate::components::test_docker_daemon::{TestDockerDaemon, TEST_LAYER_ID};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+#[case("/images/create?fromImage=nginx", None)]
+#[case("/images/create?fromImage=nginx%3A1.25", Some("nginx:1.25"))]
+#[case("/images/create?fromImage=localhost%3A5000%2Fnginx", None)]
+#[case("/images/create?fromImage=nginx%40sha256%3Aabc", Some("nginx@sha256:abc"))]
+#[case("/v1.45/images/create?fromImage=mcr.microsoft.com%2Fdotnet%2Fsdk&tag=8.0", Some("mcr.microsoft.com/dotnet/sdk:8.0"))]
+#[case("/images/create?fromImage=nginx&tag=sha256%3Aabc", Some("nginx@sha256:abc"))]
+#[case("/images/create?fromSrc=-&repo=nginx", None)]
+#[case("/images/create?fromImage=", None)]
+#[case("/images/nginx/json", None)]
+fn pull_sources(_init_logger: (), #[case] uri: &str, #[case] expected: Option<&str>) {
+    let request = Request::post(uri).body(()).unwrap();
+    assert_eq!(pull_source(&request).as_deref(), expected);
+}
+
+/// The mirrors of the dotnet images on two registries
+fn fallback_config() -> RushGetConfig {
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", "primary.example.com")
+        .replace("${secondary}", "secondary.example.com");
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+/// The proxy in front of the test daemon
+async fn start_proxy(daemon: &TestDockerDaemon) -> (tempfile::TempDir, DockerHost) {
+    start_proxy_with(daemon, fallback_config()).await
+}
+
+async fn start_proxy_with(daemon: &TestDockerDaemon, config: RushGetConfig) -> (tempfile::TempDir, DockerHost) {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("rg.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+    tokio::spawn(Arc::new(SocketProxy::new(config, daemon.host())).serve(listener));
+    (dir, DockerHost::Unix(socket))
+}
+
+fn input(image: &str) -> DockermirPullInput {
+    DockermirPullInput::new(image.to_string(), image.to_string())
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_rewritten_to_mirror(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.add_remote("secondary.example.com/secondary/sdk:8.0", "sha256:sdk");
+    let (_dir, proxy) = start_proxy(&daemon).await;
+    let api = DockerApi::new(proxy);
+    let progress = PullProgress::with_tty("test", false);
+
+    // the client asks for the upstream image, the daemon never sees that name in a pull
+    api.pull(&input("mcr.microsoft.com/dotnet/sdk:8.0"), &progress).await.unwrap();
+
+    assert_eq!(progress.layer_bytes(TEST_LAYER_ID), Some((100, Some(100))));
+    assert_eq!(daemon.local_tags(), vec!["mcr.microsoft.com/dotnet/sdk:8.0"]);
+    assert_eq!(daemon.requests(), vec![
+        "POST /images/create?fromImage=primary.example.com/primary/sdk&tag=8.0",
+        "POST /images/create?fromImage=primary.example.com/primary/dotnet_sdk&tag=8.0",
+        "POST /images/create?fromImage=secondary.example.com/secondary/sdk&tag=8.0",
+        "POST /images/secondary.example.com/secondary/sdk:8.0/tag?repo=mcr.microsoft.com/dotnet/sdk&tag=8.0",
+        "DELETE /images/secondary.example.com/secondary/sdk:8.0",
+    ]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_forwarded_when_no_mirror_serves_it(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.add_remote("mcr.microsoft.com/dotnet/sdk:8.0", "sha256:sdk");
+    daemon.add_remote("nginx", "sha256:nginx");
+    let (_dir, proxy) = start_proxy(&daemon).await;
+    let api = DockerApi::new(proxy);
+    let progress = PullProgress::with_tty("test", false);
+
+    api.pull(&input("mcr.microsoft.com/dotnet/sdk:8.0"), &progress).await.unwrap();
+    assert_eq!(daemon.requests().last().unwrap(), "POST /images/create?fromImage=mcr.microsoft.com/dotnet/sdk&tag=8.0");
+
+    // no rule matches, the request goes to the daemon untouched
+    api.pull(&input("nginx"), &progress).await.unwrap();
+    assert_eq!(daemon.requests().last().unwrap(), "POST /images/create?fromImage=docker.io/library/nginx&tag=latest");
+    assert_eq!(daemon.local_tags(), vec!["docker.io/library/nginx:latest", "mcr.microsoft.com/dotnet/sdk:8.0"]);
+
+    // the failure of the daemon reaches the client inside the progress stream
+    let result = api.pull(&input("mcr.microsoft.com/dotnet/runtime:8.0"), &progress).await;
+    match result {
+        Err(DockermirError::DockerPullError { error, .. }) => assert!(error.starts_with("404 Not Found, manifest for"), "{}", error),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_forwarded_when_no_credential_resolves(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.add_remote("secondary.example.com/secondary/sdk:8.0", "sha256:mirror");
+    daemon.add_remote("mcr.microsoft.com/dotnet/sdk:8.0", "sha256:sdk");
+    let mut config = fallback_config();
+    for ruleset in config.docker.ruleset.iter_mut() {
+        ruleset.auth = Some(RegistryAuthConfig {
+            username: "robot".to_string(),
+            password: "${env:RUSHGET_TEST_UNSET_PASSWORD}".to_string(),
+        });
+    }
+    let (_dir, proxy) = start_proxy_with(&daemon, config).await;
+
+    DockerApi::new(proxy).pull(&input("mcr.microsoft.com/dotnet/sdk:8.0"), &PullProgress::with_tty("test", false)).await.unwrap();
+
+    assert_eq!(daemon.requests(), vec!["POST /images/create?fromImage=mcr.microsoft.com/dotnet/sdk&tag=8.0"]);
+    assert_eq!(daemon.local_tags(), vec!["mcr.microsoft.com/dotnet/sdk:8.0"]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn other_requests_pass_through(_init_logger: ()) {
+    let daemon = TestDockerDaemon::start().await;
+    daemon.add_local("nginx", "sha256:nginx");
+    let (_dir, proxy) = start_proxy(&daemon).await;
+    let api = DockerApi::new(proxy.clone());
+
+    let inspect = api.inspect("nginx").await.unwrap().unwrap();
+    assert_eq!(inspect.id, "sha256:nginx");
+    assert!(api.inspect("alpine").await.unwrap().is_none());
+
+    // attach upgrades the connection, the raw stream is joined through the proxy
+    let request = Request::post("/containers/app/attach?stream=1&stdin=1&stdout=1")
+        .header("Host", "docker")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "tcp")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let response = send_request(&proxy, request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    let mut stream = TokioIo::new(hyper::upgrade::on(response).await.unwrap());
+    stream.write_all(b"ping").await.unwrap();
+    let mut echo = [0u8; 4];
+    stream.read_exact(&mut echo).await.unwrap();
+    assert_eq!(&echo, b"ping");
+}
+
+#[rstest]
+fn stale_socket_removed(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("rg.sock");
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    remove_stale_socket(&socket).unwrap();
+    assert!(!socket.exists());
+
+    let file = dir.path().join("config.yaml");
+    fs::write(&file, "").unwrap();
+    assert!(remove_stale_socket(&file).is_err());
+    assert!(file.exists());
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+use regex::Regex;
+use crate::error::DockermirError;
+
+pub(crate) const DOCKER_HUB_REGISTRY: &str = "docker.io";
+const DOCKER_HUB_OFFICIAL_NAMESPACE: &str = "library";
+const DEFAULT_TAG: &str = "latest";
+
+/// A normalized image reference, so `nginx`, `library/nginx` and `docker.io/library/nginx:latest` are the same image
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ImageReference {
+    pub(crate) registry: String,
+    pub(crate) repository: String,
+    pub(crate) tag: Option<String>,
+    pub(crate) digest: Option<String>,
+}
+
+fn repository_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*(?:/[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*)*$").unwrap())
+}
+
+fn tag_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"^\w[\w.-]{0,127}$").unwrap())
+}
+
+fn digest_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"^[a-z0-9]+(?:[.+_-][a-z0-9]+)*:[a-fA-F0-9]{32,}$").unwrap())
+}
+
+impl ImageReference {
+    /// Parse the reference the same way as docker does, filling in docker.io, library/ and the latest tag
+    pub(crate) fn parse(image: &str) -> Result<ImageReference, DockermirError> {
+        let invalid = |reason: &str| DockermirError::InvalidImageReference(format!("{}, {}", image, reason));
+        let image = image.trim();
+        if image.is_empty() {
+            return Err(invalid("empty reference"));
+        }
+
+        let (name, digest) = match image.split_once('@') {
+            Some((name, digest)) => {
+                if !digest_regex().is_match(digest) {
+                    return Err(invalid("invalid digest"));
+                }
+                (name, Some(digest.to_string()))
+            }
+            None => (image, None),
+        };
+        let (name, tag) = match name.rfind(':') {
+            Some(index) if !name[index + 1..].contains('/') => {
+                let tag = &name[index + 1..];
+                if !tag_regex().is_match(tag) {
+                    return Err(invalid("invalid tag"));
+                }
+                (&name[..index], Some(tag.to_string()))
+            }
+            _ => (name, None),
+        };
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, rest)) if host.contains('.') || host.contains(':') || host == "localhost" => {
+                (host.to_string(), rest.to_string())
+            }
+            _ => (DOCKER_HUB_REGISTRY.to_string(), name.to_string()),
+        };
+        let registry = match registry.as_str() {
+            "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB_REGISTRY.to_string(),
+            _ => registry,
+        };
+        let repository = if registry == DOCKER_HUB_REGISTRY && !repository.contains('/') {
+            format!("{}/{}", DOCKER_HUB_OFFICIAL_NAMESPACE, repository)
+        } else {
+            repository
+        };
+        if !repository_regex().is_match(&repository) {
+            return Err(invalid("invalid repository name"));
+        }
+
+        let tag = match (&tag, &digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            _ => tag,
+        };
+        Ok(ImageReference {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+
+    /// The tag or the digest to request the manifest with, digest wins when both are set
+    pub(crate) fn reference(&self) -> &str {
+        self.digest.as_deref()
+            .or(self.tag.as_deref())
+            .unwrap_or(DEFAULT_TAG)
+    }
+
+    pub(crate) fn with_digest(&self, digest: &str) -> ImageReference {
+        ImageReference {
+            registry: self.registry.clone(),
+            repository: self.repository.clone(),
+            tag: None,
+            digest: Some(digest.to_string()),
+        }
+    }
+
+    /// The reference without digest, tagged with latest if it was pinned by digest only
+    pub(crate) fn without_digest(&self) -> ImageReference {
+        ImageReference {
+            registry: self.registry.clone(),
+            repository: self.repository.clone(),
+            tag: Some(self.tag.clone().unwrap_or_else(|| DEFAULT_TAG.to_string())),
+            digest: None,
+        }
+    }
+
+    /// Replace `${registry}`, `${repository}`, `${tag}` and `${digest}` in the template
+    pub(crate) fn render_template(&self, template: &str) -> String {
+        template
+            .replace("${registry}", &self.registry)
+            .replace("${repository}", &self.repository)
+            .replace("${tag}", self.tag.as_deref().unwrap_or_default())
+            .replace("${digest}", self.digest.as_deref().unwrap_or_default())
+    }
+
+    /// Render the template into the name of another image, which takes the tag of this image when the template has none
+    pub(crate) fn render_target(&self, template: &str) -> Result<ImageReference, DockermirError> {
+        let rendered = self.render_template(template);
+        let has_tag = rendered.contains('@') || rendered.rsplit_once(':').is_some_and(|(_, tag)| !tag.contains('/'));
+        let target = ImageReference::parse(&rendered)?;
+        if has_tag {
+            return Ok(target);
+        }
+        match &self.tag {
+            Some(tag) => Ok(ImageReference {
+                tag: Some(tag.clone()),
+                ..target
+            }),
+            None => Err(DockermirError::InvalidImageReference(format!("{}, a tag is required for images pinned by digest only", rendered))),
+        }
+    }
+}
+
+impl Display for ImageReference {
+    /// The canonical form, e.g. `docker.io/library/nginx:latest` or `docker.io/library/nginx@sha256:...`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use rstest::*;
+use super::*;
+
+const DIGEST: &str = "sha256:0000000000000000000000000000000000000000000000000000000000000001";
+
+#[rstest]
+#[case("nginx", "docker.io/library/nginx:latest")]
+#[case("library/nginx", "docker.io/library/nginx:latest")]
+#[case("docker.io/library/nginx:latest", "docker.io/library/nginx:latest")]
+#[case("index.docker.io/library/nginx:1.25", "docker.io/library/nginx:1.25")]
+#[case("newbe36524/nginx:1.25", "docker.io/newbe36524/nginx:1.25")]
+#[case("mcr.microsoft.com/dotnet/sdk", "mcr.microsoft.com/dotnet/sdk:latest")]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0", "mcr.microsoft.com/dotnet/sdk:8.0")]
+#[case("localhost:5000/sdk:8.0", "localhost:5000/sdk:8.0")]
+#[case("localhost/sdk", "localhost/sdk:latest")]
+#[case(&format!("nginx@{}", DIGEST), &format!("docker.io/library/nginx@{}", DIGEST))]
+#[case(&format!("nginx:1.25@{}", DIGEST), &format!("docker.io/library/nginx:1.25@{}", DIGEST))]
+fn parse_canonical(#[case] image: &str, #[case] expected: &str) {
+    let reference = ImageReference::parse(image).unwrap();
+    assert_eq!(reference.to_string(), expected);
+}
+
+#[rstest]
+fn parse_parts() {
+    let reference = ImageReference::parse(&format!("localhost:5000/newbe36524/sdk:8.0@{}", DIGEST)).unwrap();
+    assert_eq!(reference.registry, "localhost:5000");
+    assert_eq!(reference.repository, "newbe36524/sdk");
+    assert_eq!(reference.tag.as_deref(), Some("8.0"));
+    assert_eq!(reference.digest.as_deref(), Some(DIGEST));
+    assert_eq!(reference.reference(), DIGEST);
+}
+
+#[rstest]
+#[case("")]
+#[case("Nginx")]
+#[case("nginx:")]
+#[case("nginx@sha256:abc")]
+#[case("mcr.microsoft.com/dotnet//sdk")]
+fn parse_invalid(#[case] image: &str) {
+    assert!(matches!(ImageReference::parse(image), Err(DockermirError::InvalidImageReference(_))));
+}
+
+#[rstest]
+fn render_template() {
+    let reference = ImageReference::parse("mcr.microsoft.com/dotnet/sdk:8.0").unwrap();
+    let rendered = reference.render_template("mirror/${registry}/${repository}:${tag}${digest}");
+    assert_eq!(rendered, "mirror/mcr.microsoft.com/dotnet/sdk:8.0");
+}
+
+#[rstest]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0", "registry.corp/dotnet/sdk", "registry.corp/dotnet/sdk:8.0")]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0", "registry.corp/${repository}:${tag}-mirrored", "registry.corp/dotnet/sdk:8.0-mirrored")]
+#[case("nginx", "localhost:5000/${repository}", "localhost:5000/library/nginx:latest")]
+#[case(&format!("nginx@{}", DIGEST), "localhost:5000/nginx:1.25", "localhost:5000/nginx:1.25")]
+fn render_target(#[case] source: &str, #[case] template: &str, #[case] expected: &str) {
+    let reference = ImageReference::parse(source).unwrap();
+    assert_eq!(reference.render_target(template).unwrap().to_string(), expected);
+}
+
+#[rstest]
+fn render_target_requires_tag_of_digest() {
+    let reference = ImageReference::parse(&format!("nginx@{}", DIGEST)).unwrap();
+    assert!(matches!(reference.render_target("localhost:5000/nginx"), Err(DockermirError::InvalidImageReference(_))));
+}
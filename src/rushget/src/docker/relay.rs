@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests;
+
+use crate::components::config::RushGetConfig;
+use crate::components::container_engine::{create_engine, ContainerEngine, ContainerEngineKind};
+use crate::components::credentials::CredentialResolver;
+use crate::components::docker_exec::DockermirPullInput;
+use crate::components::progress::PullProgress;
+use crate::components::registry::Platform;
+use crate::components::RushGetTask;
+use crate::docker::{login_if_required, map_mirror_candidates, DockerPullOptions, DockerPullTask};
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DockerRelayOptions {
+    /// The image to push to, `${registry}`, `${repository}` and `${tag}` are taken from the source image,
+    /// the tag of the source image is used when it has no tag
+    pub(crate) to: String,
+    /// The container engine to pull and push with, overrides the engine in config
+    pub(crate) engine: Option<ContainerEngineKind>,
+    /// Pull from the original registry when all mirrors failed
+    pub(crate) fallback_to_upstream: bool,
+    /// The platform to pick from multi-arch images, the platform of the engine if not set
+    pub(crate) platform: Option<Platform>,
+}
+
+/// Pull the image from the mirrors and push it into another registry, so one machine warms a registry for the team
+pub(crate) struct DockerRelayTask {
+    image: String,
+    config: RushGetConfig,
+    options: DockerRelayOptions,
+}
+
+impl DockerRelayTask {
+    pub(crate) fn new(config: RushGetConfig, image: String, options: DockerRelayOptions) -> Self {
+        DockerRelayTask {
+            image,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerRelayTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let source = ImageReference::parse(&self.image)?;
+        let target = source.render_target(&self.options.to)?;
+        if target.digest.is_some() {
+            return Err(DockermirError::InvalidImageReference(format!("{}, images can not be pushed to a digest", target)));
+        }
+        let kind = ContainerEngineKind::resolve(self.options.engine, &self.config)?;
+        DockerPullTask::new(self.config.clone(), self.image.to_owned(), DockerPullOptions {
+            engine: Some(kind),
+            fallback_to_upstream: self.options.fallback_to_upstream,
+            platform: self.options.platform.clone(),
+            ..Default::default()
+        }).run().await?;
+
+        let engine = create_engine(kind)?;
+        // the pull leaves the image under the source name, or under the mirror name when it is pinned by digest only
+        let mut local_names = vec![self.image.to_owned()];
+        local_names.extend(map_mirror_candidates(&self.image, &self.config)?.iter().map(|candidate| candidate.local_image()));
+        let mut local_image = None;
+        for name in local_names {
+            if engine.inspect(&name).await?.is_some() {
+                local_image = Some(name);
+                break;
+            }
+        }
+        let local_image = local_image.ok_or_else(|| DockermirError::DockerInspectError {
+            image: self.image.to_owned(),
+            error: "the pulled image is not in the local image store".to_string(),
+        })?;
+        push_image(engine.as_ref(), &local_image, &target).await?;
+        info!("Relayed image: {} to {}", self.image, target);
+        Ok(())
+    }
+}
+
+/// Tag the local image with the target name and push it, the target name is removed again afterwards
+pub(crate) async fn push_image(engine: &dyn ContainerEngine, local_image: &str, target: &ImageReference) -> Result<(), DockermirError> {
+    let target_name = target.to_string();
+    let credential = CredentialResolver::new().resolve(&target.registry, None).await?;
+    login_if_required(engine, &target.registry, credential.as_ref()).await?;
+    engine.tag(&DockermirPullInput::new(target_name.to_owned(), local_image.to_owned())).await?;
+    let result = engine.push(&target_name, &PullProgress::new(&target_name)).await;
+    if let Err(e) = engine.rmi(&target_name).await {
+        warn!("Failed to remove the relay tag: {}, error: {}", target_name, e);
+    }
+    result
+}
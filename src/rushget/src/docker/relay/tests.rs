@@ -0,0 +1,28 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::docker::tests::FakeEngine;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+#[tokio::test]
+async fn push_image_tags_pushes_and_untags(_init_logger: ()) {
+    let engine = FakeEngine::default();
+    let source = ImageReference::parse("mcr.microsoft.com/dotnet/sdk:8.0").unwrap();
+    let target = source.render_target("localhost:5000/${repository}").unwrap();
+
+    push_image(&engine, "mcr.microsoft.com/dotnet/sdk:8.0", &target).await.unwrap();
+
+    assert_eq!(*engine.calls.lock().unwrap(), vec![
+        "tag mcr.microsoft.com/dotnet/sdk:8.0 localhost:5000/dotnet/sdk:8.0",
+        "push localhost:5000/dotnet/sdk:8.0",
+        "rmi localhost:5000/dotnet/sdk:8.0",
+    ]);
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use tar::{Builder, Header, HeaderMode};
+use crate::components::config::RushGetConfig;
+use crate::components::oci_layout::{OciLayout, ANNOTATION_IMAGE_NAME};
+use crate::components::registry::{ImageManifest, Platform};
+use crate::components::RushGetTask;
+use crate::docker::{DockerPullOptions, DockerPullTask};
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+/// The format of the archive written by `rg docker save`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum SaveFormat {
+    /// Loadable by `docker load` and `podman load`, an OCI image layout with `manifest.json` and `repositories`
+    #[default]
+    DockerArchive,
+    /// A plain OCI image layout, e.g. for `skopeo copy oci-archive:` or `ctr images import`
+    Oci,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DockerSaveOptions {
+    pub(crate) output: PathBuf,
+    pub(crate) format: SaveFormat,
+    /// Pull from the original registry when all mirrors failed
+    pub(crate) fallback_to_upstream: bool,
+    /// The platform to pick from multi-arch images, the platform of the host if not set
+    pub(crate) platform: Option<Platform>,
+}
+
+/// Pull the image from the mirrors with the native registry client and write it into an archive, no daemon required
+pub(crate) struct DockerSaveTask {
+    image: String,
+    config: RushGetConfig,
+    options: DockerSaveOptions,
+}
+
+impl DockerSaveTask {
+    pub(crate) fn new(config: RushGetConfig, image: String, options: DockerSaveOptions) -> Self {
+        DockerSaveTask {
+            image,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerSaveTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let archive_error = |e: std::io::Error| DockermirError::ImageArchiveError {
+            path: self.options.output.display().to_string(),
+            error: e.to_string(),
+        };
+        let layout_dir = tempfile::tempdir().map_err(archive_error)?;
+        DockerPullTask::new(self.config, self.image.to_owned(), DockerPullOptions {
+            oci_layout: Some(layout_dir.path().to_path_buf()),
+            fallback_to_upstream: self.options.fallback_to_upstream,
+            platform: self.options.platform.clone(),
+            ..Default::default()
+        }).run().await?;
+        write_archive(layout_dir.path(), &self.options.output, self.options.format)?;
+        info!("Saved image: {} to {} as {:?}", self.image, self.options.output.display(), self.options.format);
+        Ok(())
+    }
+}
+
+/// An entry of `manifest.json` in the archive of `docker save`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerArchiveManifest {
+    config: String,
+    repo_tags: Vec<String>,
+    layers: Vec<String>,
+}
+
+/// Write the OCI layout into a tar archive, the docker archive has the `docker load` metadata next to the layout
+pub(crate) fn write_archive(layout_dir: &Path, output: &Path, format: SaveFormat) -> Result<(), DockermirError> {
+    let archive_error = |error: String| DockermirError::ImageArchiveError {
+        path: output.display().to_string(),
+        error,
+    };
+    let layout = OciLayout::open_or_create(layout_dir)?;
+    let index = layout.read_index()?;
+
+    // write next to the output and rename, so an interrupted save never leaves a truncated archive behind
+    let parent = output.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let partial = tempfile::NamedTempFile::new_in(parent).map_err(|e| archive_error(e.to_string()))?;
+    let mut builder = Builder::new(File::create(partial.path()).map_err(|e| archive_error(e.to_string()))?);
+    // no owners or timestamps of the temporary layout, the same image always gives the same archive
+    builder.mode(HeaderMode::Deterministic);
+    for name in ["oci-layout", "index.json"] {
+        builder.append_path_with_name(layout_dir.join(name), name).map_err(|e| archive_error(e.to_string()))?;
+    }
+    let mut blobs: Vec<PathBuf> = fs::read_dir(layout_dir.join("blobs").join("sha256"))
+        .map_err(|e| archive_error(e.to_string()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    blobs.sort();
+    for blob in blobs {
+        let name = format!("blobs/sha256/{}", blob.file_name().unwrap().to_string_lossy());
+        builder.append_path_with_name(&blob, name).map_err(|e| archive_error(e.to_string()))?;
+    }
+
+    if format == SaveFormat::DockerArchive {
+        let mut manifests = vec![];
+        let mut repositories: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for descriptor in &index.manifests {
+            let content = fs::read(layout.blob_path(&descriptor.digest)?).map_err(|e| archive_error(e.to_string()))?;
+            let manifest: ImageManifest = serde_json::from_slice(&content).map_err(|e| archive_error(e.to_string()))?;
+            // the source name recorded by the pull, digest only images are loaded without a tag just like `docker save` writes them
+            let source = descriptor.annotations.as_ref()
+                .and_then(|annotations| annotations.get(ANNOTATION_IMAGE_NAME))
+                .map(|name| ImageReference::parse(name))
+                .transpose()?;
+            let mut repo_tags = vec![];
+            if let Some((source, tag)) = source.as_ref().and_then(|source| source.tag.as_ref().map(|tag| (source, tag))) {
+                let name = format!("{}/{}", source.registry, source.repository);
+                repo_tags.push(format!("{}:{}", name, tag));
+                if let Some(top_layer) = manifest.layers.last() {
+                    repositories.entry(name).or_default().insert(tag.clone(), blob_id(&top_layer.digest).to_string());
+                }
+            }
+            manifests.push(DockerArchiveManifest {
+                config: blob_name(&manifest.config.digest),
+                repo_tags,
+                layers: manifest.layers.iter().map(|layer| blob_name(&layer.digest)).collect(),
+            });
+        }
+        append_json(&mut builder, "manifest.json", &manifests).map_err(archive_error)?;
+        append_json(&mut builder, "repositories", &repositories).map_err(archive_error)?;
+    }
+
+    builder.into_inner().and_then(|file| file.sync_all()).map_err(|e| archive_error(e.to_string()))?;
+    partial.persist(output).map_err(|e| archive_error(e.to_string()))?;
+    Ok(())
+}
+
+fn blob_name(digest: &str) -> String {
+    format!("blobs/sha256/{}", blob_id(digest))
+}
+
+fn blob_id(digest: &str) -> &str {
+    digest.split_once(':').map(|(_, encoded)| encoded).unwrap_or(digest)
+}
+
+pub(crate) fn append_json(builder: &mut Builder<File>, name: &str, value: &impl Serialize) -> Result<(), String> {
+    let content = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append_data(&mut header, name, content.as_slice()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::io::Read;
+use log::LevelFilter;
+use rstest::*;
+use serde_json::Value;
+use crate::components::config::ConfigLoader;
+use crate::components::test_registry::TestRegistry;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn mirror_config(host: &str) -> RushGetConfig {
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", host)
+        .replace("${secondary}", host);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+fn read_archive(path: &Path) -> HashMap<String, Vec<u8>> {
+    let mut archive = tar::Archive::new(File::open(path).unwrap());
+    archive.entries().unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut content = vec![];
+            entry.read_to_end(&mut content).unwrap();
+            (entry.path().unwrap().display().to_string(), content)
+        })
+        .collect()
+}
+
+async fn save(registry: &TestRegistry, image: &str, format: SaveFormat) -> (tempfile::TempDir, HashMap<String, Vec<u8>>) {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("image.tar");
+    let result = DockerSaveTask::new(mirror_config(&registry.host()), image.to_string(), DockerSaveOptions {
+        output: output.clone(),
+        format,
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await;
+    assert!(result.is_ok(), "{:?}", result.err());
+    let entries = read_archive(&output);
+    (dir, entries)
+}
+
+#[rstest]
+#[tokio::test]
+async fn save_docker_archive(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("primary/sdk", "6.0", &["layer one", "layer two"]);
+
+    let (_dir, entries) = save(&registry, "mcr.microsoft.com/dotnet/sdk:6.0", SaveFormat::DockerArchive).await;
+
+    let manifest: Value = serde_json::from_slice(&entries["manifest.json"]).unwrap();
+    assert_eq!(manifest[0]["RepoTags"], serde_json::json!(["mcr.microsoft.com/dotnet/sdk:6.0"]));
+    assert_eq!(manifest[0]["Config"], blob_name(&image.config.digest));
+    let layers: Vec<String> = image.layers.iter().map(|layer| blob_name(&layer.digest)).collect();
+    assert_eq!(manifest[0]["Layers"], serde_json::json!(layers));
+    for layer in &layers {
+        assert!(entries.contains_key(layer), "{} is missing", layer);
+    }
+    let repositories: Value = serde_json::from_slice(&entries["repositories"]).unwrap();
+    assert_eq!(repositories["mcr.microsoft.com/dotnet/sdk"]["6.0"], blob_id(&image.layers[1].digest));
+}
+
+#[rstest]
+#[tokio::test]
+async fn save_oci_layout(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("primary/sdk", "6.0", &["layer"]);
+
+    let (_dir, entries) = save(&registry, "mcr.microsoft.com/dotnet/sdk:6.0", SaveFormat::Oci).await;
+
+    assert!(!entries.contains_key("manifest.json"));
+    assert!(entries.contains_key("oci-layout"));
+    assert!(entries.contains_key(&blob_name(&image.digest)));
+    let index: Value = serde_json::from_slice(&entries["index.json"]).unwrap();
+    assert_eq!(index["manifests"][0]["digest"], image.digest);
+    assert_eq!(index["manifests"][0]["annotations"][ANNOTATION_IMAGE_NAME], "mcr.microsoft.com/dotnet/sdk:6.0");
+}
+
+#[rstest]
+#[tokio::test]
+async fn save_fails_without_mirror(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("image.tar");
+
+    let result = DockerSaveTask::new(mirror_config(&registry.host()), "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerSaveOptions {
+        output: output.clone(),
+        format: SaveFormat::DockerArchive,
+        fallback_to_upstream: false,
+        platform: None,
+    }).run().await;
+
+    assert!(matches!(result, Err(DockermirError::AllMirrorsFailed { .. })));
+    assert!(!output.exists());
+}
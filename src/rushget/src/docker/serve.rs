@@ -0,0 +1,327 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use futures_util::TryStreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use crate::components::config::{DockerMirrorRuleset, RushGetConfig};
+use crate::components::credentials::CredentialResolver;
+use crate::components::registry::{client_key, ClientKey, RegistryClient};
+use crate::components::RushGetTask;
+use crate::docker::map_mirror_candidates;
+use crate::docker::reference::{ImageReference, DOCKER_HUB_REGISTRY};
+use crate::error::DockermirError;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type ProxyBody = BoxBody<Bytes, BoxError>;
+
+const DOCKER_CONTENT_DIGEST: &str = "Docker-Content-Digest";
+
+#[derive(Debug, Clone)]
+pub(crate) struct DockerServeOptions {
+    /// The address to listen on, e.g. `127.0.0.1:5000`
+    pub(crate) listen: SocketAddr,
+    /// Fetch from the original registry when all mirrors failed
+    pub(crate) fallback_to_upstream: bool,
+}
+
+/// Serve the registry API on the host, every pull through it is fetched from the mirrors
+pub(crate) struct DockerServeTask {
+    config: RushGetConfig,
+    options: DockerServeOptions,
+}
+
+impl DockerServeTask {
+    pub(crate) fn new(config: RushGetConfig, options: DockerServeOptions) -> Self {
+        DockerServeTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerServeTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let listener = TcpListener::bind(self.options.listen).await
+            .map_err(|e| DockermirError::ServeError {
+                address: self.options.listen.to_string(),
+                error: e.to_string(),
+            })?;
+        info!("Serving registry mirror proxy on http://{}, press Ctrl+C to stop", self.options.listen);
+        let fallback_to_upstream = self.options.fallback_to_upstream || self.config.docker.fallback_to_upstream;
+        let proxy = Arc::new(RegistryProxy::new(self.config, fallback_to_upstream));
+        tokio::select! {
+            _ = proxy.serve(listener) => Ok(()),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stop serving registry mirror proxy");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A pull through registry, the repository is looked up in the rulesets and fetched from the first mirror serving it
+pub(crate) struct RegistryProxy {
+    config: RushGetConfig,
+    fallback_to_upstream: bool,
+    resolver: CredentialResolver,
+    /// One client per registry and credential, so the tokens of the registry are reused between requests
+    clients: Mutex<HashMap<ClientKey, Arc<RegistryClient>>>,
+    /// The target which served the last manifest of each repository, blobs carry no tag to match the rules with
+    manifest_targets: Mutex<HashMap<String, ProxyTarget>>,
+}
+
+/// Where to fetch from, a mirror of a ruleset or the upstream registry
+#[derive(Clone)]
+struct ProxyTarget {
+    reference: ImageReference,
+    ruleset: Option<DockerMirrorRuleset>,
+}
+
+impl RegistryProxy {
+    pub(crate) fn new(config: RushGetConfig, fallback_to_upstream: bool) -> RegistryProxy {
+        RegistryProxy {
+            config,
+            fallback_to_upstream,
+            resolver: CredentialResolver::new(),
+            clients: Mutex::new(HashMap::new()),
+            manifest_targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, remote) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection, error: {}", e);
+                    continue;
+                }
+            };
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let proxy = proxy.clone();
+                    async move { Ok::<_, Infallible>(proxy.handle(request).await) }
+                });
+                if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                    trace!("Connection from {} closed with error: {}", remote, e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<ProxyBody> {
+        let method = request.method().clone();
+        if method != Method::GET && method != Method::HEAD {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "UNSUPPORTED", "the proxy is read only");
+        }
+        let path = request.uri().path().to_string();
+        if path == "/v2" || path == "/v2/" {
+            return respond(StatusCode::OK, Some("application/json"), Bytes::from_static(b"{}"));
+        }
+        // containerd tells the original registry of the mirrored image in the `ns` query
+        let namespace = request.uri().query().and_then(|query| {
+            query.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == "ns")
+                .map(|(_, value)| value.to_string())
+        });
+        let Some((name, kind, reference)) = path.strip_prefix("/v2/").and_then(split_registry_path) else {
+            return error_response(StatusCode::NOT_FOUND, "UNSUPPORTED", "unsupported path");
+        };
+        let source = source_image(name, namespace.as_deref(), kind, reference);
+        trace!("{} {} is the image: {}", method, path, source);
+        let head = method == Method::HEAD;
+        match kind {
+            "manifests" => self.proxy_manifest(&source, head).await,
+            _ => self.proxy_blob(&source, reference, head).await,
+        }
+    }
+
+    /// The mirrors of the image in the order of the config, and the upstream registry when enabled
+    fn targets(&self, source: &str) -> Result<Vec<ProxyTarget>, DockermirError> {
+        let mut targets = vec![];
+        for candidate in map_mirror_candidates(source, &self.config)? {
+            targets.push(ProxyTarget {
+                reference: ImageReference::parse(&candidate.mirror_image)?,
+                ruleset: Some(candidate.hit_ruleset),
+            });
+        }
+        if self.fallback_to_upstream {
+            targets.push(ProxyTarget {
+                reference: ImageReference::parse(source)?,
+                ruleset: None,
+            });
+        }
+        Ok(targets)
+    }
+
+    async fn client(&self, target: &ProxyTarget) -> Result<Arc<RegistryClient>, DockermirError> {
+        let registry = &target.reference.registry;
+        let auth = target.ruleset.as_ref().and_then(|ruleset| ruleset.auth.as_ref());
+        let credential = self.resolver.resolve(registry, auth).await?;
+        let key = client_key(registry, credential.as_ref());
+        if let Some(client) = self.clients.lock().unwrap().get(&key) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(RegistryClient::new().with_credential(registry, credential));
+        Ok(self.clients.lock().unwrap().entry(key).or_insert(client).clone())
+    }
+
+    async fn proxy_manifest(&self, source: &str, head: bool) -> Response<ProxyBody> {
+        let targets = match self.targets(source) {
+            Ok(targets) => targets,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, "NAME_INVALID", &e.to_string()),
+        };
+        let mut errors = vec![];
+        for target in &targets {
+            let result = match self.client(target).await {
+                Ok(client) => client.fetch_manifest(&target.reference).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(manifest) => {
+                    info!("Serve manifest of {} from {}", source, target.reference);
+                    if let Some(repository) = repository_of(source) {
+                        self.manifest_targets.lock().unwrap().insert(repository, target.clone());
+                    }
+                    let mut response = respond(StatusCode::OK, Some(&manifest.media_type),
+                                               if head { Bytes::new() } else { Bytes::from(manifest.content.clone()) });
+                    let headers = response.headers_mut();
+                    headers.insert(DOCKER_CONTENT_DIGEST, manifest.digest.parse().unwrap());
+                    headers.insert(CONTENT_LENGTH, manifest.content.len().into());
+                    return response;
+                }
+                Err(e) => {
+                    warn!("Failed to fetch manifest of {} from {}, error: {}", source, target.reference, e);
+                    errors.push(e);
+                }
+            }
+        }
+        failure_response(source, "MANIFEST_UNKNOWN", &errors)
+    }
+
+    /// The blob from the target which served the manifest of the repository, then from the mirrors the rules give
+    async fn proxy_blob(&self, source: &str, digest: &str, head: bool) -> Response<ProxyBody> {
+        let manifest_target = repository_of(source).and_then(|repository| self.manifest_targets.lock().unwrap().get(&repository).cloned());
+        let matched = match (self.targets(source), &manifest_target) {
+            (Ok(matched), _) => matched,
+            (Err(e), Some(_)) => {
+                trace!("No rule matches the blob of {}, error: {}", source, e);
+                vec![]
+            }
+            (Err(e), None) => return error_response(StatusCode::BAD_REQUEST, "NAME_INVALID", &e.to_string()),
+        };
+        let is_manifest_target = |target: &ProxyTarget| manifest_target.as_ref().is_some_and(|known| {
+            known.reference.registry == target.reference.registry && known.reference.repository == target.reference.repository
+        });
+        let targets: Vec<ProxyTarget> = manifest_target.iter().cloned()
+            .chain(matched.into_iter().filter(|target| !is_manifest_target(target)))
+            .collect();
+        let mut errors = vec![];
+        for target in &targets {
+            let result = match self.client(target).await {
+                Ok(client) => client.open_blob(&target.reference, digest, head).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(upstream) => {
+                    info!("Serve blob {} of {} from {}", digest, source, target.reference);
+                    let content_length = upstream.headers().get(CONTENT_LENGTH).cloned();
+                    let mut response = if head {
+                        respond(StatusCode::OK, Some("application/octet-stream"), Bytes::new())
+                    } else {
+                        // stream the blob through, layers can be gigabytes
+                        let stream = upstream.bytes_stream()
+                            .map_ok(Frame::data)
+                            .map_err(|e| Box::new(e) as BoxError);
+                        let mut response = Response::new(BodyExt::boxed(StreamBody::new(stream)));
+                        response.headers_mut().insert(CONTENT_TYPE, "application/octet-stream".parse().unwrap());
+                        response
+                    };
+                    let headers = response.headers_mut();
+                    headers.insert(DOCKER_CONTENT_DIGEST, digest.parse().unwrap());
+                    if let Some(content_length) = content_length {
+                        headers.insert(CONTENT_LENGTH, content_length);
+                    }
+                    return response;
+                }
+                Err(e) => {
+                    warn!("Failed to fetch blob {} of {} from {}, error: {}", digest, source, target.reference, e);
+                    errors.push(e);
+                }
+            }
+        }
+        failure_response(source, "BLOB_UNKNOWN", &errors)
+    }
+}
+
+/// Split `<name>/manifests/<reference>` or `<name>/blobs/<digest>`, the name may contain slashes
+fn split_registry_path(path: &str) -> Option<(&str, &str, &str)> {
+    if let Some((name, reference)) = path.rsplit_once("/manifests/") {
+        return Some((name, "manifests", reference));
+    }
+    if let Some((name, digest)) = path.rsplit_once("/blobs/") {
+        return Some((name, "blobs", digest));
+    }
+    None
+}
+
+/// `registry/repository` of the image, the same for every tag and digest of it
+fn repository_of(source: &str) -> Option<String> {
+    ImageReference::parse(source).ok().map(|reference| format!("{}/{}", reference.registry, reference.repository))
+}
+
+/// The original image of the request, from the `ns` query, a registry host as the first part of the name, or docker hub
+fn source_image(name: &str, namespace: Option<&str>, kind: &str, reference: &str) -> String {
+    let registry = match namespace {
+        Some(namespace) => namespace.to_string(),
+        None => match name.split_once('/') {
+            Some((host, _)) if host.contains('.') || host.contains(':') || host == "localhost" => String::new(),
+            _ => DOCKER_HUB_REGISTRY.to_string(),
+        },
+    };
+    let name = if registry.is_empty() { name.to_string() } else { format!("{}/{}", registry, name) };
+    if kind == "blobs" || reference.contains(':') {
+        format!("{}@{}", name, reference)
+    } else {
+        format!("{}:{}", name, reference)
+    }
+}
+
+fn respond(status: StatusCode, content_type: Option<&str>, body: Bytes) -> Response<ProxyBody> {
+    let mut response = Response::new(Full::new(body).map_err(|never| match never {}).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert("Docker-Distribution-API-Version", "registry/2.0".parse().unwrap());
+    if let Some(content_type) = content_type {
+        response.headers_mut().insert(CONTENT_TYPE, content_type.parse().unwrap());
+    }
+    response
+}
+
+fn error_response(status: StatusCode, code: &str, message: &str) -> Response<ProxyBody> {
+    let body = serde_json::json!({ "errors": [{ "code": code, "message": message }] });
+    respond(status, Some("application/json"), Bytes::from(body.to_string()))
+}
+
+/// Not found when no mirror has the image, so the engine falls back to the original registry by itself
+fn failure_response(source: &str, code: &str, errors: &[DockermirError]) -> Response<ProxyBody> {
+    if errors.iter().all(|e| matches!(e, DockermirError::RegistryNotFound { .. })) {
+        return error_response(StatusCode::NOT_FOUND, code, &format!("{} is not served by any mirror", source));
+    }
+    let message = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ");
+    error_response(StatusCode::BAD_GATEWAY, "UNAVAILABLE", &message)
+}
@@ -0,0 +1,167 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, RegistryAuthConfig};
+use crate::components::oci_layout::OciLayout;
+use crate::components::progress::PullProgress;
+use crate::components::registry::Platform;
+use crate::components::test_registry::TestRegistry;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+#[rstest]
+#[case("library/nginx", None, "manifests", "latest", "docker.io/library/nginx:latest")]
+#[case("dotnet/sdk", Some("mcr.microsoft.com"), "manifests", "6.0", "mcr.microsoft.com/dotnet/sdk:6.0")]
+#[case("mcr.microsoft.com/dotnet/sdk", None, "manifests", "sha256:abc", "mcr.microsoft.com/dotnet/sdk@sha256:abc")]
+#[case("localhost:5000/app", None, "blobs", "sha256:abc", "localhost:5000/app@sha256:abc")]
+fn source_images(_init_logger: (), #[case]name: &str, #[case]namespace: Option<&str>, #[case]kind: &str,
+                 #[case]reference: &str, #[case]expected: &str) {
+    assert_eq!(source_image(name, namespace, kind, reference), expected);
+}
+
+/// Two mirrors of the dotnet images, the first one is tried first
+fn two_mirror_config(primary: &str, secondary: &str) -> RushGetConfig {
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", primary)
+        .replace("${secondary}", secondary);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+async fn start_proxy(config: RushGetConfig, fallback_to_upstream: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    let proxy = Arc::new(RegistryProxy::new(config, fallback_to_upstream));
+    tokio::spawn(proxy.serve(listener));
+    host
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_through_proxy(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    secondary.require_token();
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer one", "layer two"]);
+    let proxy = start_proxy(two_mirror_config(&primary.host(), &secondary.host()), false).await;
+
+    // the engine asks for the image with the original registry in the name
+    let reference = ImageReference::parse(&format!("{}/mcr.microsoft.com/dotnet/sdk:6.0", proxy)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let layout = OciLayout::open_or_create(dir.path()).unwrap();
+    let progress = PullProgress::with_tty("test", false);
+    let descriptor = RegistryClient::new().pull_into_layout(&reference, &Platform::host(), &layout, &progress).await.unwrap();
+
+    assert_eq!(descriptor.digest, image.digest);
+    assert!(image.layers.iter().all(|layer| layout.contains_blob(&layer.digest)));
+    assert!(primary.requests().contains(&"GET /v2/primary/sdk/manifests/6.0".to_string()));
+    assert!(secondary.requests().contains(&format!("GET /v2/secondary/sdk/blobs/{}", image.layers[0].digest)));
+}
+
+#[rstest]
+#[tokio::test]
+async fn blobs_follow_the_manifest_mirror(_init_logger: ()) {
+    let mirror = TestRegistry::start().await;
+    let image = mirror.push_image("pinned/sdk", "6.0", &["layer"]);
+    // only the tag has a rule, the blob requests carry no tag
+    let yaml = format!(r#"
+name: "tag rule"
+version: "0.1.0"
+description: "a rule for one tag"
+github:
+  mirrors: []
+docker:
+  ruleset:
+    - name: "pinned"
+      mirror_host: "{}"
+      mirror_namespace: "pinned"
+      rules:
+        - name: "sdk 6.0"
+          match_regex: "mcr\\.microsoft\\.com/dotnet/sdk:6\\.0"
+          replace_template: "${{mirror_host}}/${{mirror_namespace}}/sdk:6.0"
+"#, mirror.host());
+    let proxy = start_proxy(ConfigLoader::default().load_config_yaml(&yaml).unwrap(), false).await;
+
+    let reference = ImageReference::parse(&format!("{}/mcr.microsoft.com/dotnet/sdk:6.0", proxy)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let layout = OciLayout::open_or_create(dir.path()).unwrap();
+    let progress = PullProgress::with_tty("test", false);
+    let descriptor = RegistryClient::new().pull_into_layout(&reference, &Platform::host(), &layout, &progress).await.unwrap();
+
+    assert_eq!(descriptor.digest, image.digest);
+    assert!(layout.contains_blob(&image.layers[0].digest));
+    assert!(mirror.requests().contains(&format!("GET /v2/pinned/sdk/blobs/{}", image.layers[0].digest)));
+}
+
+#[rstest]
+#[tokio::test]
+async fn mirrors_of_one_host_keep_their_credential(_init_logger: ()) {
+    let mirror = TestRegistry::start().await;
+    mirror.require_credential("robot", "secret");
+    let image = mirror.push_image("secondary/sdk", "6.0", &["layer"]);
+    let mut config = two_mirror_config(&mirror.host(), &mirror.host());
+    for (ruleset, password) in config.docker.ruleset.iter_mut().zip(["wrong", "secret"]) {
+        ruleset.auth = Some(RegistryAuthConfig {
+            username: "robot".to_string(),
+            password: password.to_string(),
+        });
+    }
+    let proxy = start_proxy(config, false).await;
+
+    let reference = ImageReference::parse(&format!("{}/mcr.microsoft.com/dotnet/sdk:6.0", proxy)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let layout = OciLayout::open_or_create(dir.path()).unwrap();
+    let progress = PullProgress::with_tty("test", false);
+    let descriptor = RegistryClient::new().pull_into_layout(&reference, &Platform::host(), &layout, &progress).await.unwrap();
+
+    assert_eq!(descriptor.digest, image.digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn head_manifest_with_namespace(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let image = primary.push_image("primary/sdk", "6.0", &["layer"]);
+    let proxy = start_proxy(two_mirror_config(&primary.host(), &primary.host()), false).await;
+
+    // containerd keeps the original name and tells the registry in the ns query
+    let response = reqwest::Client::new()
+        .head(format!("http://{}/v2/dotnet/sdk/manifests/6.0?ns=mcr.microsoft.com", proxy))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()[DOCKER_CONTENT_DIGEST], image.digest.as_str());
+
+    let response = reqwest::get(format!("http://{}/v2/", proxy)).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["Docker-Distribution-API-Version"], "registry/2.0");
+}
+
+#[rstest]
+#[tokio::test]
+async fn unmatched_image_is_not_found(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let proxy = start_proxy(two_mirror_config(&primary.host(), &primary.host()), false).await;
+
+    let response = reqwest::get(format!("http://{}/v2/library/nginx/manifests/latest", proxy)).await.unwrap();
+    assert_eq!(response.status(), 404);
+    assert!(response.text().await.unwrap().contains("MANIFEST_UNKNOWN"));
+
+    // matched but missing on every mirror
+    let response = reqwest::get(format!("http://{}/v2/mcr.microsoft.com/dotnet/sdk/manifests/missing", proxy)).await.unwrap();
+    assert_eq!(response.status(), 404);
+    assert!(primary.requests().contains(&"GET /v2/primary/sdk/manifests/missing".to_string()));
+
+    let response = reqwest::Client::new()
+        .delete(format!("http://{}/v2/mcr.microsoft.com/dotnet/sdk/manifests/6.0", proxy))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 405);
+}
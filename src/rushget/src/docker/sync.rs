@@ -0,0 +1,247 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use regex::Regex;
+use crate::components::config::{RegistryAuthConfig, RushGetConfig};
+use crate::components::credentials::CredentialResolver;
+use crate::components::progress::PullProgress;
+use crate::components::registry::{client_key, ClientKey, Descriptor, FetchedManifest, RegistryClient};
+use crate::components::RushGetTask;
+use crate::docker::map_mirror_candidates;
+use crate::docker::reference::ImageReference;
+use crate::error::DockermirError;
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DockerSyncOptions {
+    /// Upstream images, the tag may be a pattern like `8.0*` which is matched against the tags of the repository
+    pub(crate) images: Vec<String>,
+    /// A file with one upstream image per line, `#` starts a comment
+    pub(crate) file: Option<PathBuf>,
+    /// Only sync into the mirrors of this ruleset
+    pub(crate) ruleset: Option<String>,
+    /// Only report what would be copied
+    pub(crate) dry_run: bool,
+}
+
+/// Copy upstream images into the mirrors they are mapped to by the rulesets, registry to registry
+pub(crate) struct DockerSyncTask {
+    config: RushGetConfig,
+    options: DockerSyncOptions,
+}
+
+impl DockerSyncTask {
+    pub(crate) fn new(config: RushGetConfig, options: DockerSyncOptions) -> Self {
+        DockerSyncTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncOutcome {
+    Copied,
+    /// The mirror serves the same digest already
+    UpToDate,
+    /// Dry run, the mirror is missing the image or serves another digest
+    Outdated,
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerSyncTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let mut sources = self.options.images.clone();
+        if let Some(file) = &self.options.file {
+            sources.extend(read_image_list(file)?);
+        }
+        let syncer = Syncer::new();
+        let mut total = 0;
+        let mut failed = 0;
+        for pattern in &sources {
+            let images = match syncer.expand(pattern).await {
+                Ok(images) => images,
+                Err(e) => {
+                    error!("Failed to list the tags of {}, error: {}", pattern, e);
+                    total += 1;
+                    failed += 1;
+                    continue;
+                }
+            };
+            if images.is_empty() {
+                warn!("No tag matches {}", pattern);
+            }
+            for image in images {
+                total += 1;
+                if let Err(e) = self.sync_image(&syncer, &image).await {
+                    error!("Failed to sync image: {}, error: {}", image, e);
+                    failed += 1;
+                }
+            }
+        }
+        info!("Synced {} images, {} failed", total - failed, failed);
+        if failed > 0 {
+            return Err(DockermirError::BatchSyncFailed { failed, total });
+        }
+        Ok(())
+    }
+}
+
+impl DockerSyncTask {
+    async fn sync_image(&self, syncer: &Syncer, image: &str) -> Result<(), DockermirError> {
+        let mut candidates = map_mirror_candidates(image, &self.config)?;
+        if let Some(ruleset) = &self.options.ruleset {
+            candidates.retain(|candidate| &candidate.hit_ruleset.name == ruleset);
+        }
+        // pulls fall back through every matching rule, so every mirror they may try is filled, each target once
+        let mut seen_targets = vec![];
+        candidates.retain(|candidate| {
+            let first = !seen_targets.contains(&candidate.mirror_image);
+            seen_targets.push(candidate.mirror_image.clone());
+            first
+        });
+        if candidates.is_empty() {
+            return Err(DockermirError::MismatchAllRule);
+        }
+        let source = ImageReference::parse(image)?;
+        for candidate in candidates {
+            let target = ImageReference::parse(&candidate.mirror_image)?;
+            let target_client = syncer.client(&target.registry, candidate.hit_ruleset.auth.as_ref()).await?;
+            let source_client = syncer.client(&source.registry, None).await?;
+            let outcome = copy_image(&source_client, &source, &target_client, &target, self.options.dry_run).await?;
+            match outcome {
+                SyncOutcome::Copied => info!("Copied {} to {}", source, target),
+                SyncOutcome::UpToDate => info!("{} is up to date in {}", source, target),
+                SyncOutcome::Outdated => info!("{} would be copied to {}", source, target),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The registry clients of the run, so tokens are reused between the images of a registry and credential
+struct Syncer {
+    resolver: CredentialResolver,
+    clients: tokio::sync::Mutex<HashMap<ClientKey, Arc<RegistryClient>>>,
+}
+
+impl Syncer {
+    fn new() -> Syncer {
+        Syncer {
+            resolver: CredentialResolver::new(),
+            clients: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn client(&self, registry: &str, auth: Option<&RegistryAuthConfig>)
+                    -> Result<Arc<RegistryClient>, DockermirError> {
+        let credential = self.resolver.resolve(registry, auth).await?;
+        let key = client_key(registry, credential.as_ref());
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(RegistryClient::new().with_credential(registry, credential));
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// The images of the pattern, the tags of the repository are listed when the tag has `*` or `?`
+    async fn expand(&self, pattern: &str) -> Result<Vec<String>, DockermirError> {
+        let Some((name, tag_pattern)) = split_tag_pattern(pattern) else {
+            return Ok(vec![pattern.to_string()]);
+        };
+        let reference = ImageReference::parse(name)?;
+        let tags = self.client(&reference.registry, None).await?.list_tags(&reference).await?;
+        let matcher = tag_matcher(tag_pattern);
+        let mut images: Vec<String> = tags.iter()
+            .filter(|tag| matcher.is_match(tag))
+            .map(|tag| format!("{}/{}:{}", reference.registry, reference.repository, tag))
+            .collect();
+        images.sort();
+        Ok(images)
+    }
+}
+
+pub(crate) fn read_image_list(path: &Path) -> Result<Vec<String>, DockermirError> {
+    let content = fs::read_to_string(path).map_err(|e| DockermirError::ImageListError {
+        path: path.display().to_string(),
+        error: e.to_string(),
+    })?;
+    Ok(content.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// `mcr.microsoft.com/dotnet/sdk:8.0*` is the name and the tag pattern, None if the tag is not a pattern
+fn split_tag_pattern(pattern: &str) -> Option<(&str, &str)> {
+    let (name, tag) = pattern.rsplit_once(':')?;
+    (!tag.contains('/') && tag.contains(['*', '?'])).then_some((name, tag))
+}
+
+fn tag_matcher(pattern: &str) -> Regex {
+    let regex = regex::escape(pattern).replace(r"\*", ".*").replace(r"\?", ".");
+    Regex::new(&format!("^{}$", regex)).unwrap()
+}
+
+/// Copy the manifest with everything it refers to, the images of an index are copied before the index
+pub(crate) async fn copy_image(source_client: &RegistryClient, source: &ImageReference,
+                               target_client: &RegistryClient, target: &ImageReference, dry_run: bool)
+                               -> Result<SyncOutcome, DockermirError> {
+    let manifest = source_client.fetch_manifest(source).await?;
+    match target_client.fetch_manifest(target).await {
+        Ok(existing) if existing.digest == manifest.digest => return Ok(SyncOutcome::UpToDate),
+        Ok(existing) => trace!("{} is {} in the mirror, {} upstream", target, existing.digest, manifest.digest),
+        Err(DockermirError::RegistryNotFound { .. }) => trace!("{} is missing in the mirror", target),
+        Err(e) => return Err(e),
+    }
+    if dry_run {
+        return Ok(SyncOutcome::Outdated);
+    }
+
+    // blobs are staged like in an OCI layout, download_blob reports its failures the same way
+    let staging = tempfile::tempdir().map_err(|e| DockermirError::OciLayoutError {
+        path: std::env::temp_dir().display().to_string(),
+        error: e.to_string(),
+    })?;
+    let progress = PullProgress::new(&source.to_string());
+    if manifest.is_index() {
+        for child in manifest.as_index()?.manifests {
+            let child_manifest = source_client.fetch_manifest(&source.with_digest(&child.digest)).await?;
+            copy_blobs(source_client, source, target_client, target, &child_manifest, staging.path(), &progress).await?;
+            target_client.push_manifest(&target.with_digest(&child.digest), &child_manifest).await?;
+        }
+    } else {
+        copy_blobs(source_client, source, target_client, target, &manifest, staging.path(), &progress).await?;
+    }
+    target_client.push_manifest(target, &manifest).await?;
+    Ok(SyncOutcome::Copied)
+}
+
+/// Copy the config and layers of the image manifest which the target does not have yet, each through a verified local file
+async fn copy_blobs(source_client: &RegistryClient, source: &ImageReference,
+                    target_client: &RegistryClient, target: &ImageReference,
+                    manifest: &FetchedManifest, staging: &Path, progress: &PullProgress) -> Result<(), DockermirError> {
+    let image = manifest.as_image()?;
+    let blobs: Vec<&Descriptor> = std::iter::once(&image.config).chain(image.layers.iter()).collect();
+    for descriptor in blobs {
+        if target_client.blob_exists(target, &descriptor.digest).await? {
+            progress.finish_layer(&descriptor.digest, "Already exists");
+            continue;
+        }
+        let file_name = descriptor.digest.replace(':', "-");
+        let file = staging.join(&file_name);
+        let partial = staging.join(format!("{}.partial", file_name));
+        source_client.download_blob(source, descriptor, &partial, &file, progress).await?;
+        progress.set_status(&descriptor.digest, "Pushing");
+        target_client.upload_blob(target, &descriptor.digest, &file).await?;
+        progress.finish_layer(&descriptor.digest, "Pushed");
+        let _ = fs::remove_file(&file);
+    }
+    Ok(())
+}
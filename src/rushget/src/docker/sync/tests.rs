@@ -0,0 +1,181 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::ConfigLoader;
+use crate::components::test_registry::TestRegistry;
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+/// Images of the upstream test registry are mapped to `secondary/<name>` in the mirror
+fn mirror_config(mirror: &str) -> RushGetConfig {
+    let yaml = include_str!("../fallback.yaml")
+        .replace("${primary}", mirror)
+        .replace("${secondary}", mirror);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+async fn sync(mirror: &TestRegistry, images: &[String], dry_run: bool) -> Result<(), DockermirError> {
+    DockerSyncTask::new(mirror_config(&mirror.host()), DockerSyncOptions {
+        images: images.to_vec(),
+        ruleset: Some("secondary".to_string()),
+        dry_run,
+        ..Default::default()
+    }).run().await
+}
+
+async fn mirror_digest(mirror: &TestRegistry, image: &str) -> String {
+    let reference = ImageReference::parse(&format!("{}/{}", mirror.host(), image)).unwrap();
+    RegistryClient::new().fetch_manifest(&reference).await.unwrap().digest
+}
+
+fn writes(registry: &TestRegistry) -> Vec<String> {
+    registry.requests().into_iter()
+        .filter(|request| request.starts_with("PUT") || request.starts_with("POST"))
+        .collect()
+}
+
+#[rstest]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0*", Some(("mcr.microsoft.com/dotnet/sdk", "8.0*")))]
+#[case("localhost:5000/app:v?", Some(("localhost:5000/app", "v?")))]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0", None)]
+#[case("localhost:5000/app", None)]
+fn split_tag_patterns(_init_logger: (), #[case]pattern: &str, #[case]expected: Option<(&str, &str)>) {
+    assert_eq!(split_tag_pattern(pattern), expected);
+}
+
+#[rstest]
+#[case("8.0*", "8.0-alpine", true)]
+#[case("8.0*", "18.0", false)]
+#[case("v?", "v1", true)]
+#[case("1.2.3", "1x2x3", false)]
+fn tag_matchers(_init_logger: (), #[case]pattern: &str, #[case]tag: &str, #[case]expected: bool) {
+    assert_eq!(tag_matcher(pattern).is_match(tag), expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn sync_copies_and_skips_up_to_date(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    mirror.require_token();
+    let image = upstream.push_image("dotnet/sdk", "6.0", &["layer one", "layer two"]);
+    let source = format!("{}/dotnet/sdk:6.0", upstream.host());
+
+    sync(&mirror, std::slice::from_ref(&source), false).await.unwrap();
+    assert_eq!(mirror_digest(&mirror, "secondary/sdk:6.0").await, image.digest);
+    let pushed = writes(&mirror).len();
+    assert!(pushed > 0);
+
+    sync(&mirror, &[source], false).await.unwrap();
+    assert_eq!(writes(&mirror).len(), pushed);
+}
+
+#[rstest]
+#[tokio::test]
+async fn sync_index_of_tag_pattern(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    let (digest, images) = upstream.push_index("dotnet/runtime", "8.0", &[("linux", "amd64"), ("linux", "arm64")]);
+    upstream.push_image("dotnet/runtime", "7.0", &["old"]);
+
+    sync(&mirror, &[format!("{}/dotnet/runtime:8.*", upstream.host())], false).await.unwrap();
+
+    assert_eq!(mirror_digest(&mirror, "secondary/runtime:8.0").await, digest);
+    for image in &images {
+        assert_eq!(mirror_digest(&mirror, &format!("secondary/runtime@{}", image.digest)).await, image.digest);
+    }
+    let reference = ImageReference::parse(&format!("{}/secondary/runtime", mirror.host())).unwrap();
+    let tags = RegistryClient::new().list_tags(&reference).await.unwrap();
+    assert!(tags.contains(&"8.0".to_string()));
+    assert!(!tags.contains(&"7.0".to_string()));
+}
+
+#[rstest]
+#[tokio::test]
+async fn sync_fills_every_rule_of_the_ruleset(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    let image = upstream.push_image("dotnet/sdk", "6.0", &["layer"]);
+    let yaml = format!(r#"
+name: "two rules"
+version: "0.1.0"
+description: "two mirrors of the same images in one ruleset"
+github:
+  mirrors: []
+docker:
+  ruleset:
+    - name: "mirror"
+      mirror_host: "{}"
+      mirror_namespace: "mirror"
+      rules:
+        - name: "dotnet"
+          match_regex: "^127\\.0\\.0\\.1:[0-9]+/dotnet/(.*):(.*)"
+          replace_template: "${{mirror_host}}/${{mirror_namespace}}/$1:$2"
+        - name: "dotnet with prefix"
+          match_regex: "^127\\.0\\.0\\.1:[0-9]+/dotnet/(.*):(.*)"
+          replace_template: "${{mirror_host}}/${{mirror_namespace}}/dotnet_$1:$2"
+        - name: "dotnet again"
+          match_regex: "^127\\.0\\.0\\.1:[0-9]+/dotnet/(.*):(.*)"
+          replace_template: "${{mirror_host}}/${{mirror_namespace}}/$1:$2"
+"#, mirror.host());
+
+    DockerSyncTask::new(ConfigLoader::default().load_config_yaml(&yaml).unwrap(), DockerSyncOptions {
+        images: vec![format!("{}/dotnet/sdk:6.0", upstream.host())],
+        ..Default::default()
+    }).run().await.unwrap();
+
+    assert_eq!(mirror_digest(&mirror, "mirror/sdk:6.0").await, image.digest);
+    assert_eq!(mirror_digest(&mirror, "mirror/dotnet_sdk:6.0").await, image.digest);
+    let manifest_pushes = writes(&mirror).iter().filter(|request| request.contains("/manifests/")).count();
+    assert_eq!(manifest_pushes, 2);
+}
+
+#[rstest]
+#[tokio::test]
+async fn sync_dry_run_writes_nothing(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_image("dotnet/sdk", "6.0", &["layer"]);
+
+    sync(&mirror, &[format!("{}/dotnet/sdk:6.0", upstream.host())], true).await.unwrap();
+
+    assert!(writes(&mirror).is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn sync_reports_missing_images(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+
+    let result = sync(&mirror, &[format!("{}/dotnet/sdk:6.0", upstream.host()), "docker.io/library/nginx".to_string()], false).await;
+
+    assert!(matches!(result, Err(DockermirError::BatchSyncFailed { failed: 2, total: 2 })), "{:?}", result);
+}
+
+#[rstest]
+#[tokio::test]
+async fn clients_of_one_registry_kept_by_credential(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let syncer = Syncer {
+        resolver: CredentialResolver::with_config_dir(dir.path().to_path_buf(), None),
+        clients: tokio::sync::Mutex::new(HashMap::new()),
+    };
+    let auth = RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: "secret".to_string(),
+    };
+
+    let anonymous = syncer.client("localhost:5000", None).await.unwrap();
+    let authenticated = syncer.client("localhost:5000", Some(&auth)).await.unwrap();
+
+    assert!(!Arc::ptr_eq(&anonymous, &authenticated));
+    assert!(Arc::ptr_eq(&anonymous, &syncer.client("localhost:5000", None).await.unwrap()));
+    assert!(Arc::ptr_eq(&authenticated, &syncer.client("localhost:5000", Some(&auth)).await.unwrap()));
+}
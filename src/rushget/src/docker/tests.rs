@@ -1,6 +1,11 @@
 use log::LevelFilter;
 use rstest::*;
-use crate::components::config::{ConfigLoader, DEFAULT_CONFIG_YAML};
+use crate::components::config::{ConfigLoader, RegistryAuthConfig, DEFAULT_CONFIG_YAML};
+use crate::components::credentials::{CredentialSource, IDENTITY_TOKEN_USERNAME};
+use crate::components::oci_layout::{ANNOTATION_IMAGE_NAME, ANNOTATION_REF_NAME};
+use crate::components::container_engine::ImageInspect;
+use crate::components::registry::{ImageManifest, MEDIA_TYPE_OCI_MANIFEST};
+use crate::components::test_registry::{TestImage, TestRegistry};
 use super::*;
 
 #[fixture]
@@ -51,4 +56,651 @@ fn map_failed(init_logger: ()) {
     let source = "mcr.microsoft.com/java/jdk:15u2-zulu-ubuntu-18.04";
     let result = map_mirror_by_configuration(source, &config);
     assert!(result.is_err());
-}
\ No newline at end of file
+}
+
+fn local_mirror_config(mirror_host: &str) -> RushGetConfig {
+    let yaml = format!(r#"name: "local mirror"
+version: "0.1.0"
+description: "mirror served by a test registry"
+github:
+  mirrors: []
+docker:
+  ruleset:
+    - name: "local"
+      mirror_host: "{}"
+      mirror_namespace: "newbe36524"
+      rules:
+        - name: "mcr dotnet"
+          match_regex: "mcr\\.microsoft\\.com/dotnet/(.*):(.*)"
+          replace_template: "${{mirror_host}}/${{mirror_namespace}}/$1:$2"
+"#, mirror_host);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_into_oci_layout_without_daemon(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer one", "layer two"]);
+    let dir = tempfile::tempdir().unwrap();
+    let config = local_mirror_config(&registry.host());
+
+    let result = DockerPullTask::new(config, "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    let layout = OciLayout::open_or_create(dir.path()).unwrap();
+    let index = layout.read_index().unwrap();
+    assert_eq!(index.manifests.len(), 1);
+    assert_eq!(index.manifests[0].digest, image.digest);
+    let annotations = index.manifests[0].annotations.as_ref().unwrap();
+    assert_eq!(annotations[ANNOTATION_IMAGE_NAME], "mcr.microsoft.com/dotnet/sdk:6.0");
+    assert_eq!(annotations[ANNOTATION_REF_NAME], "6.0");
+    assert!(image.layers.iter().all(|layer| layout.contains_blob(&layer.digest)));
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_into_oci_layout_with_ruleset_auth(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    registry.require_credential("robot", "secret");
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer"]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = local_mirror_config(&registry.host());
+    let secrets = tempfile::tempdir().unwrap();
+    let password = secrets.path().join("password");
+    std::fs::write(&password, "secret").unwrap();
+    config.docker.ruleset[0].auth = Some(RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: format!("${{file:{}}}", password.display()),
+    });
+
+    let result = DockerPullTask::new(config, "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert!(OciLayout::open_or_create(dir.path()).unwrap().contains_blob(&image.digest));
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_into_oci_layout_selects_platform(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let (_, images) = registry.push_index("newbe36524/sdk", "6.0", &[("linux", "amd64"), ("linux", "arm64")]);
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = DockerPullTask::new(local_mirror_config(&registry.host()), "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        platform: Some("linux/arm64/v8".parse().unwrap()),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests[0].digest, images[1].digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_with_engine_skips_mirror_without_platform(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    registry.push_index("newbe36524/sdk", "6.0", &[("linux", "amd64")]);
+    let mirror_image = map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:6.0", &local_mirror_config(&registry.host())).unwrap();
+    let engine = FakeEngine::default();
+    let platform: Platform = "linux/arm64".parse().unwrap();
+
+    let result = pull_with_engine(&engine, &mirror_image, None, Some(&platform), &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await;
+
+    assert!(matches!(result, Err(DockermirError::PlatformNotFound { .. })));
+    assert!(engine.calls.lock().unwrap().is_empty());
+
+    let platform: Platform = "linux/amd64".parse().unwrap();
+    pull_with_engine(&engine, &mirror_image, None, Some(&platform), &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await.unwrap();
+    assert_eq!(engine.calls.lock().unwrap()[0], format!("pull {} linux/amd64", mirror_image.mirror_image));
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_with_engine_logs_in_first(_init_logger: ()) {
+    let config = ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let mirror_image = map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:8.0", &config).unwrap();
+    let credential = RegistryCredential {
+        username: "robot".to_string(),
+        secret: "secret".to_string(),
+        source: CredentialSource::Config,
+    };
+    let engine = FakeEngine::default();
+    pull_with_engine(&engine, &mirror_image, Some(&credential), None, &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await.unwrap();
+    assert_eq!(engine.calls.lock().unwrap()[..2], [
+        "login registry.cn-hangzhou.aliyuncs.com robot".to_string(),
+        "pull registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:8.0".to_string(),
+    ]);
+
+    // the engine reads the docker config by itself
+    let engine = FakeEngine::default();
+    let credential = RegistryCredential {
+        source: CredentialSource::DockerConfig,
+        ..credential
+    };
+    pull_with_engine(&engine, &mirror_image, Some(&credential), None, &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await.unwrap();
+    assert!(engine.calls.lock().unwrap()[0].starts_with("pull "));
+
+    // `docker login` can not take an identity token
+    let engine = FakeEngine::default();
+    let credential = RegistryCredential {
+        username: IDENTITY_TOKEN_USERNAME.to_string(),
+        source: CredentialSource::Config,
+        ..credential
+    };
+    let result = pull_with_engine(&engine, &mirror_image, Some(&credential), None, &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await;
+    assert!(matches!(result, Err(DockermirError::DockerLoginError { .. })), "{:?}", result);
+    assert!(engine.calls.lock().unwrap().is_empty());
+}
+
+pub const STRUCTURED_YAML: &str = include_str!("structured.yaml");
+
+#[rstest]
+#[case("nginx", "registry.cn-hangzhou.aliyuncs.com/newbe36524/library/nginx:latest")]
+#[case("library/nginx", "registry.cn-hangzhou.aliyuncs.com/newbe36524/library/nginx:latest")]
+#[case("docker.io/library/nginx:latest", "registry.cn-hangzhou.aliyuncs.com/newbe36524/library/nginx:latest")]
+#[case("nginx:1.25-alpine", "registry.cn-hangzhou.aliyuncs.com/newbe36524/library/nginx:1.25-alpine")]
+#[case("mcr.microsoft.com/dotnet/sdk", "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:latest")]
+fn map_canonical_form(_init_logger: (), #[case] source: &str, #[case] expected: &str) {
+    let loader = ConfigLoader::default();
+    let config = loader.load_config_yaml(STRUCTURED_YAML).unwrap();
+    let result = map_mirror_by_configuration(source, &config).unwrap();
+    assert_eq!(result.mirror_image, expected);
+    assert_eq!(result.source_image, source);
+}
+
+#[rstest]
+fn map_invalid_reference(_init_logger: ()) {
+    let loader = ConfigLoader::default();
+    let config = loader.load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let result = map_mirror_by_configuration("mcr.microsoft.com/dotnet/SDK:8.0", &config);
+    assert!(matches!(result, Err(DockermirError::InvalidImageReference(_))));
+}
+
+const DIGEST: &str = "sha256:0000000000000000000000000000000000000000000000000000000000000001";
+
+#[rstest]
+#[case(&format!("mcr.microsoft.com/dotnet/sdk@{}", DIGEST), &format!("registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk@{}", DIGEST), None)]
+#[case(&format!("mcr.microsoft.com/dotnet/sdk:8.0@{}", DIGEST), &format!("registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk@{}", DIGEST), Some("mcr.microsoft.com/dotnet/sdk:8.0"))]
+#[case("mcr.microsoft.com/dotnet/sdk:8.0", "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:8.0", Some("mcr.microsoft.com/dotnet/sdk:8.0"))]
+fn map_digest_pinned(_init_logger: (), #[case] source: &str, #[case] expected: &str, #[case] tag_target: Option<&str>) {
+    let loader = ConfigLoader::default();
+    let config = loader.load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let result = map_mirror_by_configuration(source, &config).unwrap();
+    assert_eq!(result.mirror_image, expected);
+    assert_eq!(result.tag_target().as_deref(), tag_target);
+}
+
+/// Engine which keeps the calls in memory instead of running a container engine
+#[derive(Default)]
+pub(crate) struct FakeEngine {
+    pub(crate) repo_digests: Vec<String>,
+    /// The id of the local image, `sha256:image` if not set
+    pub(crate) image_id: Option<String>,
+    /// The image is missing in the local image store
+    pub(crate) missing: bool,
+    pub(crate) calls: std::sync::Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl ContainerEngine for FakeEngine {
+    fn kind(&self) -> ContainerEngineKind {
+        ContainerEngineKind::Docker
+    }
+
+    async fn login(&self, registry: &str, credential: &RegistryCredential) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("login {} {}", registry, credential.username));
+        Ok(())
+    }
+
+    async fn pull(&self, input: &DockermirPullInput, _progress: &PullProgress) -> Result<(), DockermirError> {
+        match &input.platform {
+            Some(platform) => self.calls.lock().unwrap().push(format!("pull {} {}", input.mirror_image, platform)),
+            None => self.calls.lock().unwrap().push(format!("pull {}", input.mirror_image)),
+        }
+        Ok(())
+    }
+
+    async fn tag(&self, input: &DockermirPullInput) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("tag {} {}", input.mirror_image, input.source_image));
+        Ok(())
+    }
+
+    async fn rmi(&self, image: &str) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("rmi {}", image));
+        Ok(())
+    }
+
+    async fn load(&self, archive: &Path, _progress: &PullProgress) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("load {}", archive.display()));
+        Ok(())
+    }
+
+    async fn push(&self, image: &str, _progress: &PullProgress) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("push {}", image));
+        Ok(())
+    }
+
+    async fn build(&self, args: &[String]) -> Result<(), DockermirError> {
+        self.calls.lock().unwrap().push(format!("build {}", args.join(" ")));
+        Ok(())
+    }
+
+    async fn inspect(&self, _image: &str) -> Result<Option<ImageInspect>, DockermirError> {
+        if self.missing {
+            return Ok(None);
+        }
+        Ok(Some(ImageInspect {
+            id: self.image_id.clone().unwrap_or_else(|| "sha256:image".to_string()),
+            repo_tags: None,
+            repo_digests: Some(self.repo_digests.clone()),
+        }))
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn present_locally_when_digest_matches(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer"]);
+    let mirror_image = map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:6.0", &local_mirror_config(&registry.host())).unwrap();
+    let mirror_repository = mirror_image.mirror_image.rsplit_once(':').unwrap().0;
+    let cases = [
+        (FakeEngine { repo_digests: vec![format!("{}@{}", mirror_repository, image.digest)], ..Default::default() }, true),
+        // docker and podman report the digest of the config as the id, podman without the algorithm
+        (FakeEngine { image_id: Some(image.config.digest.clone()), ..Default::default() }, true),
+        (FakeEngine { image_id: Some(image.config.digest.trim_start_matches("sha256:").to_string()), ..Default::default() }, true),
+        (FakeEngine { repo_digests: vec![format!("mcr.microsoft.com/dotnet/sdk@{}", DIGEST)], ..Default::default() }, false),
+        (FakeEngine { image_id: Some(image.config.digest.clone()), missing: true, ..Default::default() }, false),
+    ];
+
+    for (engine, expected) in cases {
+        assert_eq!(is_present_locally(&engine, &mirror_image, None, None).await, expected, "{:?}", engine.image_id);
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn present_locally_unknown_without_mirror(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let mirror_image = map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:6.0", &local_mirror_config(&registry.host())).unwrap();
+    let engine = FakeEngine {
+        image_id: Some(DIGEST.to_string()),
+        ..Default::default()
+    };
+
+    assert!(!is_present_locally(&engine, &mirror_image, None, None).await);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_digest_pinned_verified(_init_logger: ()) {
+    let config = ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let mirror_image = map_mirror_by_configuration(&format!("mcr.microsoft.com/dotnet/sdk:8.0@{}", DIGEST), &config).unwrap();
+    let engine = FakeEngine {
+        repo_digests: vec![format!("registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk@{}", DIGEST)],
+        ..Default::default()
+    };
+    pull_with_engine(&engine, &mirror_image, None, None, &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await.unwrap();
+    let mirror = format!("registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk@{}", DIGEST);
+    assert_eq!(*engine.calls.lock().unwrap(), vec![
+        format!("pull {}", mirror),
+        format!("tag {} mcr.microsoft.com/dotnet/sdk:8.0", mirror),
+        format!("rmi {}", mirror),
+    ]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_with_engine_post_pull(_init_logger: ()) {
+    let config = ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let mirror_image = map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:8.0", &config).unwrap();
+    let engine = FakeEngine::default();
+    let post_pull = PostPullConfig {
+        keep_mirror_tag: true,
+        tags: vec!["registry.corp/${repository}".to_string(), "sdk:ci".to_string()],
+    };
+
+    pull_with_engine(&engine, &mirror_image, None, None, &post_pull, &PullProgress::with_tty("test", false)).await.unwrap();
+
+    let mirror = "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:8.0";
+    assert_eq!(*engine.calls.lock().unwrap(), vec![
+        format!("pull {}", mirror),
+        format!("tag {} mcr.microsoft.com/dotnet/sdk:8.0", mirror),
+        "tag mcr.microsoft.com/dotnet/sdk:8.0 registry.corp/dotnet/sdk:8.0".to_string(),
+        "tag mcr.microsoft.com/dotnet/sdk:8.0 docker.io/library/sdk:ci".to_string(),
+    ]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_digest_pinned_mismatch(_init_logger: ()) {
+    let config = ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    let mirror_image = map_mirror_by_configuration(&format!("mcr.microsoft.com/dotnet/sdk@{}", DIGEST), &config).unwrap();
+    let engine = FakeEngine {
+        repo_digests: vec!["registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk@sha256:stale".to_string()],
+        ..Default::default()
+    };
+    let result = pull_with_engine(&engine, &mirror_image, None, None, &PostPullConfig::default(), &PullProgress::with_tty("test", false)).await;
+    assert!(matches!(result, Err(DockermirError::ImageDigestMismatch { .. })), "{:?}", result);
+    assert!(engine.calls.lock().unwrap().iter().all(|call| !call.starts_with("tag")));
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_into_oci_layout_digest_mismatch(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer"]);
+    let tampered = registry.push_image("newbe36524/sdk", "6.0-tampered", &["tampered layer"]);
+    registry.put_manifest_at("newbe36524/sdk", &image.digest, MEDIA_TYPE_OCI_MANIFEST,
+                             &serde_json::to_vec(&tampered_manifest(&tampered)).unwrap());
+    let dir = tempfile::tempdir().unwrap();
+    let config = local_mirror_config(&registry.host());
+
+    let result = DockerPullTask::new(config, format!("mcr.microsoft.com/dotnet/sdk:6.0@{}", image.digest), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    match result {
+        Err(DockermirError::AllMirrorsFailed { attempts, .. }) => {
+            assert!(attempts[0].contains("digest of the pulled image is mismatched"), "{}", attempts[0]);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+fn tampered_manifest(image: &TestImage) -> ImageManifest {
+    ImageManifest {
+        schema_version: 2,
+        media_type: Some(MEDIA_TYPE_OCI_MANIFEST.to_string()),
+        config: image.config.clone(),
+        layers: image.layers.clone(),
+    }
+}
+
+pub const FALLBACK_YAML: &str = include_str!("fallback.yaml");
+
+fn fallback_config(primary: &str, secondary: &str) -> RushGetConfig {
+    let yaml = FALLBACK_YAML.replace("${primary}", primary).replace("${secondary}", secondary);
+    ConfigLoader::default().load_config_yaml(&yaml).unwrap()
+}
+
+#[rstest]
+fn map_candidates_in_config_order(_init_logger: ()) {
+    let config = fallback_config("primary.io", "secondary.io");
+    let candidates = map_mirror_candidates("mcr.microsoft.com/dotnet/sdk:8.0", &config).unwrap();
+    let mirrors: Vec<&str> = candidates.iter().map(|candidate| candidate.mirror_image.as_str()).collect();
+    assert_eq!(mirrors, vec![
+        "primary.io/primary/sdk:8.0",
+        "primary.io/primary/dotnet_sdk:8.0",
+        "secondary.io/secondary/sdk:8.0",
+    ]);
+    assert_eq!(map_mirror_by_configuration("mcr.microsoft.com/dotnet/sdk:8.0", &config).unwrap().mirror_image, mirrors[0]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_falls_back_to_next_mirror(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = DockerPullTask::new(fallback_config(&primary.host(), &secondary.host()), "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert_eq!(primary.requests(), vec![
+        "GET /v2/primary/sdk/manifests/6.0",
+        "GET /v2/primary/dotnet_sdk/manifests/6.0",
+    ]);
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests[0].digest, image.digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_skips_mirror_with_unresolved_credential(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let image = secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = fallback_config(&primary.host(), &secondary.host());
+    config.docker.ruleset[0].auth = Some(RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: "${env:RUSHGET_TEST_UNSET_PASSWORD}".to_string(),
+    });
+
+    let result = DockerPullTask::new(config, "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert!(primary.requests().is_empty());
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests[0].digest, image.digest);
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_reports_all_attempts(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = DockerPullTask::new(fallback_config(&primary.host(), &secondary.host()), "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    match result {
+        Err(DockermirError::AllMirrorsFailed { source_image, attempts }) => {
+            assert_eq!(source_image, "mcr.microsoft.com/dotnet/sdk:6.0");
+            assert_eq!(attempts.len(), 3);
+            assert!(attempts[2].contains("(secondary/mcr dotnet)"), "{}", attempts[2]);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn pull_falls_back_to_upstream(_init_logger: ()) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    let image = upstream.push_image("dotnet/sdk", "6.0", &["layer"]);
+    let dir = tempfile::tempdir().unwrap();
+    let source = format!("{}/dotnet/sdk:6.0", upstream.host());
+    let options = || DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        fallback_to_upstream: true,
+        ..Default::default()
+    };
+
+    let result = DockerPullTask::new(fallback_config(&mirror.host(), &mirror.host()), source.clone(), options()).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert_eq!(mirror.requests(), vec!["GET /v2/secondary/sdk/manifests/6.0"]);
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests[0].digest, image.digest);
+}
+
+#[rstest]
+#[case(false)]
+#[case(true)]
+#[tokio::test]
+async fn pull_require_fresh_skips_stale_mirror(_init_logger: (), #[case]fallback_to_upstream: bool) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    let image = upstream.push_image("dotnet/sdk", "8.0", &["new layer"]);
+    mirror.push_image("secondary/sdk", "8.0", &["old layer"]);
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = DockerPullTask::new(fallback_config(&mirror.host(), &mirror.host()), format!("{}/dotnet/sdk:8.0", upstream.host()), DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        fallback_to_upstream,
+        require_fresh: true,
+        ..Default::default()
+    }).run().await;
+
+    if fallback_to_upstream {
+        assert!(result.is_ok(), "{:?}", result.err());
+        let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+        assert_eq!(index.manifests[0].digest, image.digest);
+    } else {
+        match result {
+            Err(DockermirError::AllMirrorsFailed { attempts, .. }) => assert!(attempts[0].contains("behind upstream"), "{}", attempts[0]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+    assert!(!mirror.requests().iter().any(|request| request.starts_with("GET /v2/secondary/sdk/blobs")));
+}
+
+#[rstest]
+#[tokio::test]
+async fn batch_pull_reports_each_image(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    registry.push_image("newbe36524/sdk", "6.0", &["sdk"]);
+    registry.push_image("newbe36524/runtime", "6.0", &["runtime"]);
+    let dir = tempfile::tempdir().unwrap();
+    let images = vec![
+        "mcr.microsoft.com/dotnet/sdk:6.0".to_string(),
+        "mcr.microsoft.com/dotnet/aspnet:6.0".to_string(),
+        "mcr.microsoft.com/dotnet/runtime:6.0".to_string(),
+    ];
+
+    let result = DockerBatchPullTask::new(local_mirror_config(&registry.host()), images, DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    }).run().await;
+
+    assert_eq!(result, Err(DockermirError::BatchPullFailed { failed: 1, total: 3 }));
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests.len(), 2);
+}
+
+#[rstest]
+#[tokio::test]
+async fn batch_pull_with_bounded_concurrency(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let images: Vec<String> = (0..6).map(|i| {
+        registry.push_image(&format!("newbe36524/app{}", i), "6.0", &["shared base layer", &format!("app {}", i)]);
+        format!("mcr.microsoft.com/dotnet/app{}:6.0", i)
+    }).collect();
+    registry.delay(std::time::Duration::from_millis(50));
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = DockerBatchPullTask::new(local_mirror_config(&registry.host()), images, DockerPullOptions {
+        oci_layout: Some(dir.path().to_path_buf()),
+        concurrency: Some(2),
+        ..Default::default()
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert_eq!(registry.max_in_flight(), 2);
+    let index = OciLayout::open_or_create(dir.path()).unwrap().read_index().unwrap();
+    assert_eq!(index.manifests.len(), 6);
+}
+
+#[rstest]
+#[tokio::test]
+async fn batch_result_of_panicking_pull(_init_logger: ()) {
+    let images = vec!["nginx".to_string(), "redis".to_string()];
+    let mut tasks = JoinSet::new();
+    let mut indexes = HashMap::new();
+    indexes.insert(tasks.spawn(async { Ok(()) }).id(), 0);
+    indexes.insert(tasks.spawn(async { panic!("broken pull") }).id(), 1);
+
+    let mut results = vec![];
+    while let Some(joined) = tasks.join_next_with_id().await {
+        results.push(batch_result(&images, &indexes, joined));
+    }
+    results.sort_by_key(|(index, _)| *index);
+
+    assert_eq!(results[0], (0, Ok(())));
+    assert!(matches!(&results[1], (1, Err(DockermirError::PullTaskPanicked { image, .. })) if image == "redis"), "{:?}", results[1]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn check_mirror_reports_status(_init_logger: ()) {
+    let registry = TestRegistry::start().await;
+    let image = registry.push_image("newbe36524/sdk", "6.0", &["layer one", "layer two"]);
+    let (index_digest, _) = registry.push_index("newbe36524/runtime", "6.0", &[("linux", "amd64"), ("linux", "arm64")]);
+    let client = RegistryClient::new();
+    let reference = |image: &str| ImageReference::parse(&format!("{}/{}", registry.host(), image)).unwrap();
+
+    let size = image.config.size + image.layers.iter().map(|layer| layer.size).sum::<u64>();
+    assert_eq!(check_mirror(&client, &reference("newbe36524/sdk:6.0")).await, MirrorStatus::Exists {
+        digest: image.digest,
+        size: Some(size),
+        platforms: vec!["linux/amd64".parse().unwrap()],
+    });
+    assert_eq!(check_mirror(&client, &reference("newbe36524/runtime:6.0")).await, MirrorStatus::Exists {
+        digest: index_digest,
+        size: None,
+        platforms: vec!["linux/amd64".parse().unwrap(), "linux/arm64".parse().unwrap()],
+    });
+    assert_eq!(check_mirror(&client, &reference("newbe36524/sdk:7.0")).await, MirrorStatus::Missing);
+
+    registry.require_credential("robot", "secret");
+    assert_eq!(check_mirror(&RegistryClient::new(), &reference("newbe36524/sdk:6.0")).await, MirrorStatus::AuthRequired);
+}
+
+#[rstest]
+#[case::served("6.0", None, true)]
+#[case::missing("7.0", None, false)]
+#[case::platform_served("6.0", Some("linux/amd64"), true)]
+#[case::platform_missing("6.0", Some("linux/arm64"), false)]
+#[tokio::test]
+async fn check_task_queries_mirrors(_init_logger: (), #[case]tag: &str, #[case]platform: Option<&str>, #[case]expected: bool) {
+    let upstream = TestRegistry::start().await;
+    let mirror = TestRegistry::start().await;
+    upstream.push_image("dotnet/sdk", "6.0", &["layer"]);
+    mirror.push_image("secondary/sdk", "6.0", &["layer"]);
+    let yaml = include_str!("fallback.yaml")
+        .replace("${primary}", &mirror.host())
+        .replace("${secondary}", &mirror.host());
+    let config = ConfigLoader::default().load_config_yaml(&yaml).unwrap();
+
+    let result = DockerCheckTask::new(config, format!("{}/dotnet/sdk:{}", upstream.host(), tag), DockerCheckOptions {
+        offline: false,
+        platform: platform.map(|platform| platform.parse().unwrap()),
+    }).run().await;
+
+    assert_eq!(result.is_ok(), expected, "{:?}", result.err());
+    assert!(mirror.requests().iter().any(|request| request.starts_with("HEAD /v2/secondary/sdk/manifests/")));
+}
+
+#[rstest]
+#[tokio::test]
+async fn check_task_skips_mirror_with_unresolved_credential(_init_logger: ()) {
+    let primary = TestRegistry::start().await;
+    let secondary = TestRegistry::start().await;
+    secondary.push_image("secondary/sdk", "6.0", &["layer"]);
+    let mut config = fallback_config(&primary.host(), &secondary.host());
+    config.docker.ruleset[0].auth = Some(RegistryAuthConfig {
+        username: "robot".to_string(),
+        password: "${env:RUSHGET_TEST_UNSET_PASSWORD}".to_string(),
+    });
+
+    let result = DockerCheckTask::new(config, "mcr.microsoft.com/dotnet/sdk:6.0".to_string(), DockerCheckOptions {
+        offline: false,
+        platform: None,
+    }).run().await;
+
+    assert!(result.is_ok(), "{:?}", result.err());
+    assert!(primary.requests().is_empty());
+}
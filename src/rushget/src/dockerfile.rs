@@ -0,0 +1,307 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use regex::Regex;
+use crate::components::config::RushGetConfig;
+use crate::components::container_engine::{create_engine, ContainerEngineKind};
+use crate::components::RushGetTask;
+use crate::docker::compose::interpolate;
+use crate::docker::map_mirror_by_configuration;
+use crate::error::DockermirError;
+
+const DEFAULT_DOCKERFILE: &str = "Dockerfile";
+const SCRATCH: &str = "scratch";
+
+/// Print the Dockerfile with the base images replaced by their mirrors
+pub(crate) struct DockerfileRewriteTask {
+    path: PathBuf,
+    build_args: Vec<String>,
+    config: RushGetConfig,
+}
+
+impl DockerfileRewriteTask {
+    pub(crate) fn new(config: RushGetConfig, path: PathBuf, build_args: Vec<String>) -> Self {
+        DockerfileRewriteTask {
+            path,
+            build_args,
+            config,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerfileRewriteTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let result = rewrite_dockerfile_file(&self.path, &parse_build_args(&self.build_args), &self.config)?;
+        print!("{}", result.content);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DockerBuildOptions {
+    /// The build context
+    pub(crate) context: PathBuf,
+    /// The Dockerfile, `Dockerfile` in the context if not set
+    pub(crate) file: Option<PathBuf>,
+    /// `KEY=VALUE` or `KEY` to take the value from the environment
+    pub(crate) build_args: Vec<String>,
+    pub(crate) tags: Vec<String>,
+    /// Passed to the build of the engine as they are
+    pub(crate) args: Vec<String>,
+    /// The container engine to build with, overrides the engine in config
+    pub(crate) engine: Option<ContainerEngineKind>,
+}
+
+/// Build with a temporary copy of the Dockerfile whose base images are pulled from mirrors
+pub(crate) struct DockerBuildTask {
+    config: RushGetConfig,
+    options: DockerBuildOptions,
+}
+
+impl DockerBuildTask {
+    pub(crate) fn new(config: RushGetConfig, options: DockerBuildOptions) -> Self {
+        DockerBuildTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for DockerBuildTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let dockerfile = self.options.file.clone().unwrap_or_else(|| self.options.context.join(DEFAULT_DOCKERFILE));
+        let result = rewrite_dockerfile_file(&dockerfile, &parse_build_args(&self.options.build_args), &self.config)?;
+
+        let dockerfile_error = |e: std::io::Error| DockermirError::DockerfileError {
+            path: dockerfile.display().to_string(),
+            error: e.to_string(),
+        };
+        // the rewritten file lives in its own directory, next to a copy of the Dockerfile specific ignore file
+        let dir = tempfile::tempdir().map_err(dockerfile_error)?;
+        let rewritten = dir.path().join(DEFAULT_DOCKERFILE);
+        fs::write(&rewritten, &result.content).map_err(dockerfile_error)?;
+        let ignore_file = PathBuf::from(format!("{}.dockerignore", dockerfile.display()));
+        if ignore_file.is_file() {
+            fs::copy(&ignore_file, dir.path().join(format!("{}.dockerignore", DEFAULT_DOCKERFILE))).map_err(dockerfile_error)?;
+        }
+
+        let mut args = vec!["-f".to_string(), rewritten.display().to_string()];
+        for build_arg in &self.options.build_args {
+            args.extend(["--build-arg".to_string(), build_arg.to_owned()]);
+        }
+        for tag in &self.options.tags {
+            args.extend(["-t".to_string(), tag.to_owned()]);
+        }
+        args.extend(self.options.args.iter().cloned());
+        args.push(self.options.context.display().to_string());
+
+        let engine = create_engine(ContainerEngineKind::resolve(self.options.engine, &self.config)?)?;
+        info!("Build {} with {}, {} base images from mirrors", dockerfile.display(), engine.kind(), result.rewritten.len());
+        engine.build(&args).await
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DockerfileRewrite {
+    pub(crate) content: String,
+    /// The base image and its mirror, in the order of the stages
+    pub(crate) rewritten: Vec<(String, String)>,
+    /// The base images without mirror and the reason
+    pub(crate) unmatched: Vec<(String, String)>,
+}
+
+fn rewrite_dockerfile_file(path: &Path, build_args: &HashMap<String, String>, config: &RushGetConfig)
+                           -> Result<DockerfileRewrite, DockermirError> {
+    let dockerfile_error = |error: String| DockermirError::DockerfileError {
+        path: path.display().to_string(),
+        error,
+    };
+    let content = fs::read_to_string(path).map_err(|e| dockerfile_error(e.to_string()))?;
+    let result = rewrite_dockerfile(&content, build_args, config).map_err(dockerfile_error)?;
+    for (image, mirror) in &result.rewritten {
+        info!("{}: {} -> {}", path.display(), image, mirror);
+    }
+    for (image, reason) in &result.unmatched {
+        warn!("{}: base image {} is kept as it is, {}", path.display(), image, reason);
+    }
+    Ok(result)
+}
+
+/// `KEY=VALUE`, or `KEY` to take the value from the environment like `docker build` does
+pub(crate) fn parse_build_args(build_args: &[String]) -> HashMap<String, String> {
+    build_args.iter()
+        .filter_map(|build_arg| match build_arg.split_once('=') {
+            Some((key, value)) => Some((key.to_string(), value.to_string())),
+            None => std::env::var(build_arg).ok().map(|value| (build_arg.to_string(), value)),
+        })
+        .collect()
+}
+
+fn escape_directive_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"^#\s*escape\s*=\s*(\S)\s*$").unwrap())
+}
+
+fn heredoc_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r#"<<-?["']?([A-Za-z_][A-Za-z0-9_]*)["']?"#).unwrap())
+}
+
+/// One instruction, which may span multiple lines through the escape character
+struct Instruction {
+    /// The byte range of the instruction in the Dockerfile
+    start: usize,
+    end: usize,
+    /// The instruction with line continuations removed
+    text: String,
+}
+
+fn parse_instructions(content: &str) -> Vec<Instruction> {
+    let mut lines = content.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some((start, line))
+    }).peekable();
+
+    // parser directives are only recognized in the comments at the very top
+    let mut escape = '\\';
+    while let Some((_, line)) = lines.peek() {
+        let trimmed = line.trim();
+        if !trimmed.starts_with('#') {
+            break;
+        }
+        if let Some(captures) = escape_directive_regex().captures(trimmed) {
+            escape = captures[1].chars().next().unwrap();
+        }
+        lines.next();
+    }
+
+    let mut instructions = vec![];
+    let mut current: Option<Instruction> = None;
+    let mut heredoc: Option<String> = None;
+    for (start, line) in lines {
+        let trimmed = line.trim();
+        if let Some(terminator) = &heredoc {
+            if trimmed == terminator {
+                heredoc = None;
+            }
+            continue;
+        }
+        if current.is_none() && (trimmed.is_empty() || trimmed.starts_with('#')) {
+            continue;
+        }
+        let instruction = current.get_or_insert(Instruction {
+            start,
+            end: start,
+            text: String::new(),
+        });
+        instruction.end = start + line.len();
+        // comments are allowed between continued lines
+        if trimmed.starts_with('#') {
+            continue;
+        }
+        match trimmed.strip_suffix(escape) {
+            Some(continued) => {
+                instruction.text.push_str(continued);
+                instruction.text.push(' ');
+            }
+            None => {
+                instruction.text.push_str(trimmed);
+                let instruction = current.take().unwrap();
+                if let Some(captures) = heredoc_regex().captures(&instruction.text) {
+                    heredoc = Some(captures[1].to_string());
+                }
+                instructions.push(instruction);
+            }
+        }
+    }
+    instructions.extend(current);
+    instructions
+}
+
+/// Replace the base images in `FROM` with their mirrors, stages and `scratch` are kept,
+/// `ARG`s declared before the first `FROM` are substituted with the build args taking precedence over their defaults
+pub(crate) fn rewrite_dockerfile(content: &str, build_args: &HashMap<String, String>, config: &RushGetConfig)
+                                 -> Result<DockerfileRewrite, String> {
+    let mut result = DockerfileRewrite::default();
+    let mut global_args: HashMap<String, Option<String>> = HashMap::new();
+    let mut stages: Vec<String> = vec![];
+    let mut replacements: Vec<(usize, usize, String)> = vec![];
+
+    for instruction in parse_instructions(content) {
+        let (keyword, arguments) = instruction.text.split_once(char::is_whitespace).unwrap_or((&instruction.text, ""));
+        if keyword.eq_ignore_ascii_case("ARG") && stages.is_empty() {
+            for declaration in arguments.split_whitespace() {
+                let (name, default) = match declaration.split_once('=') {
+                    Some((name, default)) => {
+                        let default = default.trim_matches(|c| c == '"' || c == '\'');
+                        (name, Some(interpolate(default, &|name| arg_value(&global_args, build_args, name))?))
+                    }
+                    None => (declaration, None),
+                };
+                global_args.insert(name.to_string(), default);
+            }
+            continue;
+        }
+        if !keyword.eq_ignore_ascii_case("FROM") {
+            continue;
+        }
+
+        // find every token in the original text, so the image is replaced where it is written
+        let original = &content[instruction.start..instruction.end];
+        let mut offset = original.find(keyword).unwrap_or(0) + keyword.len();
+        let mut image = None;
+        let mut tokens = arguments.split_whitespace();
+        for token in tokens.by_ref() {
+            let position = offset + original[offset..].find(token).unwrap_or(0);
+            offset = position + token.len();
+            if !token.starts_with("--") {
+                image = Some((token, instruction.start + position));
+                break;
+            }
+        }
+        let Some((raw_image, position)) = image else {
+            return Err(format!("FROM without image: {}", instruction.text));
+        };
+        // one stage per FROM, so the names stay in step with the stages they belong to
+        match (tokens.next(), tokens.next(), tokens.next()) {
+            (None, _, _) => stages.push(String::new()),
+            (Some(as_keyword), Some(alias), None) if as_keyword.eq_ignore_ascii_case("AS") => stages.push(alias.to_lowercase()),
+            _ => return Err(format!("malformed FROM, expected `FROM image [AS name]`: {}", instruction.text)),
+        }
+
+        let image = interpolate(raw_image, &|name| arg_value(&global_args, build_args, name))?;
+        if image.eq_ignore_ascii_case(SCRATCH) || stages[..stages.len() - 1].contains(&image.to_lowercase()) {
+            trace!("FROM {} is a stage or scratch, skip", image);
+            continue;
+        }
+        match map_mirror_by_configuration(&image, config) {
+            Ok(mirror) => {
+                replacements.push((position, position + raw_image.len(), mirror.mirror_image.clone()));
+                result.rewritten.push((image, mirror.mirror_image));
+            }
+            Err(e) => result.unmatched.push((image, e.to_string())),
+        }
+    }
+
+    let mut last = 0;
+    for (start, end, mirror) in replacements {
+        result.content.push_str(&content[last..start]);
+        result.content.push_str(&mirror);
+        last = end;
+    }
+    result.content.push_str(&content[last..]);
+    Ok(result)
+}
+
+/// Only declared args can be used, the build arg takes precedence over the default
+fn arg_value(global_args: &HashMap<String, Option<String>>, build_args: &HashMap<String, String>, name: &str) -> Option<String> {
+    let default = global_args.get(name)?;
+    build_args.get(name).cloned().or(default.clone())
+}
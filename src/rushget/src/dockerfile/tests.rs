@@ -0,0 +1,71 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, DEFAULT_CONFIG_YAML};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn default_config() -> RushGetConfig {
+    ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap()
+}
+
+#[rstest]
+fn rewrite_stages(_init_logger: ()) {
+    let content = include_str!("Dockerfile");
+    let result = rewrite_dockerfile(content, &HashMap::new(), &default_config()).unwrap();
+
+    let expected = content
+        .replace("FROM --platform=$BUILDPLATFORM ${SDK_IMAGE} AS build",
+                 "FROM --platform=$BUILDPLATFORM registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0 AS build")
+        .replace("    mcr.microsoft.com/dotnet/aspnet:$DOTNET_VERSION \\",
+                 "    registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0 \\");
+    assert_eq!(result.content, expected);
+    assert_eq!(result.rewritten, vec![
+        ("mcr.microsoft.com/dotnet/sdk:6.0".to_string(), "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0".to_string()),
+        ("mcr.microsoft.com/dotnet/aspnet:6.0".to_string(), "registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0".to_string()),
+    ]);
+    // the stage `build` is not an image, nginx has no rule
+    assert_eq!(result.unmatched.len(), 1);
+    assert_eq!(result.unmatched[0].0, "nginx:1.25");
+}
+
+#[rstest]
+fn rewrite_with_build_args(_init_logger: ()) {
+    let build_args = parse_build_args(&["DOTNET_VERSION=8.0".to_string()]);
+    let result = rewrite_dockerfile(include_str!("Dockerfile"), &build_args, &default_config()).unwrap();
+    assert_eq!(result.rewritten[0].1, "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:8.0");
+    assert_eq!(result.rewritten[1].1, "registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:8.0");
+}
+
+#[rstest]
+#[case::undeclared_arg("FROM mcr.microsoft.com/dotnet/sdk:${VERSION}\n", "FROM mcr.microsoft.com/dotnet/sdk:${VERSION}\n")]
+#[case::scratch("FROM scratch\nCOPY app /\n", "FROM scratch\nCOPY app /\n")]
+#[case::lowercase("from mcr.microsoft.com/dotnet/runtime:6.0 as base\n", "from registry.cn-hangzhou.aliyuncs.com/newbe36524/runtime:6.0 as base\n")]
+#[case::escape_directive("# escape=`\nFROM `\n  mcr.microsoft.com/windows:ltsc2022\n",
+    "# escape=`\nFROM `\n  registry.cn-hangzhou.aliyuncs.com/newbe36524/windows:ltsc2022\n")]
+#[case::heredoc("FROM alpine\nRUN <<EOF\nFROM mcr.microsoft.com/dotnet/sdk:6.0\nEOF\n", "FROM alpine\nRUN <<EOF\nFROM mcr.microsoft.com/dotnet/sdk:6.0\nEOF\n")]
+fn rewrite_cases(_init_logger: (), #[case]content: &str, #[case]expected: &str) {
+    let result = rewrite_dockerfile(content, &HashMap::new(), &default_config()).unwrap();
+    assert_eq!(result.content, expected);
+}
+
+#[rstest]
+fn rewrite_without_image(_init_logger: ()) {
+    assert!(rewrite_dockerfile("FROM --platform=linux/amd64\n", &HashMap::new(), &default_config()).is_err());
+}
+
+#[rstest]
+#[case("FROM mcr.microsoft.com/dotnet/sdk:6.0 build\n")]
+#[case("FROM mcr.microsoft.com/dotnet/sdk:6.0 AS\n")]
+#[case("FROM mcr.microsoft.com/dotnet/sdk:6.0 AS build extra\n")]
+#[case("FROM mcr.microsoft.com/dotnet/sdk:6.0 AS build\nFROM build x y\n")]
+fn rewrite_malformed_from(_init_logger: (), #[case]content: &str) {
+    let result = rewrite_dockerfile(content, &HashMap::new(), &default_config());
+    assert!(matches!(&result, Err(e) if e.starts_with("malformed FROM")), "{:?}", result.map(|result| result.content));
+}
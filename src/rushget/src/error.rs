@@ -4,6 +4,8 @@ use thiserror::Error;
 pub enum DockermirError {
     #[error("the image name is mismatched with all rules")]
     MismatchAllRule,
+    #[error("invalid image reference: {0}")]
+    InvalidImageReference(String),
     #[error("failed to load remote config from url: {0}")]
     FailedToLoadRemoteConfig(String),
     #[error("failed to pull image source: {source_image}, mirror: {mirror_image}, error: {error}")]
@@ -12,6 +14,111 @@ pub enum DockermirError {
         mirror_image: String,
         error: String,
     },
+    #[error("digest of the pulled image is mismatched, image: {image}, expected: {expected}, actual: {actual}")]
+    ImageDigestMismatch {
+        image: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("failed to pull image: {source_image} from all mirrors, attempts: [{}]", .attempts.join("; "))]
+    AllMirrorsFailed {
+        source_image: String,
+        attempts: Vec<String>,
+    },
+    #[error("no mirror serves image: {source_image}")]
+    MirrorImageMissing {
+        source_image: String,
+    },
+    #[error("mirror is behind upstream: {mirror_image}, mirror digest: {mirror_digest}, upstream digest: {upstream_digest}")]
+    MirrorStale {
+        mirror_image: String,
+        mirror_digest: String,
+        upstream_digest: String,
+    },
+    #[error("{stale} of {total} mirrors are behind upstream")]
+    StaleMirrors {
+        stale: usize,
+        total: usize,
+    },
+    #[error("the pull of image: {image} panicked, error: {error}")]
+    PullTaskPanicked {
+        image: String,
+        error: String,
+    },
+    #[error("failed to pull {failed} of {total} images")]
+    BatchPullFailed {
+        failed: usize,
+        total: usize,
+    },
+    #[error("failed to sync {failed} of {total} images")]
+    BatchSyncFailed {
+        failed: usize,
+        total: usize,
+    },
+    #[error("failed to read lock file: {path}, error: {error}")]
+    LockFileError {
+        path: String,
+        error: String,
+    },
+    #[error("the lock is out of date for image: {image}, {reason}, run `rg docker lock` again")]
+    LockOutdated {
+        image: String,
+        reason: String,
+    },
+    #[error("invalid bundle: {path}, error: {error}")]
+    BundleError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to read image list: {path}, error: {error}")]
+    ImageListError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to load compose file: {path}, error: {error}")]
+    ComposeFileError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to rewrite kubernetes manifest: {path}, error: {error}")]
+    K8sManifestError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to rewrite Dockerfile: {path}, error: {error}")]
+    DockerfileError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to build image, error: {error}")]
+    DockerBuildError {
+        error: String,
+    },
+    #[error("the {engine} engine does not support {operation}, use the docker, podman or nerdctl engine")]
+    EngineUnsupported {
+        engine: String,
+        operation: String,
+    },
+    #[error("failed to login to registry: {registry}, error: {error}")]
+    DockerLoginError {
+        registry: String,
+        error: String,
+    },
+    #[error("failed to load TLS certificate: {path}, error: {error}")]
+    TlsCertificateError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to serve on: {address}, error: {error}")]
+    ServeError {
+        address: String,
+        error: String,
+    },
+    #[error("failed to configure container runtime: {path}, error: {error}")]
+    RuntimeConfigureError {
+        path: String,
+        error: String,
+    },
     #[error("failed to tag image source: {source_image}, mirror: {mirror_image}, error: {error}")]
     DockerTagError {
         source_image: String,
@@ -23,9 +130,97 @@ pub enum DockermirError {
         image: String,
         error: String,
     },
+    #[error("failed to push image: {image}, error: {error}")]
+    DockerPushError {
+        image: String,
+        error: String,
+    },
+    #[error("failed to load image archive: {archive}, error: {error}")]
+    DockerLoadError {
+        archive: String,
+        error: String,
+    },
+    #[error("failed to inspect image: {image}, error: {error}")]
+    DockerInspectError {
+        image: String,
+        error: String,
+    },
+    #[error("failed to talk to the docker daemon: {host}, error: {error}")]
+    DockerDaemonError {
+        host: String,
+        error: String,
+    },
+    #[error("no container engine found on PATH, please install docker, podman or nerdctl")]
+    ContainerEngineNotFound,
     #[error("failed to download file from url: {url}, error: {error}")]
     GithubReleaseDownloadError {
         url: String,
         error: String,
     },
+    #[error("failed to request registry: {url}, error: {error}")]
+    RegistryRequestError {
+        url: String,
+        error: String,
+    },
+    #[error("failed to resolve the credential of registry: {registry}, error: {error}")]
+    RegistryCredentialError {
+        registry: String,
+        error: String,
+    },
+    #[error("registry requires authentication: {url}")]
+    RegistryUnauthorized {
+        url: String,
+    },
+    #[error("registry resource not found: {url}")]
+    RegistryNotFound {
+        url: String,
+    },
+    #[error("registry returned unexpected status: {status}, url: {url}")]
+    RegistryUnexpectedStatus {
+        url: String,
+        status: u16,
+    },
+    #[error("invalid manifest of image: {image}, error: {error}")]
+    InvalidManifest {
+        image: String,
+        error: String,
+    },
+    #[error("no manifest matches platform: {platform}, image: {image}")]
+    PlatformNotFound {
+        image: String,
+        platform: String,
+    },
+    #[error("blob digest mismatch, expected: {expected}, actual: {actual}")]
+    BlobDigestMismatch {
+        expected: String,
+        actual: String,
+    },
+    #[error("failed to write oci layout: {path}, error: {error}")]
+    OciLayoutError {
+        path: String,
+        error: String,
+    },
+    #[error("failed to write image archive: {path}, error: {error}")]
+    ImageArchiveError {
+        path: String,
+        error: String,
+    },
+}
+
+impl DockermirError {
+    /// Whether the error is caused by the mirror, so another mirror may still serve the image
+    pub(crate) fn is_mirror_failure(&self) -> bool {
+        matches!(self,
+            DockermirError::DockerPullError { .. }
+            | DockermirError::DockerLoginError { .. }
+            | DockermirError::ImageDigestMismatch { .. }
+            | DockermirError::MirrorStale { .. }
+            | DockermirError::RegistryRequestError { .. }
+            | DockermirError::RegistryUnauthorized { .. }
+            | DockermirError::RegistryNotFound { .. }
+            | DockermirError::RegistryUnexpectedStatus { .. }
+            | DockermirError::InvalidManifest { .. }
+            | DockermirError::PlatformNotFound { .. }
+            | DockermirError::BlobDigestMismatch { .. })
+    }
 }
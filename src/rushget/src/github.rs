@@ -3,6 +3,7 @@ mod tests;
 
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 use reqwest::Client;
 use crate::components::config::RushGetConfig;
 use crate::components::RushGetTask;
@@ -27,36 +28,42 @@ impl GithubReleaseTask {
     async fn run_core(&self) -> Result<(), String> {
         // get file name of url
         let file_name = self.release_url.split('/').last().unwrap();
-        for mirror in &self.config.github.mirrors {
-            let url = mirror.replace_template.replace("${release_url}", &self.release_url);
-            // download file
-            trace!("Downloading release file from url: {}", &url);
-            let client = Client::new();
-            let response = client.get(&url).send().await;
-            if response.is_err() {
-                trace!("Failed to download release file from mirror: {}, error: {}", &mirror.name, response.err().unwrap());
-                continue;
+        download_release_file(&self.config, &self.release_url, Path::new(file_name)).await
+    }
+}
+
+/// Download the release file into the output from the first mirror serving it
+pub(crate) async fn download_release_file(config: &RushGetConfig, release_url: &str, output: &Path) -> Result<(), String> {
+    for mirror in &config.github.mirrors {
+        let url = mirror.replace_template.replace("${release_url}", release_url);
+        // download file
+        trace!("Downloading release file from url: {}", &url);
+        let client = Client::new();
+        let response = client.get(&url).send().await;
+        if response.is_err() {
+            trace!("Failed to download release file from mirror: {}, error: {}", &mirror.name, response.err().unwrap());
+            continue;
+        }
+        let response = response.unwrap();
+        if response.status().is_success() {
+            let file = File::create(output);
+            if file.is_err() {
+                return Err(format!("Failed to create file: {}", output.display()));
+            }
+            let mut file = file.unwrap();
+            let bytes = response.bytes().await;
+            if bytes.is_err() {
+                return Err(format!("Failed to read bytes from response."));
             }
-            let response = response.unwrap();
-            if response.status().is_success() {
-                let file = File::create(file_name);
-                if file.is_err() {
-                    return Err(format!("Failed to create file: {}", file_name));
-                }
-                let mut file = file.unwrap();
-                let bytes = response.bytes().await;
-                if bytes.is_err() {
-                    return Err(format!("Failed to read bytes from response."));
-                }
-                let write_result = file.write_all(&bytes.unwrap());
-                if write_result.is_err() {
-                    return Err(format!("Failed to write bytes to file: {}", file_name));
-                }
-                return Ok(());
+            let write_result = file.write_all(&bytes.unwrap());
+            if write_result.is_err() {
+                return Err(format!("Failed to write bytes to file: {}", output.display()));
             }
+            return Ok(());
         }
-        Err("Failed to download release file.".to_string())
+        trace!("Failed to download release file from mirror: {}, status: {}", mirror.name, response.status());
     }
+    Err("Failed to download release file.".to_string())
 }
 
 #[async_trait::async_trait]
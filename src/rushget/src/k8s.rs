@@ -0,0 +1,201 @@
+#[cfg(test)]
+mod tests;
+pub(crate) mod webhook;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use regex::Regex;
+use serde_yaml::Value;
+use crate::components::config::RushGetConfig;
+use crate::components::RushGetTask;
+use crate::docker::map_mirror_by_configuration;
+use crate::error::DockermirError;
+
+const CONTAINER_FIELDS: [&str; 3] = ["containers", "initContainers", "ephemeralContainers"];
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct K8sRewriteOptions {
+    /// Write the rewritten manifests back into the files instead of stdout
+    pub(crate) in_place: bool,
+}
+
+/// Replace the images of the workloads in kubernetes manifests with their mirrors
+pub(crate) struct K8sRewriteTask {
+    path: PathBuf,
+    config: RushGetConfig,
+    options: K8sRewriteOptions,
+}
+
+impl K8sRewriteTask {
+    pub(crate) fn new(config: RushGetConfig, path: PathBuf, options: K8sRewriteOptions) -> Self {
+        K8sRewriteTask {
+            path,
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for K8sRewriteTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let files = manifest_files(&self.path)?;
+        let mut unmatched = vec![];
+        for (index, file) in files.iter().enumerate() {
+            let manifest_error = |error: String| DockermirError::K8sManifestError {
+                path: file.display().to_string(),
+                error,
+            };
+            let content = fs::read_to_string(file).map_err(|e| manifest_error(e.to_string()))?;
+            let result = rewrite_manifests(&content, &self.config).map_err(manifest_error)?;
+            for (image, mirror) in &result.rewritten {
+                info!("{}: {} -> {}", file.display(), image, mirror);
+            }
+            for (image, reason) in &result.unmatched {
+                unmatched.push(format!("{}: {} ({})", file.display(), image, reason));
+            }
+
+            if self.options.in_place {
+                if result.content != content {
+                    fs::write(file, &result.content).map_err(|e| manifest_error(e.to_string()))?;
+                }
+            } else {
+                // the files are joined into one multi document stream
+                if index > 0 {
+                    println!("---");
+                }
+                print!("{}", result.content);
+            }
+        }
+        if !unmatched.is_empty() {
+            warn!("{} images are not matched with any rules and are kept as they are:", unmatched.len());
+            for image in &unmatched {
+                warn!("  {}", image);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The yaml files in the directory and its sub directories, or the file itself
+fn manifest_files(path: &Path) -> Result<Vec<PathBuf>, DockermirError> {
+    let manifest_error = |e: std::io::Error| DockermirError::K8sManifestError {
+        path: path.display().to_string(),
+        error: e.to_string(),
+    };
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(path).map_err(manifest_error)? {
+        let entry_path = entry.map_err(manifest_error)?.path();
+        if entry_path.is_dir() {
+            files.extend(manifest_files(&entry_path)?);
+        } else if entry_path.extension().map(|extension| extension == "yaml" || extension == "yml").unwrap_or(false) {
+            files.push(entry_path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RewriteResult {
+    pub(crate) content: String,
+    /// The source image and its mirror, in the order they appear
+    pub(crate) rewritten: Vec<(String, String)>,
+    /// The images without mirror and the reason
+    pub(crate) unmatched: Vec<(String, String)>,
+}
+
+fn image_line_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r#"^(\s*(?:-\s+)?image:\s*)(["']?)([^"'\s#]+)(["']?)(\s*(?:#.*)?)$"#).unwrap())
+}
+
+/// Rewrite the images of all documents, the lines are replaced in place so comments and formatting are kept
+pub(crate) fn rewrite_manifests(content: &str, config: &RushGetConfig) -> Result<RewriteResult, String> {
+    let mut result = RewriteResult::default();
+    for document in split_documents(content) {
+        let value: Value = serde_yaml::from_str(&document).map_err(|e| e.to_string())?;
+        let mut images = vec![];
+        collect_images(&value, &mut images);
+
+        let mut mirrors = BTreeMap::new();
+        for image in images {
+            if mirrors.contains_key(&image) || result.unmatched.iter().any(|(unmatched, _)| unmatched == &image) {
+                continue;
+            }
+            match map_mirror_by_configuration(&image, config) {
+                Ok(mirror) => {
+                    mirrors.insert(image, mirror.mirror_image);
+                }
+                Err(e) => result.unmatched.push((image, e.to_string())),
+            }
+        }
+
+        for line in document.split_inclusive('\n') {
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+            let replaced = image_line_regex().captures(text).and_then(|captures| {
+                let mirror = mirrors.get(&captures[3])?;
+                result.rewritten.push((captures[3].to_string(), mirror.clone()));
+                Some(format!("{}{}{}{}{}", &captures[1], &captures[2], mirror, &captures[4], &captures[5]))
+            });
+            result.content.push_str(replaced.as_deref().unwrap_or(text));
+            result.content.push_str(newline);
+        }
+    }
+    Ok(result)
+}
+
+/// Split the stream at the `---` separators, the separators stay at the start of the following document
+fn split_documents(content: &str) -> Vec<String> {
+    let mut documents = vec![String::new()];
+    for line in content.split_inclusive('\n') {
+        if line.trim_end() == "---" || line.starts_with("--- ") {
+            documents.push(String::new());
+        }
+        documents.last_mut().unwrap().push_str(line);
+    }
+    documents
+}
+
+/// Collect the images of the containers in the pod spec of the workload, or of every item of a list
+fn collect_images(value: &Value, images: &mut Vec<String>) {
+    let kind = value.get("kind").and_then(|kind| kind.as_str()).unwrap_or_default();
+    let pod_spec = match kind {
+        "Pod" => value.get("spec"),
+        "Deployment" | "StatefulSet" | "DaemonSet" | "ReplicaSet" | "ReplicationController" | "Job" => {
+            value.get("spec").and_then(|spec| spec.get("template")).and_then(|template| template.get("spec"))
+        }
+        "CronJob" => value.get("spec")
+            .and_then(|spec| spec.get("jobTemplate"))
+            .and_then(|job| job.get("spec"))
+            .and_then(|spec| spec.get("template"))
+            .and_then(|template| template.get("spec")),
+        "PodTemplate" => value.get("template").and_then(|template| template.get("spec")),
+        _ if kind.ends_with("List") => {
+            for item in value.get("items").and_then(|items| items.as_sequence()).into_iter().flatten() {
+                collect_images(item, images);
+            }
+            None
+        }
+        _ => None,
+    };
+    let Some(pod_spec) = pod_spec else {
+        return;
+    };
+    for field in CONTAINER_FIELDS {
+        let containers = pod_spec.get(field).and_then(|containers| containers.as_sequence());
+        for container in containers.into_iter().flatten() {
+            if let Some(image) = container.get("image").and_then(|image| image.as_str()) {
+                images.push(image.to_string());
+            }
+        }
+    }
+}
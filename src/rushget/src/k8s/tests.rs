@@ -0,0 +1,68 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, DEFAULT_CONFIG_YAML};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn default_config() -> RushGetConfig {
+    ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap()
+}
+
+#[rstest]
+fn rewrite_workloads(_init_logger: ()) {
+    let content = include_str!("manifests.yaml");
+    let result = rewrite_manifests(content, &default_config()).unwrap();
+
+    let expected = content
+        .replace("image: \"mcr.microsoft.com/dotnet/sdk:6.0\" # run", "image: \"registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0\" # run")
+        .replace("image: mcr.microsoft.com/dotnet/aspnet:6.0", "image: registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0")
+        .replace("image: 'mcr.microsoft.com/dotnet/runtime:6.0'", "image: 'registry.cn-hangzhou.aliyuncs.com/newbe36524/runtime:6.0'")
+        .replace("      image: mcr.microsoft.com/dotnet/runtime:6.0", "      image: registry.cn-hangzhou.aliyuncs.com/newbe36524/runtime:6.0")
+        .replace("      image: mcr.microsoft.com/dotnet/sdk:6.0\n---", "      image: registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0\n---");
+    assert_eq!(result.content, expected);
+    // the image in the config map is not a container, so it is kept
+    assert!(result.content.ends_with("  image: mcr.microsoft.com/dotnet/sdk:6.0\n"));
+    assert_eq!(result.rewritten.len(), 5);
+    assert_eq!(result.unmatched.len(), 1);
+    assert_eq!(result.unmatched[0].0, "nginx:1.25");
+}
+
+#[rstest]
+fn rewrite_list(_init_logger: ()) {
+    let content = "apiVersion: v1\nkind: List\nitems:\n  - kind: StatefulSet\n    spec:\n      template:\n        spec:\n          containers:\n            - image: mcr.microsoft.com/mssql/server:2022-latest\n";
+    let result = rewrite_manifests(content, &default_config()).unwrap();
+    assert_eq!(result.content, content.replace("mcr.microsoft.com/mssql/server", "registry.cn-hangzhou.aliyuncs.com/newbe36524/server"));
+    assert!(result.unmatched.is_empty());
+}
+
+#[rstest]
+fn rewrite_invalid_yaml(_init_logger: ()) {
+    let result = rewrite_manifests("kind: Pod\nspec: [", &default_config());
+    assert!(result.is_err());
+}
+
+#[rstest]
+#[tokio::test]
+async fn rewrite_directory_in_place(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("jobs")).unwrap();
+    fs::write(dir.path().join("jobs/manifests.yml"), include_str!("manifests.yaml")).unwrap();
+    fs::write(dir.path().join("README.md"), "image: mcr.microsoft.com/dotnet/sdk:6.0\n").unwrap();
+
+    K8sRewriteTask::new(default_config(), dir.path().to_path_buf(), K8sRewriteOptions { in_place: true })
+        .run()
+        .await
+        .unwrap();
+
+    let rewritten = fs::read_to_string(dir.path().join("jobs/manifests.yml")).unwrap();
+    assert!(rewritten.contains("image: registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0"));
+    assert!(rewritten.contains("image: nginx:1.25"));
+    assert_eq!(fs::read_to_string(dir.path().join("README.md")).unwrap(), "image: mcr.microsoft.com/dotnet/sdk:6.0\n");
+}
@@ -0,0 +1,267 @@
+#[cfg(test)]
+mod tests;
+
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_native_tls::TlsAcceptor;
+use crate::components::config::RushGetConfig;
+use crate::components::RushGetTask;
+use crate::docker::map_mirror_by_configuration;
+use crate::error::DockermirError;
+
+/// Pods annotated with `"true"` are admitted unchanged, the rewrites are returned as warnings
+pub(crate) const DRY_RUN_ANNOTATION: &str = "rg.newbe.pro/dry-run";
+
+/// Ephemeral containers are added through their own subresource, not with the pod
+const POD_CONTAINER_FIELDS: [&str; 2] = ["initContainers", "containers"];
+
+#[derive(Debug, Clone)]
+pub(crate) struct K8sWebhookOptions {
+    /// The address to listen on
+    pub(crate) listen: SocketAddr,
+    /// The PEM certificate chain to serve HTTPS with, plain HTTP without it
+    pub(crate) tls_cert: Option<PathBuf>,
+    /// The PKCS#8 PEM private key of the certificate
+    pub(crate) tls_key: Option<PathBuf>,
+    /// Only patch the pods of these namespaces, every namespace if empty
+    pub(crate) namespaces: Vec<String>,
+    /// Never patch the pods of these namespaces, even when they are in the allow list
+    pub(crate) exclude_namespaces: Vec<String>,
+}
+
+/// Serve a mutating admission webhook which replaces the images of new pods with their mirrors
+pub(crate) struct K8sWebhookTask {
+    config: RushGetConfig,
+    options: K8sWebhookOptions,
+}
+
+impl K8sWebhookTask {
+    pub(crate) fn new(config: RushGetConfig, options: K8sWebhookOptions) -> Self {
+        K8sWebhookTask {
+            config,
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RushGetTask for K8sWebhookTask {
+    async fn run(self) -> Result<(), DockermirError> {
+        let tls = match (&self.options.tls_cert, &self.options.tls_key) {
+            (Some(cert), Some(key)) => Some(load_tls_acceptor(cert, key)?),
+            (None, None) => {
+                warn!("Serving without TLS, the API server only calls webhooks over HTTPS");
+                None
+            }
+            (Some(path), None) | (None, Some(path)) => return Err(DockermirError::TlsCertificateError {
+                path: path.display().to_string(),
+                error: "the certificate and the private key are required together".to_string(),
+            }),
+        };
+        let listener = TcpListener::bind(self.options.listen).await
+            .map_err(|e| DockermirError::ServeError {
+                address: self.options.listen.to_string(),
+                error: e.to_string(),
+            })?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        info!("Serving admission webhook on {}://{}, press Ctrl+C to stop", scheme, self.options.listen);
+        let webhook = Arc::new(AdmissionWebhook::new(self.config, self.options.namespaces, self.options.exclude_namespaces));
+        tokio::select! {
+            _ = webhook.serve(listener, tls) => Ok(()),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stop serving admission webhook");
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn load_tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, DockermirError> {
+    let tls_error = |path: &Path, error: String| DockermirError::TlsCertificateError {
+        path: path.display().to_string(),
+        error,
+    };
+    let cert_pem = fs::read(cert).map_err(|e| tls_error(cert, e.to_string()))?;
+    let key_pem = fs::read(key).map_err(|e| tls_error(key, e.to_string()))?;
+    let identity = native_tls::Identity::from_pkcs8(&cert_pem, &key_pem).map_err(|e| tls_error(cert, e.to_string()))?;
+    let acceptor = native_tls::TlsAcceptor::new(identity).map_err(|e| tls_error(cert, e.to_string()))?;
+    Ok(TlsAcceptor::from(acceptor))
+}
+
+/// The image of a container and the JSON pointer to it in the pod
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ImageRewrite {
+    pub(crate) path: String,
+    pub(crate) image: String,
+    pub(crate) mirror: String,
+}
+
+/// Answers `AdmissionReview` requests, pods are admitted in any case and only patched when an image has a mirror
+pub(crate) struct AdmissionWebhook {
+    config: RushGetConfig,
+    namespaces: Vec<String>,
+    exclude_namespaces: Vec<String>,
+}
+
+impl AdmissionWebhook {
+    pub(crate) fn new(config: RushGetConfig, namespaces: Vec<String>, exclude_namespaces: Vec<String>) -> AdmissionWebhook {
+        AdmissionWebhook {
+            config,
+            namespaces,
+            exclude_namespaces,
+        }
+    }
+
+    pub(crate) async fn serve(self: Arc<Self>, listener: TcpListener, tls: Option<TlsAcceptor>) {
+        loop {
+            let (stream, remote) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection, error: {}", e);
+                    continue;
+                }
+            };
+            let webhook = self.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => webhook.serve_connection(stream, remote).await,
+                        Err(e) => warn!("TLS handshake with {} failed, error: {}", remote, e),
+                    },
+                    None => webhook.serve_connection(stream, remote).await,
+                }
+            });
+        }
+    }
+
+    async fn serve_connection<S>(self: Arc<Self>, stream: S, remote: SocketAddr)
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        let service = service_fn(move |request| {
+            let webhook = self.clone();
+            async move { Ok::<_, Infallible>(webhook.handle(request).await) }
+        });
+        if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+            trace!("Connection from {} closed with error: {}", remote, e);
+        }
+    }
+
+    async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        if request.method() == Method::GET && request.uri().path() == "/healthz" {
+            return respond(StatusCode::OK, "text/plain", "ok".to_string());
+        }
+        if request.method() != Method::POST {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "text/plain", "expected a POST of an AdmissionReview".to_string());
+        }
+        let body = match request.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return respond(StatusCode::BAD_REQUEST, "text/plain", e.to_string()),
+        };
+        let review = serde_json::from_slice(&body).map_err(|e| e.to_string()).and_then(|review| self.review(&review));
+        match review {
+            Ok(review) => respond(StatusCode::OK, "application/json", review.to_string()),
+            Err(e) => {
+                warn!("Invalid AdmissionReview, error: {}", e);
+                respond(StatusCode::BAD_REQUEST, "text/plain", e)
+            }
+        }
+    }
+
+    /// The `AdmissionReview` with the response to the request of the given one
+    pub(crate) fn review(&self, review: &Value) -> Result<Value, String> {
+        let request = review.get("request").ok_or("the AdmissionReview has no request")?;
+        let uid = request.get("uid").and_then(|uid| uid.as_str()).ok_or("the request has no uid")?;
+        let api_version = review.get("apiVersion").and_then(|version| version.as_str()).unwrap_or("admission.k8s.io/v1");
+        let mut response = json!({ "uid": uid, "allowed": true });
+
+        let pod = request.get("object").unwrap_or(&Value::Null);
+        let namespace = request.get("namespace").and_then(|namespace| namespace.as_str()).unwrap_or_default();
+        let name = request.get("name").and_then(|name| name.as_str())
+            .or_else(|| pod.pointer("/metadata/generateName").and_then(|name| name.as_str()))
+            .unwrap_or_default();
+        let kind = request.pointer("/kind/kind").and_then(|kind| kind.as_str()).unwrap_or_default();
+        if kind != "Pod" {
+            trace!("Admit {} {}/{} unchanged, only pods are patched", kind, namespace, name);
+        } else if !self.is_selected(namespace) {
+            trace!("Admit pod {}/{} unchanged, the namespace is not selected", namespace, name);
+        } else {
+            let rewrites = pod_rewrites(pod, &self.config);
+            let dry_run = pod.get("metadata")
+                .and_then(|metadata| metadata.get("annotations"))
+                .and_then(|annotations| annotations.get(DRY_RUN_ANNOTATION))
+                .and_then(|dry_run| dry_run.as_str()) == Some("true");
+            for rewrite in &rewrites {
+                info!("Pod {}/{}: {} -> {}{}", namespace, name, rewrite.image, rewrite.mirror, if dry_run { " (dry run)" } else { "" });
+            }
+            if dry_run && !rewrites.is_empty() {
+                response["warnings"] = rewrites.iter()
+                    .map(|rewrite| format!("{} would be replaced with {}", rewrite.image, rewrite.mirror))
+                    .collect();
+            } else if !rewrites.is_empty() {
+                let patch: Vec<Value> = rewrites.iter()
+                    .map(|rewrite| json!({ "op": "replace", "path": rewrite.path, "value": rewrite.mirror }))
+                    .collect();
+                response["patchType"] = json!("JSONPatch");
+                response["patch"] = json!(STANDARD.encode(Value::from(patch).to_string()));
+            }
+        }
+        Ok(json!({
+            "apiVersion": api_version,
+            "kind": "AdmissionReview",
+            "response": response,
+        }))
+    }
+
+    /// Excluded namespaces are never selected, every other namespace is when the allow list is empty
+    fn is_selected(&self, namespace: &str) -> bool {
+        if self.exclude_namespaces.iter().any(|excluded| excluded == namespace) {
+            return false;
+        }
+        self.namespaces.is_empty() || self.namespaces.iter().any(|allowed| allowed == namespace)
+    }
+}
+
+/// The containers of the pod whose images have a mirror, images without a rule are kept
+pub(crate) fn pod_rewrites(pod: &Value, config: &RushGetConfig) -> Vec<ImageRewrite> {
+    let mut rewrites = vec![];
+    for field in POD_CONTAINER_FIELDS {
+        let containers = pod.pointer(&format!("/spec/{}", field)).and_then(|containers| containers.as_array());
+        for (index, container) in containers.into_iter().flatten().enumerate() {
+            let Some(image) = container.get("image").and_then(|image| image.as_str()) else {
+                continue;
+            };
+            match map_mirror_by_configuration(image, config) {
+                Ok(mirror) if mirror.mirror_image != image => rewrites.push(ImageRewrite {
+                    path: format!("/spec/{}/{}/image", field, index),
+                    image: image.to_string(),
+                    mirror: mirror.mirror_image,
+                }),
+                Ok(_) => {}
+                Err(e) => trace!("Keep image: {}, error: {}", image, e),
+            }
+        }
+    }
+    rewrites
+}
+
+fn respond(status: StatusCode, content_type: &str, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, content_type.parse().unwrap());
+    response
+}
@@ -0,0 +1,142 @@
+use log::LevelFilter;
+use rstest::*;
+use crate::components::config::{ConfigLoader, DEFAULT_CONFIG_YAML};
+use super::*;
+
+#[fixture]
+fn init_logger() {
+    let _ = env_logger::builder()
+        .filter_level(LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+fn webhook(namespaces: &[&str], exclude_namespaces: &[&str]) -> AdmissionWebhook {
+    let config = ConfigLoader::default().load_config_yaml(DEFAULT_CONFIG_YAML).unwrap();
+    AdmissionWebhook::new(config,
+                          namespaces.iter().map(|namespace| namespace.to_string()).collect(),
+                          exclude_namespaces.iter().map(|namespace| namespace.to_string()).collect())
+}
+
+/// The review the API server sends for a pod of a deployment, recorded from a cluster
+fn recorded_review() -> Value {
+    serde_json::from_str(include_str!("admission_review.json")).unwrap()
+}
+
+fn decoded_patch(review: &Value) -> Option<Value> {
+    let patch = review["response"]["patch"].as_str()?;
+    Some(serde_json::from_slice(&STANDARD.decode(patch).unwrap()).unwrap())
+}
+
+#[rstest]
+fn patch_pod_images(_init_logger: ()) {
+    let review = webhook(&[], &[]).review(&recorded_review()).unwrap();
+
+    assert_eq!(review["apiVersion"], "admission.k8s.io/v1");
+    assert_eq!(review["kind"], "AdmissionReview");
+    assert_eq!(review["response"]["uid"], "705ab4f5-6393-11e8-b7cc-42010a800002");
+    assert_eq!(review["response"]["allowed"], true);
+    assert_eq!(review["response"]["patchType"], "JSONPatch");
+    // nginx has no rule and is kept
+    assert_eq!(decoded_patch(&review).unwrap(), json!([
+        { "op": "replace", "path": "/spec/initContainers/0/image", "value": "registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0" },
+        { "op": "replace", "path": "/spec/containers/0/image", "value": "registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0" },
+    ]));
+}
+
+#[rstest]
+#[case(&[], &[], true)]
+#[case(&["team-a", "team-b"], &[], true)]
+#[case(&["team-b"], &[], false)]
+#[case(&[], &["team-a"], false)]
+#[case(&["team-a"], &["team-a"], false)]
+fn select_namespaces(_init_logger: (), #[case] namespaces: &[&str], #[case] exclude_namespaces: &[&str], #[case] patched: bool) {
+    let review = webhook(namespaces, exclude_namespaces).review(&recorded_review()).unwrap();
+
+    assert_eq!(review["response"]["allowed"], true);
+    assert_eq!(decoded_patch(&review).is_some(), patched);
+}
+
+#[rstest]
+fn dry_run_annotation(_init_logger: ()) {
+    let mut request = recorded_review();
+    request["request"]["object"]["metadata"]["annotations"][DRY_RUN_ANNOTATION] = json!("true");
+
+    let review = webhook(&[], &[]).review(&request).unwrap();
+
+    assert_eq!(review["response"]["allowed"], true);
+    assert!(decoded_patch(&review).is_none());
+    assert_eq!(review["response"]["warnings"], json!([
+        "mcr.microsoft.com/dotnet/sdk:6.0 would be replaced with registry.cn-hangzhou.aliyuncs.com/newbe36524/sdk:6.0",
+        "mcr.microsoft.com/dotnet/aspnet:6.0 would be replaced with registry.cn-hangzhou.aliyuncs.com/newbe36524/aspnet:6.0",
+    ]));
+}
+
+#[rstest]
+fn admit_other_kinds_unchanged(_init_logger: ()) {
+    let mut request = recorded_review();
+    request["request"]["kind"]["kind"] = json!("Deployment");
+
+    let review = webhook(&[], &[]).review(&request).unwrap();
+
+    assert_eq!(review["response"], json!({ "uid": "705ab4f5-6393-11e8-b7cc-42010a800002", "allowed": true }));
+}
+
+#[rstest]
+#[case(json!({ "kind": "AdmissionReview" }))]
+#[case(json!({ "kind": "AdmissionReview", "request": { "kind": { "kind": "Pod" } } }))]
+fn invalid_review(_init_logger: (), #[case] review: Value) {
+    assert!(webhook(&[], &[]).review(&review).is_err());
+}
+
+async fn start_webhook(tls: Option<TlsAcceptor>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(Arc::new(webhook(&[], &[])).serve(listener, tls));
+    port
+}
+
+#[rstest]
+#[tokio::test]
+async fn serve_admission_reviews(_init_logger: ()) {
+    let port = start_webhook(None).await;
+    let client = reqwest::Client::new();
+
+    let health = client.get(format!("http://127.0.0.1:{}/healthz", port)).send().await.unwrap();
+    assert_eq!(health.status(), reqwest::StatusCode::OK);
+
+    let response = client.post(format!("http://127.0.0.1:{}/mutate", port))
+        .header("Content-Type", "application/json")
+        .body(include_str!("admission_review.json"))
+        .send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let review: Value = response.json().await.unwrap();
+    assert_eq!(decoded_patch(&review).unwrap().as_array().unwrap().len(), 2);
+
+    let response = client.post(format!("http://127.0.0.1:{}/mutate", port)).body("{").send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[rstest]
+#[tokio::test]
+async fn serve_over_tls(_init_logger: ()) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/k8s/webhook");
+    let tls = load_tls_acceptor(&dir.join("tls.crt"), &dir.join("tls.key")).unwrap();
+    let port = start_webhook(Some(tls)).await;
+    let certificate = reqwest::Certificate::from_pem(include_bytes!("tls.crt")).unwrap();
+    let client = reqwest::Client::builder().add_root_certificate(certificate).build().unwrap();
+
+    let response = client.post(format!("https://localhost:{}/mutate", port))
+        .body(include_str!("admission_review.json"))
+        .send().await.unwrap();
+
+    let review: Value = response.json().await.unwrap();
+    assert_eq!(review["response"]["patchType"], "JSONPatch");
+}
+
+#[rstest]
+fn missing_certificate(_init_logger: ()) {
+    let dir = tempfile::tempdir().unwrap();
+    let result = load_tls_acceptor(&dir.path().join("tls.crt"), &dir.path().join("tls.key"));
+    assert!(matches!(result, Err(DockermirError::TlsCertificateError { .. })));
+}
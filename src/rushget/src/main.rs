@@ -1,22 +1,44 @@
 #[macro_use]
 extern crate log;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use clap::{Parser, Subcommand};
 
 mod error;
+mod bundle;
 mod components;
 mod docker;
+mod dockerfile;
 mod github;
+mod k8s;
 
 use anyhow::Result;
 use log::LevelFilter;
 
 use error::DockermirError;
+use crate::bundle::{BundleCreateOptions, BundleCreateTask, BundleImportOptions, BundleImportTask, DEFAULT_BUNDLE_FILE};
 use crate::components::config::{ConfigLoader, LoadConfigOptions};
+use crate::components::container_engine::ContainerEngineKind;
+use crate::components::progress;
+use crate::components::registry::Platform;
 
 use crate::components::RushGetTask;
-use crate::docker::{DockerCheckTask, DockerPullTask};
+use crate::docker::{DockerBatchPullTask, DockerCheckOptions, DockerCheckTask, DockerPullOptions, DockerPullTask};
+use crate::docker::compose::load_compose_images;
+use crate::docker::configure::{ContainerRuntime, DockerConfigureOptions, DockerConfigureTask};
+use crate::docker::freshness::DockerFreshnessTask;
+use crate::docker::lock::{DockerLockOptions, DockerLockTask, DEFAULT_LOCK_FILE};
+#[cfg(unix)]
+use crate::docker::proxy_socket::{DockerProxySocketOptions, DockerProxySocketTask};
+use crate::docker::relay::{DockerRelayOptions, DockerRelayTask};
+use crate::docker::save::{DockerSaveOptions, DockerSaveTask, SaveFormat};
+use crate::docker::serve::{DockerServeOptions, DockerServeTask};
+use crate::docker::sync::{DockerSyncOptions, DockerSyncTask};
+use crate::dockerfile::{DockerBuildOptions, DockerBuildTask, DockerfileRewriteTask};
 use crate::github::GithubReleaseTask;
+use crate::k8s::{K8sRewriteOptions, K8sRewriteTask};
+use crate::k8s::webhook::{K8sWebhookOptions, K8sWebhookTask};
 use appinsights::TelemetryClient;
 
 
@@ -45,14 +67,32 @@ struct Cli {
 enum Commands {
     /// Docker commands
     Docker {
+        /// The container engine to use, detected from PATH if not set in cli or config
+        #[arg(long, global = true)]
+        engine: Option<ContainerEngineKind>,
         #[command(subcommand)]
         command: DockerCommands,
     },
+    /// Dockerfile commands
+    Dockerfile {
+        #[command(subcommand)]
+        command: DockerfileCommands,
+    },
+    /// Bundle images and release files for machines without network
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
     /// Github commands
     Github {
         #[command(subcommand)]
         command: GithubCommands,
     },
+    /// Kubernetes commands
+    K8s {
+        #[command(subcommand)]
+        command: K8sCommands,
+    },
     /// Update dockermir
     SelfUpdate {
         /// The url of the remote metadata file
@@ -67,13 +107,220 @@ enum Commands {
 enum DockerCommands {
     /// Pull image from mirror
     Pull {
-        /// The name of the Docker image to be pull
+        /// The names of the Docker images to be pull
+        #[arg(required_unless_present = "compose", conflicts_with = "compose")]
+        image: Vec<String>,
+        /// Pull all images of the services in the compose file
+        #[arg(long)]
+        compose: Option<PathBuf>,
+        /// Enable the services of the compose profile, can be set multiple times
+        #[arg(long, requires = "compose")]
+        profile: Vec<String>,
+        /// Pull with the native registry client into this OCI image layout directory instead of the docker daemon
+        #[arg(long)]
+        oci_layout: Option<PathBuf>,
+        /// Pull from the original registry when all mirrors failed
+        #[arg(long)]
+        fallback_upstream: bool,
+        /// How many images are pulled at the same time
+        #[arg(short = 'j', long)]
+        concurrency: Option<usize>,
+        /// Pull this platform of multi-arch images, `os/arch[/variant]` like `linux/arm64/v8`
+        #[arg(long)]
+        platform: Option<Platform>,
+        /// Skip mirrors which are behind upstream, falling back to upstream when enabled
+        #[arg(long)]
+        require_fresh: bool,
+        /// Pull even when the image is already present locally with the digest of the mirror
+        #[arg(long)]
+        force: bool,
+        /// Keep the mirror name of the pulled image next to the source name
+        #[arg(long)]
+        keep_mirror_tag: bool,
+        /// Extra name of the pulled image, `${registry}`, `${repository}` and `${tag}` are taken from the source image
+        #[arg(long)]
+        tag: Vec<String>,
+        /// Pull the mirrors and digests recorded by `rg docker lock`, fail when the lock is out of date
+        #[arg(long)]
+        locked: bool,
+        /// The lock file to pull with `--locked`
+        #[arg(long, default_value = DEFAULT_LOCK_FILE)]
+        lock_file: PathBuf,
+    },
+    /// Record the mirror and the digest of each image in a lock file, for reproducible pulls with `pull --locked`
+    Lock {
+        /// The names of the Docker images to be locked
+        #[arg(required_unless_present = "compose", conflicts_with = "compose")]
+        image: Vec<String>,
+        /// Lock all images of the services in the compose file
+        #[arg(long)]
+        compose: Option<PathBuf>,
+        /// Enable the services of the compose profile, can be set multiple times
+        #[arg(long, requires = "compose")]
+        profile: Vec<String>,
+        /// The lock file to write
+        #[arg(short, long, default_value = DEFAULT_LOCK_FILE)]
+        output: PathBuf,
+    },
+    /// Pull the image from the mirrors and push it into another registry
+    Relay {
+        /// The name of the Docker image to be relayed
         image: String,
+        /// The image to push to, the tag of the source image is used when it has no tag
+        #[arg(long)]
+        to: String,
+        /// Pull from the original registry when all mirrors failed
+        #[arg(long)]
+        fallback_upstream: bool,
+        /// Relay this platform of multi-arch images, `os/arch[/variant]` like `linux/arm64/v8`
+        #[arg(long)]
+        platform: Option<Platform>,
     },
-    /// Check whether the image is matched with any rules
+    /// Check whether the image is matched with any rules and whether the mirrors have it
     Check {
         /// The name of the Docker image to be pull
         image: String,
+        /// Only match the rules, without asking the registries
+        #[arg(long)]
+        offline: bool,
+        /// Also check that the mirrors have this platform, `os/arch[/variant]`
+        #[arg(long)]
+        platform: Option<Platform>,
+    },
+    /// Build with the base images of the Dockerfile pulled from mirrors
+    Build {
+        /// The build context
+        context: PathBuf,
+        /// The Dockerfile, `Dockerfile` in the context if not set
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Set a build arg, `KEY=VALUE` or `KEY` to take the value from the environment
+        #[arg(long)]
+        build_arg: Vec<String>,
+        /// The name and tag of the built image
+        #[arg(short, long)]
+        tag: Vec<String>,
+        /// Extra arguments passed to the build of the container engine, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Serve the registry API locally, every pull through it is fetched from the mirrors
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:5000")]
+        listen: SocketAddr,
+        /// Fetch from the original registry when all mirrors failed
+        #[arg(long)]
+        fallback_upstream: bool,
+    },
+    /// Serve the Engine API on a unix socket in front of the daemon, pulls through it are fetched from the mirrors
+    #[cfg(unix)]
+    ProxySocket {
+        /// The unix socket to listen on, point `DOCKER_HOST` at it
+        #[arg(long)]
+        listen: PathBuf,
+        /// The daemon to forward to, `DOCKER_HOST` or the default socket if not set
+        #[arg(long)]
+        upstream: Option<String>,
+    },
+    /// Pull the image from the mirrors into an archive without a container engine
+    Save {
+        /// The name of the Docker image to be saved
+        image: String,
+        /// The archive to write
+        #[arg(short, long)]
+        output: PathBuf,
+        /// The format of the archive
+        #[arg(long, value_enum, default_value_t = SaveFormat::DockerArchive)]
+        format: SaveFormat,
+        /// Pull from the original registry when all mirrors failed
+        #[arg(long)]
+        fallback_upstream: bool,
+        /// Save this platform of multi-arch images, `os/arch[/variant]` like `linux/arm64/v8`
+        #[arg(long)]
+        platform: Option<Platform>,
+    },
+    /// Compare the digests of the mirrors with upstream and report stale mirrors
+    Freshness {
+        /// Images, or files with one image per line
+        #[arg(required = true)]
+        input: Vec<String>,
+    },
+    /// Copy upstream images into the mirrors they are mapped to by the rulesets
+    Sync {
+        /// Upstream images, the tag may be a pattern like `mcr.microsoft.com/dotnet/sdk:8.0*`
+        image: Vec<String>,
+        /// A file with one upstream image per line
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+        /// Only sync into the mirrors of this ruleset
+        #[arg(long)]
+        ruleset: Option<String>,
+        /// Only report what would be copied
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Point the mirror configuration of the container runtime at `rg docker serve`
+    Configure {
+        /// The container runtime to configure
+        #[arg(long)]
+        runtime: ContainerRuntime,
+        /// The address of `rg docker serve`
+        #[arg(long, default_value = "http://127.0.0.1:5000")]
+        proxy: String,
+        /// Prefix of the configuration paths
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+        /// Only show the diff, nothing is written
+        #[arg(long)]
+        dry_run: bool,
+        /// Restore the configuration from the backups
+        #[arg(long)]
+        revert: bool,
+    },
+}
+
+#[derive(Subcommand)]
+#[derive(Debug)]
+enum DockerfileCommands {
+    /// Print the Dockerfile with the base images replaced by their mirrors
+    Rewrite {
+        /// The Dockerfile
+        path: PathBuf,
+        /// Set a build arg used in `FROM`, `KEY=VALUE` or `KEY` to take the value from the environment
+        #[arg(long)]
+        build_arg: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+#[derive(Debug)]
+enum BundleCommands {
+    /// Pull the images and download the release files of the manifest into one archive
+    Create {
+        /// The manifest, a yaml file with the `images` and the release `files` to bundle
+        #[arg(short, long)]
+        file: PathBuf,
+        /// The bundle to write
+        #[arg(short, long, default_value = DEFAULT_BUNDLE_FILE)]
+        output: PathBuf,
+        /// Pull from the original registry when all mirrors failed
+        #[arg(long)]
+        fallback_upstream: bool,
+        /// Bundle this platform of multi-arch images, `os/arch[/variant]` like `linux/arm64/v8`
+        #[arg(long)]
+        platform: Option<Platform>,
+    },
+    /// Verify the bundle, load its images into the container engine and place its release files
+    Import {
+        /// The bundle written by `rg bundle create`
+        bundle: PathBuf,
+        /// The directory to place the release files in
+        #[arg(long, default_value = ".")]
+        dest: PathBuf,
+        /// The container engine to load the images with, detected from PATH if not set in cli or config
+        #[arg(long)]
+        engine: Option<ContainerEngineKind>,
     },
 }
 
@@ -87,6 +334,37 @@ enum GithubCommands {
     }
 }
 
+#[derive(Subcommand)]
+#[derive(Debug)]
+enum K8sCommands {
+    /// Replace the images of the workloads in kubernetes manifests with their mirrors
+    Rewrite {
+        /// The manifest file, or a directory whose yaml files are rewritten
+        path: PathBuf,
+        /// Write the rewritten manifests back into the files instead of stdout
+        #[arg(long)]
+        in_place: bool,
+    },
+    /// Serve a mutating admission webhook which replaces the images of new pods with their mirrors
+    Webhook {
+        /// The address to listen on
+        #[arg(long, default_value = "0.0.0.0:8443")]
+        listen: SocketAddr,
+        /// The PEM certificate chain to serve HTTPS with, plain HTTP without it
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// The PKCS#8 PEM private key of the certificate
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// Only patch the pods of this namespace, may be repeated, every namespace if not set
+        #[arg(long)]
+        namespace: Vec<String>,
+        /// Never patch the pods of this namespace, may be repeated
+        #[arg(long)]
+        exclude_namespace: Vec<String>,
+    },
+}
+
 
 #[tokio::main]
 async fn main() {
@@ -106,15 +384,8 @@ async fn run() -> Result<(), DockermirError> {
 
     let cli: Cli = Cli::parse();
 
-    if let Some(level) = cli.verbose {
-        env_logger::builder()
-            .filter_level(level)
-            .init();
-    } else {
-        env_logger::builder()
-            .filter_level(LevelFilter::Info)
-            .init();
-    }
+    // log lines are written above the progress bars of pulls instead of through them
+    progress::init_logger(cli.verbose.unwrap_or(LevelFilter::Info));
     let loader = ConfigLoader::default();
     let config = loader.load_config(LoadConfigOptions {
         remote_config_url: cli.remote_config_url.to_owned(),
@@ -123,15 +394,166 @@ async fn run() -> Result<(), DockermirError> {
 
     trace!("cli: {:?}", &cli);
     let result = match &cli.command {
-        Commands::Docker { command } => {
+        Commands::Docker { engine, command } => {
+            match command {
+                DockerCommands::Pull { image, compose, profile, oci_layout, fallback_upstream, concurrency, platform, require_fresh, force, keep_mirror_tag, tag, locked, lock_file } => {
+                    let options = DockerPullOptions {
+                        oci_layout: oci_layout.to_owned(),
+                        engine: engine.to_owned(),
+                        fallback_to_upstream: *fallback_upstream,
+                        concurrency: concurrency.to_owned(),
+                        platform: platform.to_owned(),
+                        require_fresh: *require_fresh,
+                        force: *force,
+                        keep_mirror_tag: *keep_mirror_tag,
+                        tags: tag.to_owned(),
+                        locked: locked.then(|| lock_file.to_owned()),
+                    };
+                    match (image.as_slice(), compose) {
+                        ([image], _) => {
+                            DockerPullTask::new(config, image.to_owned(), options)
+                                .run()
+                                .await
+                        }
+                        ([], Some(compose)) => match load_compose_images(compose, profile) {
+                            Ok(images) => {
+                                DockerBatchPullTask::new(config, images, options)
+                                    .run()
+                                    .await
+                            }
+                            Err(e) => Err(e),
+                        },
+                        (images, _) => {
+                            DockerBatchPullTask::new(config, images.to_vec(), options)
+                                .run()
+                                .await
+                        }
+                    }
+                }
+                DockerCommands::Lock { image, compose, profile, output } => {
+                    let images = match compose {
+                        Some(compose) => load_compose_images(compose, profile),
+                        None => Ok(image.to_owned()),
+                    };
+                    match images {
+                        Ok(images) => {
+                            DockerLockTask::new(config, images, DockerLockOptions { output: output.to_owned() })
+                                .run()
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                DockerCommands::Relay { image, to, fallback_upstream, platform } => {
+                    DockerRelayTask::new(config, image.to_owned(), DockerRelayOptions {
+                        to: to.to_owned(),
+                        engine: engine.to_owned(),
+                        fallback_to_upstream: *fallback_upstream,
+                        platform: platform.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Check { image, offline, platform } => {
+                    DockerCheckTask::new(config, image.to_owned(), DockerCheckOptions {
+                        offline: *offline,
+                        platform: platform.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Build { context, file, build_arg, tag, args } => {
+                    DockerBuildTask::new(config, DockerBuildOptions {
+                        context: context.to_owned(),
+                        file: file.to_owned(),
+                        build_args: build_arg.to_owned(),
+                        tags: tag.to_owned(),
+                        args: args.to_owned(),
+                        engine: engine.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Serve { listen, fallback_upstream } => {
+                    DockerServeTask::new(config, DockerServeOptions {
+                        listen: listen.to_owned(),
+                        fallback_to_upstream: *fallback_upstream,
+                    })
+                        .run()
+                        .await
+                }
+                #[cfg(unix)]
+                DockerCommands::ProxySocket { listen, upstream } => {
+                    DockerProxySocketTask::new(config, DockerProxySocketOptions {
+                        listen: listen.to_owned(),
+                        upstream: upstream.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Save { image, output, format, fallback_upstream, platform } => {
+                    DockerSaveTask::new(config, image.to_owned(), DockerSaveOptions {
+                        output: output.to_owned(),
+                        format: format.to_owned(),
+                        fallback_to_upstream: *fallback_upstream,
+                        platform: platform.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Freshness { input } => {
+                    DockerFreshnessTask::new(config, input.to_owned())
+                        .run()
+                        .await
+                }
+                DockerCommands::Sync { image, file, ruleset, dry_run } => {
+                    DockerSyncTask::new(config, DockerSyncOptions {
+                        images: image.to_owned(),
+                        file: file.to_owned(),
+                        ruleset: ruleset.to_owned(),
+                        dry_run: *dry_run,
+                    })
+                        .run()
+                        .await
+                }
+                DockerCommands::Configure { runtime, proxy, root, dry_run, revert } => {
+                    DockerConfigureTask::new(config, DockerConfigureOptions {
+                        runtime: runtime.to_owned(),
+                        proxy: proxy.to_owned(),
+                        root: root.to_owned(),
+                        dry_run: *dry_run,
+                        revert: *revert,
+                    })
+                        .run()
+                        .await
+                }
+            }
+        }
+        Commands::Dockerfile { command } => {
+            match command {
+                DockerfileCommands::Rewrite { path, build_arg } => {
+                    DockerfileRewriteTask::new(config, path.to_owned(), build_arg.to_owned())
+                        .run()
+                        .await
+                }
+            }
+        }
+        Commands::Bundle { command } => {
             match command {
-                DockerCommands::Pull { image } => {
-                    DockerPullTask::new(config, image.to_owned())
+                BundleCommands::Create { file, output, fallback_upstream, platform } => {
+                    BundleCreateTask::new(config, file.to_owned(), BundleCreateOptions {
+                        output: output.to_owned(),
+                        fallback_to_upstream: *fallback_upstream,
+                        platform: platform.to_owned(),
+                    })
                         .run()
                         .await
                 }
-                DockerCommands::Check { image } => {
-                    DockerCheckTask::new(config, image.to_owned())
+                BundleCommands::Import { bundle, dest, engine } => {
+                    BundleImportTask::new(config, bundle.to_owned(), BundleImportOptions {
+                        dest: dest.to_owned(),
+                        engine: engine.to_owned(),
+                    })
                         .run()
                         .await
                 }
@@ -146,6 +568,26 @@ async fn run() -> Result<(), DockermirError> {
                 }
             }
         }
+        Commands::K8s { command } => {
+            match command {
+                K8sCommands::Rewrite { path, in_place } => {
+                    K8sRewriteTask::new(config, path.to_owned(), K8sRewriteOptions { in_place: *in_place })
+                        .run()
+                        .await
+                }
+                K8sCommands::Webhook { listen, tls_cert, tls_key, namespace, exclude_namespace } => {
+                    K8sWebhookTask::new(config, K8sWebhookOptions {
+                        listen: listen.to_owned(),
+                        tls_cert: tls_cert.to_owned(),
+                        tls_key: tls_key.to_owned(),
+                        namespaces: namespace.to_owned(),
+                        exclude_namespaces: exclude_namespace.to_owned(),
+                    })
+                        .run()
+                        .await
+                }
+            }
+        }
         Commands::SelfUpdate { .. } => {
             info!("Self update is not implemented yet, please visit https://github.com/newbe36524/Dockermir");
             Ok(())